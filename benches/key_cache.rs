@@ -0,0 +1,82 @@
+//! Cost of FHE operations with the keys and ciphertexts kept in memory, as the
+//! server does, against loading the keys from the key store and decoding the
+//! ciphertexts for every operation, as it did before keys were cached. Uses the
+//! key store configured by `FHE_KEY_STORE` and `FHE_KEY_DIR`, like the server.
+//!
+//! Usage: cargo bench --bench key_cache [iterations]
+
+use std::env;
+use std::error::Error;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use tfhe::set_server_key;
+
+use fhe_orderbook::utils::ciphertext::Ciphertext;
+use fhe_orderbook::utils::encrypted_uint::EncryptedUint;
+use fhe_orderbook::utils::fhe_operations;
+use fhe_orderbook::utils::generate_key;
+use fhe_orderbook::utils::key_store;
+
+type BenchResult<T> = Result<T, Box<dyn Error>>;
+
+// Average time of one run of `operation`
+fn time<T>(iterations: u32, mut operation: impl FnMut() -> BenchResult<T>) -> BenchResult<Duration> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(operation()?);
+    }
+    Ok(start.elapsed() / iterations)
+}
+
+fn report(name: &str, uncached: Duration, cached: Duration) {
+    println!(
+        "{:<8} uncached {:>10.1} ms   cached {:>10.1} ms   {:>6.1}x",
+        name,
+        uncached.as_secs_f64() * 1e3,
+        cached.as_secs_f64() * 1e3,
+        uncached.as_secs_f64() / cached.as_secs_f64()
+    );
+}
+
+fn main() -> BenchResult<()> {
+    // `cargo bench` passes `--bench` to every target; only a number is read
+    let iterations = env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(5);
+
+    generate_key::set_key_store(key_store::from_env()?)?;
+    fhe_operations::init_fhe()?;
+    let key_id = fhe_operations::ensure_server_key()?;
+    let client_key = fhe_operations::client_key()?;
+    let width = fhe_operations::key_parameters(key_id)?.integer_bits;
+
+    let price1 = fhe_operations::encrypt_u32(105, &client_key)?;
+    let price2 = fhe_operations::encrypt_u32(100, &client_key)?;
+    let encoded1 = bincode::serialize(&price1)?;
+    let encoded2 = bincode::serialize(&price2)?;
+
+    println!("Key version {}, {} iterations per operation", key_id, iterations);
+
+    // Encrypt an order value, loading the client key first and encoding the result
+    let uncached = time(iterations, || {
+        let client_key = generate_key::load_client_key(key_id)?;
+        let value = EncryptedUint::encrypt(width, 100, &client_key)?;
+        Ok(bincode::serialize(&Ciphertext::new(key_id, value))?)
+    })?;
+    let cached = time(iterations, || Ok(fhe_operations::encrypt_u32(100, &client_key)?))?;
+    report("encrypt", uncached, cached);
+
+    // Compare two prices homomorphically and decrypt the result bit, loading
+    // both keys and decoding both ciphertexts first
+    let uncached = time(iterations, || {
+        set_server_key(generate_key::load_server_key(key_id)?);
+        let client_key = generate_key::load_client_key(key_id)?;
+        let price1: Ciphertext = bincode::deserialize(&encoded1)?;
+        let price2: Ciphertext = bincode::deserialize(&encoded2)?;
+        Ok(price1.ge(&*price2)?.decrypt(&client_key))
+    })?;
+    // The uncached runs replaced this thread's server key, so install the cached one again
+    set_server_key((*fhe_operations::get_server_key()?).clone());
+    let cached = time(iterations, || Ok(fhe_operations::compare_prices(&price1, &price2)?))?;
+    report("compare", uncached, cached);
+
+    Ok(())
+}
@@ -4,10 +4,5 @@
 
 echo "Generating FHE keys for encrypted orderbook..."
 
-# Create the keys directory if it doesn't exist
-mkdir -p keys
-
-# Send a request to the API to generate keys
-curl -X POST http://localhost:3000/generate-keys
-
-echo "Keys generation request sent. Check the server logs for details."
+# Generate the first keys offline; options such as --profile and --integer-bits are passed through
+cargo run --release --bin fhe-orderbook-admin -- keys generate "$@"
@@ -72,7 +72,7 @@ async function toggleEncryption() {
     await new Promise(resolve => setTimeout(resolve, 1000));
     const result = await apiRequest('/config', 'POST', { 
       use_encryption: !state.encryptionEnabled 
-    });
+    }, true);
     
     if (result.success) {
       state.encryptionEnabled = !state.encryptionEnabled;
@@ -111,6 +111,8 @@ document.addEventListener('DOMContentLoaded', initDemo);/**
 
 // API endpoint for the orderbook service
 const API_URL = 'http://localhost:8080';
+// Session storage key of the admin token for the encryption toggle and key generation
+const ADMIN_TOKEN_KEY = 'orderbook_admin_token';
 
 // DOM Elements
 const elements = {
@@ -154,11 +156,27 @@ let state = {
   fills: []
 };
 
+/**
+ * Admin Token
+ * Asks for the admin token the first time an admin endpoint is used and keeps it
+ * for the browser session only, so it never appears in the URL or history
+ */
+function adminToken() {
+  let token = sessionStorage.getItem(ADMIN_TOKEN_KEY);
+  if (!token) {
+    token = window.prompt('Admin token for this orderbook server:');
+    if (token) {
+      sessionStorage.setItem(ADMIN_TOKEN_KEY, token);
+    }
+  }
+  return token;
+}
+
 /**
  * API Request Handler
- * Makes requests to the orderbook API
+ * Makes requests to the orderbook API; only admin requests carry the admin token
  */
-async function apiRequest(endpoint, method = 'GET', data = null) {
+async function apiRequest(endpoint, method = 'GET', data = null, admin = false) {
   try {
     const options = {
       method,
@@ -167,6 +185,13 @@ async function apiRequest(endpoint, method = 'GET', data = null) {
       }
     };
     
+    if (admin) {
+      const token = adminToken();
+      if (token) {
+        options.headers['Authorization'] = `Bearer ${token}`;
+      }
+    }
+    
     if (data) {
       options.body = JSON.stringify(data);
     }
@@ -174,6 +199,10 @@ async function apiRequest(endpoint, method = 'GET', data = null) {
     const response = await fetch(`${API_URL}${endpoint}`, options);
     
     if (!response.ok) {
+      // Ask again next time if the token was refused
+      if (admin && response.status === 401) {
+        sessionStorage.removeItem(ADMIN_TOKEN_KEY);
+      }
       throw new Error(`API error: ${response.status}`);
     }
     
@@ -518,7 +547,7 @@ async function generateKeys() {
     
     // Send request to generate keys (with slight delay to show animation)
     await new Promise(resolve => setTimeout(resolve, 1500));
-    const result = await apiRequest('/generate-keys', 'POST');
+    const result = await apiRequest('/generate-keys', 'POST', null, true);
     
     if (result.success) {
       state.keysGenerated = true;
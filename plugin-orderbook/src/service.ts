@@ -7,6 +7,7 @@ import axios from 'axios';
 export class OrderbookService extends Service {
   static serviceType = 'orderbook';
   private baseUrl: string;
+  private adminToken?: string;
   
   capabilityDescription = 'This service integrates with the encrypted orderbook API';
   
@@ -14,6 +15,7 @@ export class OrderbookService extends Service {
     super(runtime);
     // Get config from environment variables
     this.baseUrl = process.env.ORDERBOOK_API_URL || 'http://localhost:8080';
+    this.adminToken = process.env.ORDERBOOK_ADMIN_TOKEN || undefined;
     logger.info(`OrderbookService initialized with API URL: ${this.baseUrl}`);
   }
 
@@ -37,6 +39,13 @@ export class OrderbookService extends Service {
     logger.info('OrderbookService stopped');
   }
 
+  /**
+   * Request options for the admin endpoints, which need the admin token
+   */
+  private adminOptions() {
+    return this.adminToken ? { headers: { Authorization: `Bearer ${this.adminToken}` } } : {};
+  }
+
   /**
    * Get all orders from the orderbook
    */
@@ -113,7 +122,7 @@ export class OrderbookService extends Service {
   async generateKeys() {
     try {
       logger.info('Generating FHE keys');
-      const response = await axios.post(`${this.baseUrl}/generate-keys`);
+      const response = await axios.post(`${this.baseUrl}/generate-keys`, undefined, this.adminOptions());
       return response.data;
     } catch (error) {
       logger.error('Error generating keys:', error);
@@ -141,7 +150,7 @@ export class OrderbookService extends Service {
   async updateConfig(config: any) {
     try {
       logger.info('Updating orderbook configuration');
-      const response = await axios.post(`${this.baseUrl}/config`, config);
+      const response = await axios.post(`${this.baseUrl}/config`, config, this.adminOptions());
       return response.data;
     } catch (error) {
       logger.error('Error updating configuration:', error);
@@ -155,7 +164,7 @@ export class OrderbookService extends Service {
   async resetOrderbook() {
     try {
       logger.info('Resetting orderbook');
-      const response = await axios.post(`${this.baseUrl}/reset`);
+      const response = await axios.post(`${this.baseUrl}/reset`, undefined, this.adminOptions());
       return response.data;
     } catch (error) {
       logger.error('Error resetting orderbook:', error);
@@ -0,0 +1,245 @@
+//! Command-line client for trading against the orderbook API. Prices and
+//! quantities can be encrypted locally with the server's public key, so they
+//! never leave this machine in plaintext.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Serialize;
+use fhe_orderbook::cli::Args;
+use fhe_orderbook_sdk::types::{Fill, MarketOrderRequest, Order, OrderRequest};
+use fhe_orderbook_sdk::{EncryptionKey, OrderbookClient};
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+// How often `fills --follow` polls for new fills
+const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+const DEFAULT_DEPTH_LEVELS: usize = 10;
+
+const USAGE: &str = "Usage: fhe-orderbook-client [--url URL] <command>
+
+Orders:
+  place buy|sell PRICE QUANTITY --user U [--display N] [--expires-at MS]
+        [--encrypt] [--public-key FILE]         Place a limit order; --encrypt encrypts the
+                                                price and quantity locally before sending
+  market buy|sell QUANTITY --user U             Place a market order
+  cancel ID                                     Cancel a resting order
+  orders                                        List resting orders
+  order ID                                      Show one order
+
+Market:
+  depth [--levels N]                            Show aggregated depth per price level
+  fills [--follow] [--interval MS]              List fills, or keep printing new ones
+
+Keys:
+  public-key --out FILE                         Download the active public key for --encrypt
+
+URL defaults to $ORDERBOOK_API_URL, or http://localhost:8080.";
+
+#[tokio::main]
+async fn main() {
+    let args = match Args::parse(env::args().skip(1), &["encrypt", "follow"]) {
+        Ok(args) => args,
+        Err(e) => fail(&e),
+    };
+    let client = match args.option("url") {
+        Some(url) => OrderbookClient::new(url),
+        None => OrderbookClient::from_env(),
+    };
+    if let Err(e) = run(&client, &args).await {
+        fail(&e.to_string());
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    process::exit(1);
+}
+
+async fn run(client: &OrderbookClient, args: &Args) -> CliResult {
+    match args.command().as_slice() {
+        ["place", side, price, quantity] => place(client, args, side, price, quantity).await,
+        ["market", side, quantity] => market(client, args, side, quantity).await,
+        ["cancel", id] => {
+            args.allow(&["url"])?;
+            print_json(&client.cancel_order(parse("order id", id)?).await?)
+        }
+        ["orders"] => list_orders(client, args).await,
+        ["order", id] => {
+            args.allow(&["url"])?;
+            print_order(&client.order(parse("order id", id)?).await?);
+            Ok(())
+        }
+        ["depth"] => depth(client, args).await,
+        ["fills"] => fills(client, args).await,
+        ["public-key"] => {
+            args.allow(&["url", "out"])?;
+            let out = args.option("out").ok_or("public-key needs --out FILE")?;
+            let active = client.keys().await?.active_key_id;
+            fs::write(out, client.public_key_bytes(active).await?)?;
+            println!("Saved the public key of version {} to {}", active, out);
+            Ok(())
+        }
+        [] | ["help"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            Err("Unknown command".into())
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid {} '{}'", name, value))
+}
+
+fn parse_side(side: &str) -> Result<String, String> {
+    match side {
+        "buy" | "sell" => Ok(side.to_string()),
+        _ => Err(format!("Invalid side '{}': must be buy or sell", side)),
+    }
+}
+
+fn print_json(value: &impl Serialize) -> CliResult {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+// Load the public key from a file saved by `public-key`, or download the active one,
+// and expand it for encryption
+async fn encryption_key(client: &OrderbookClient, file: Option<&str>) -> Result<EncryptionKey, Box<dyn Error>> {
+    eprintln!("Expanding public key...");
+    Ok(match file {
+        Some(path) => {
+            let public_key = bincode::deserialize(&fs::read(path)?)?;
+            tokio::task::spawn_blocking(move || EncryptionKey::from_public_key(public_key)).await?
+        }
+        None => client.encryption_key().await?,
+    })
+}
+
+async fn place(client: &OrderbookClient, args: &Args, side: &str, price: &str, quantity: &str) -> CliResult {
+    args.allow(&["url", "user", "display", "expires-at", "encrypt", "public-key"])?;
+    let mut request = OrderRequest {
+        price: Some(parse("price", price)?),
+        quantity: Some(parse("quantity", quantity)?),
+        encrypted_price: None,
+        encrypted_quantity: None,
+        side: parse_side(side)?,
+        user_pubkey: args.option("user").ok_or("place needs --user")?.to_string(),
+        expires_at: args.option("expires-at").map(|at| parse("expiry", at)).transpose()?,
+        display_quantity: args.option("display").map(|display| parse("display quantity", display)).transpose()?,
+    };
+
+    if args.flag("encrypt") || args.option("public-key").is_some() {
+        let key = Arc::new(encryption_key(client, args.option("public-key")).await?);
+        eprintln!("Encrypting price and quantity with key version {}...", key.key_id());
+        request = tokio::task::spawn_blocking(move || key.encrypt_order(request)).await??;
+    }
+
+    print_json(&client.place_order(&request).await?)
+}
+
+async fn market(client: &OrderbookClient, args: &Args, side: &str, quantity: &str) -> CliResult {
+    args.allow(&["url", "user"])?;
+    let request = MarketOrderRequest {
+        quantity: parse("quantity", quantity)?,
+        user_pubkey: args.option("user").ok_or("market needs --user")?.to_string(),
+    };
+    let response = match parse_side(side)?.as_str() {
+        "buy" => client.market_buy(&request).await?,
+        _ => client.market_sell(&request).await?,
+    };
+    print_json(&response)
+}
+
+fn print_order(order: &Order) {
+    println!(
+        "#{} {:?} {} @ {} ({} filled, {:?}, {:?}, {})",
+        order.id,
+        order.side,
+        order.quantity,
+        order.price,
+        order.filled_quantity,
+        order.order_type,
+        order.status,
+        order.user_pubkey
+    );
+}
+
+async fn list_orders(client: &OrderbookClient, args: &Args) -> CliResult {
+    args.allow(&["url"])?;
+    let (buy_orders, sell_orders) = client.orders().await?;
+    println!("Buy orders ({}):", buy_orders.len());
+    buy_orders.iter().for_each(print_order);
+    println!("Sell orders ({}):", sell_orders.len());
+    sell_orders.iter().for_each(print_order);
+    Ok(())
+}
+
+// Remaining quantity per price level
+fn levels(orders: &[Order]) -> BTreeMap<u32, u64> {
+    let mut levels = BTreeMap::new();
+    for order in orders {
+        *levels.entry(order.price).or_insert(0) += u64::from(order.quantity.saturating_sub(order.filled_quantity));
+    }
+    levels
+}
+
+async fn depth(client: &OrderbookClient, args: &Args) -> CliResult {
+    args.allow(&["url", "levels"])?;
+    let count = args.option("levels").map(|levels| parse("levels", levels)).transpose()?.unwrap_or(DEFAULT_DEPTH_LEVELS);
+    let (buy_orders, sell_orders) = client.orders().await?;
+
+    println!("{:>12}  {:>10}", "ASK", "QUANTITY");
+    let asks: Vec<(u32, u64)> = levels(&sell_orders).into_iter().take(count).collect();
+    for (price, quantity) in asks.iter().rev() {
+        println!("{:>12}  {:>10}", price, quantity);
+    }
+    println!("{:>12}  {:>10}", "BID", "QUANTITY");
+    for (price, quantity) in levels(&buy_orders).into_iter().rev().take(count) {
+        println!("{:>12}  {:>10}", price, quantity);
+    }
+    Ok(())
+}
+
+fn print_fill(fill: &Fill) {
+    println!(
+        "trade {}: {} @ {} buyer {} (#{}) seller {} (#{}), {:?} aggressor",
+        fill.trade_id,
+        fill.quantity,
+        fill.price,
+        fill.buyer_pubkey,
+        fill.buy_order_id,
+        fill.seller_pubkey,
+        fill.sell_order_id,
+        fill.aggressor_side
+    );
+}
+
+async fn fills(client: &OrderbookClient, args: &Args) -> CliResult {
+    args.allow(&["url", "follow", "interval"])?;
+    let interval = args
+        .option("interval")
+        .map(|interval| parse("interval", interval))
+        .transpose()?
+        .unwrap_or(DEFAULT_POLL_INTERVAL_MS);
+
+    let mut last_trade_id = 0;
+    loop {
+        for fill in client.fills(Some(last_trade_id)).await? {
+            print_fill(&fill);
+            last_trade_id = last_trade_id.max(fill.trade_id);
+        }
+        if !args.flag("follow") {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(interval)).await;
+    }
+}
@@ -0,0 +1,304 @@
+use std::env;
+use std::path::Path;
+use hyper::body;
+use hyper::{header, Body, Client, Method, Request};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use fhe_orderbook::utils::fhe_operations::VersionedPublicKey;
+
+use crate::encryption::EncryptionKey;
+use crate::error::{ClientError, ClientResult};
+use crate::tls::HttpsConnector;
+use crate::types::*;
+
+// Base URL of the API, shared with the ElizaOS plugin
+const API_URL_ENV: &str = "ORDERBOOK_API_URL";
+const DEFAULT_API_URL: &str = "http://localhost:8080";
+// Token for the admin endpoints, as given to the server
+const ADMIN_TOKEN_ENV: &str = "ORDERBOOK_ADMIN_TOKEN";
+
+#[derive(Serialize)]
+struct FillsQuery {
+    after: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct FeesQuery<'a> {
+    user_pubkey: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct CandlesQuery {
+    interval: Interval,
+    limit: Option<usize>,
+}
+
+// Whether a request goes to an admin endpoint, the only ones the admin token is sent to
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Public,
+    Admin,
+}
+
+/// Async client for the orderbook API, over HTTP or HTTPS. Cloning it is cheap
+/// and clones share their connections.
+#[derive(Clone)]
+pub struct OrderbookClient {
+    base_url: String,
+    // Sent as a bearer token with requests to admin endpoints
+    admin_token: Option<String>,
+    http: Client<HttpsConnector>,
+}
+
+impl OrderbookClient {
+    /// Client for `base_url`. HTTPS servers are verified against the CA
+    /// certificates named by `ORDERBOOK_CA_CERT`, or the system's CA bundle.
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            admin_token: None,
+            http: Client::builder().build(HttpsConnector::new()),
+        }
+    }
+
+    /// Client for the URL in `ORDERBOOK_API_URL`, or `http://localhost:8080`,
+    /// with the admin token in `ORDERBOOK_ADMIN_TOKEN` if it is set
+    pub fn from_env() -> Self {
+        let client = Self::new(&env::var(API_URL_ENV).unwrap_or_else(|_| DEFAULT_API_URL.to_string()));
+        match env::var(ADMIN_TOKEN_ENV) {
+            Ok(token) if !token.is_empty() => client.with_admin_token(&token),
+            _ => client,
+        }
+    }
+
+    /// Authenticate as an operator, which the administration methods require
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.to_string());
+        self
+    }
+
+    /// Verify HTTPS servers against the CA certificates in a PEM file only,
+    /// e.g. the certificate of a server with a self-signed one
+    pub fn with_ca_certificates(mut self, ca_file: impl AsRef<Path>) -> ClientResult<Self> {
+        let connector = HttpsConnector::new().with_ca_certificates(ca_file.as_ref()).map_err(ClientError::Tls)?;
+        self.http = Client::builder().build(connector);
+        Ok(self)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    // Send a request and return the body of a successful response. Error
+    // responses are turned into their code and message.
+    async fn send(&self, method: Method, path: &str, body: Option<Vec<u8>>, access: Access) -> ClientResult<Vec<u8>> {
+        let mut request = Request::builder().method(method).uri(format!("{}{}", self.base_url, path));
+        if body.is_some() {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+        }
+        if access == Access::Admin
+            && let Some(token) = &self.admin_token
+        {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = request
+            .body(body.map_or_else(Body::empty, Body::from))
+            .map_err(|e| ClientError::InvalidUrl(e.to_string()))?;
+        let response = self.http.request(request).await?;
+        let status = response.status().as_u16();
+        let bytes = body::to_bytes(response.into_body()).await?.to_vec();
+        if !(200..300).contains(&status) {
+            return Err(match serde_json::from_slice::<ErrorResponse>(&bytes) {
+                Ok(error) => ClientError::Api {
+                    status,
+                    code: error.code,
+                    message: error.error,
+                    retry_after_ms: error.retry_after_ms,
+                },
+                Err(_) => ClientError::UnexpectedResponse { status, body: String::from_utf8_lossy(&bytes).into_owned() },
+            });
+        }
+        Ok(bytes)
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+        access: Access,
+    ) -> ClientResult<T> {
+        let bytes = self.send(method, path, body, access).await?;
+        serde_json::from_slice(&bytes).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> ClientResult<T> {
+        self.request(Method::GET, path, None, Access::Public).await
+    }
+
+    async fn post<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> ClientResult<T> {
+        let body = serde_json::to_vec(body).map_err(|e| ClientError::Decode(e.to_string()))?;
+        self.request(Method::POST, path, Some(body), Access::Public).await
+    }
+
+    // Requests to admin endpoints, which carry the admin token
+
+    async fn admin_get<T: DeserializeOwned>(&self, path: &str) -> ClientResult<T> {
+        self.request(Method::GET, path, None, Access::Admin).await
+    }
+
+    async fn admin_post<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> ClientResult<T> {
+        let body = serde_json::to_vec(body).map_err(|e| ClientError::Decode(e.to_string()))?;
+        self.request(Method::POST, path, Some(body), Access::Admin).await
+    }
+
+    // POST without a body, for actions that take no parameters
+    async fn admin_post_empty<T: DeserializeOwned>(&self, path: &str) -> ClientResult<T> {
+        self.request(Method::POST, path, None, Access::Admin).await
+    }
+
+    fn with_query<Q: Serialize>(path: &str, query: &Q) -> ClientResult<String> {
+        let query = serde_urlencoded::to_string(query).map_err(|e| ClientError::InvalidUrl(e.to_string()))?;
+        Ok(if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query) })
+    }
+
+    // Orders
+
+    /// Resting buy and sell orders
+    pub async fn orders(&self) -> ClientResult<(Vec<Order>, Vec<Order>)> {
+        self.get("/orders").await
+    }
+
+    /// A single order, including orders that have left the book
+    pub async fn order(&self, id: u128) -> ClientResult<Order> {
+        self.get(&format!("/orders/{}", id)).await
+    }
+
+    /// Place a limit order, with plaintext or encrypted values
+    pub async fn place_order(&self, request: &OrderRequest) -> ClientResult<OrderResponse> {
+        self.post("/orders", request).await
+    }
+
+    pub async fn cancel_order(&self, id: u128) -> ClientResult<CancelResponse> {
+        self.request(Method::DELETE, &format!("/orders/{}", id), None, Access::Public).await
+    }
+
+    /// Stop orders waiting in the trigger book
+    pub async fn stop_orders(&self) -> ClientResult<Vec<Order>> {
+        self.get("/stop-orders").await
+    }
+
+    pub async fn place_stop_order(&self, request: &StopOrderRequest) -> ClientResult<StopOrderResponse> {
+        self.post("/stop-orders", request).await
+    }
+
+    pub async fn place_pegged_order(&self, request: &PeggedOrderRequest) -> ClientResult<PeggedOrderResponse> {
+        self.post("/pegged-orders", request).await
+    }
+
+    pub async fn market_buy(&self, request: &MarketOrderRequest) -> ClientResult<OrderResponse> {
+        self.post("/market-buy", request).await
+    }
+
+    pub async fn market_sell(&self, request: &MarketOrderRequest) -> ClientResult<OrderResponse> {
+        self.post("/market-sell", request).await
+    }
+
+    /// Fills, or only those with a trade id after `after`, to poll for new ones
+    pub async fn fills(&self, after: Option<u64>) -> ClientResult<Vec<Fill>> {
+        self.get(&Self::with_query("/fills", &FillsQuery { after })?).await
+    }
+
+    /// Encrypted fills produced by oblivious matching, optionally only those swept after a timestamp (ms)
+    pub async fn encrypted_fills(&self, after: Option<u64>) -> ClientResult<Vec<EncryptedFill>> {
+        self.get(&Self::with_query("/fills/encrypted", &FillsQuery { after })?).await
+    }
+
+    // Fees and market data
+
+    /// Accrued fees per market and per user, optionally for a single user
+    pub async fn fees(&self, user_pubkey: Option<&str>) -> ClientResult<FeesResponse> {
+        self.get(&Self::with_query("/fees", &FeesQuery { user_pubkey })?).await
+    }
+
+    pub async fn fee_schedule(&self) -> ClientResult<FeeSchedule> {
+        self.get("/fees/schedule").await
+    }
+
+    pub async fn update_fee_schedule(&self, schedule: &FeeSchedule) -> ClientResult<UpdateResponse> {
+        self.admin_post("/fees/schedule", schedule).await
+    }
+
+    pub async fn candles(&self, interval: Interval, limit: Option<usize>) -> ClientResult<Vec<Candle>> {
+        self.get(&Self::with_query("/market-data/candles", &CandlesQuery { interval, limit })?).await
+    }
+
+    pub async fn stats(&self) -> ClientResult<TradeStats> {
+        self.get("/market-data/stats").await
+    }
+
+    /// Publish pending encrypted fills as one aggregated trade
+    pub async fn disclose_trades(&self) -> ClientResult<DiscloseResponse> {
+        self.admin_post_empty("/market-data/disclose").await
+    }
+
+    // Auctions
+
+    pub async fn run_auction(&self) -> ClientResult<AuctionResponse<AuctionResult>> {
+        self.admin_post_empty("/auction/run").await
+    }
+
+    pub async fn run_encrypted_clearing(&self, grid: &PriceGrid) -> ClientResult<AuctionResponse<EncryptedClearing>> {
+        self.admin_post("/auction/encrypted", grid).await
+    }
+
+    // Keys
+
+    pub async fn keys(&self) -> ClientResult<KeysResponse> {
+        self.get("/keys").await
+    }
+
+    /// Serialized public key of a key version, as saved for later use
+    pub async fn public_key_bytes(&self, id: KeyId) -> ClientResult<Vec<u8>> {
+        self.send(Method::GET, &format!("/keys/{}/public", id), None, Access::Public).await
+    }
+
+    /// Public key of a key version
+    pub async fn public_key(&self, id: KeyId) -> ClientResult<VersionedPublicKey> {
+        let bytes = self.public_key_bytes(id).await?;
+        bincode::deserialize(&bytes).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    /// Download and expand the public key of the active key version, ready to
+    /// encrypt orders. Expanding takes a while and runs off the async runtime.
+    pub async fn encryption_key(&self) -> ClientResult<EncryptionKey> {
+        let public_key = self.public_key(self.keys().await?.active_key_id).await?;
+        tokio::task::spawn_blocking(move || EncryptionKey::from_public_key(public_key))
+            .await
+            .map_err(|e| ClientError::Decode(format!("Key expansion task failed: {}", e)))
+    }
+
+    pub async fn generate_keys(&self, request: &GenerateKeysRequest) -> ClientResult<GenerateKeysResponse> {
+        self.admin_post("/generate-keys", request).await
+    }
+
+    // Administration. These, along with updating the fee schedule, running
+    // auctions, disclosing trades and generating keys, need an admin token.
+
+    pub async fn snapshot(&self) -> ClientResult<BookSnapshot> {
+        self.admin_get("/snapshot").await
+    }
+
+    pub async fn config(&self) -> ClientResult<ConfigResponse> {
+        self.get("/config").await
+    }
+
+    pub async fn update_config(&self, request: &ConfigRequest) -> ClientResult<UpdateResponse> {
+        self.admin_post("/config", request).await
+    }
+
+    pub async fn reset(&self) -> ClientResult<ResetResponse> {
+        self.admin_post_empty("/reset").await
+    }
+}
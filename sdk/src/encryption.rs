@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use fhe_orderbook::error::OrderbookError;
+use fhe_orderbook::utils::fhe_operations::{self, ExpandedPublicKey, VersionedClientKey, VersionedPublicKey};
+
+use crate::error::{ClientError, ClientResult};
+use crate::types::{Ciphertext, KeyId, OrderRequest};
+
+/// Key a client encrypts order values with. A public key downloaded from the
+/// server is all a trader needs; a client key only exists where the keys were
+/// generated, e.g. in tests and bots run by the operator.
+pub enum EncryptionKey {
+    Public(Box<ExpandedPublicKey>),
+    Client(Arc<VersionedClientKey>),
+}
+
+impl EncryptionKey {
+    /// Expand a downloaded public key. This takes a while, so do it once and
+    /// reuse the key for every order.
+    pub fn from_public_key(public_key: VersionedPublicKey) -> Self {
+        EncryptionKey::Public(Box::new(public_key.expand()))
+    }
+
+    /// Version of the keys values are encrypted under
+    pub fn key_id(&self) -> KeyId {
+        match self {
+            EncryptionKey::Public(key) => key.id,
+            EncryptionKey::Client(key) => key.id,
+        }
+    }
+
+    /// Encrypt a value at the integer width of the key version
+    pub fn encrypt(&self, value: u32) -> ClientResult<Ciphertext> {
+        let encrypted = match self {
+            EncryptionKey::Public(key) => fhe_operations::encrypt_u32_public(value, key),
+            EncryptionKey::Client(key) => fhe_operations::encrypt_u32(value, key),
+        };
+        encrypted.map_err(ClientError::Encryption)
+    }
+
+    /// Replace the plaintext price and quantity of a limit order with their
+    /// encryptions. Encrypting with a public key is slow, so call this off the
+    /// async runtime, e.g. with `tokio::task::spawn_blocking`.
+    pub fn encrypt_order(&self, mut request: OrderRequest) -> ClientResult<OrderRequest> {
+        let (Some(price), Some(quantity)) = (request.price.take(), request.quantity.take()) else {
+            return Err(ClientError::Encryption(OrderbookError::InvalidRequest(
+                "The order has no plaintext price and quantity to encrypt".to_string()
+            )));
+        };
+        request.encrypted_price = Some(self.encrypt(price)?);
+        request.encrypted_quantity = Some(self.encrypt(quantity)?);
+        Ok(request)
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::time::Duration;
+
+use fhe_orderbook::error::OrderbookError;
+
+use crate::types::ErrorCode;
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Errors returned by the client
+#[derive(Debug)]
+pub enum ClientError {
+    // The server refused the request; `code` is the stable code from its error body,
+    // and `retry_after_ms` how long to wait before retrying a throttled request
+    Api { status: u16, code: ErrorCode, message: String, retry_after_ms: Option<u64> },
+    // The server answered with something other than the API's JSON
+    UnexpectedResponse { status: u16, body: String },
+    // The request could not be sent or the response could not be read
+    Http(hyper::Error),
+    InvalidUrl(String),
+    // TLS could not be set up, e.g. an unreadable CA certificate file
+    Tls(String),
+    // A response body did not match the expected type
+    Decode(String),
+    // Encrypting order values failed, e.g. a value too wide for the keys
+    Encryption(OrderbookError),
+}
+
+impl ClientError {
+    /// Code of an error returned by the API, if the server sent one
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// How long the server asked to wait before retrying, if it throttled the request
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ClientError::Api { retry_after_ms: Some(ms), .. } => Some(Duration::from_millis(*ms)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Api { status, code, message, .. } => {
+                let code = serde_json::to_value(code).ok();
+                let code = code.as_ref().and_then(|code| code.as_str()).unwrap_or("unknown");
+                write!(f, "{} ({}, HTTP {})", message, code, status)
+            }
+            ClientError::UnexpectedResponse { status, body } => write!(f, "HTTP {}: {}", status, body),
+            ClientError::Http(e) => write!(f, "Request failed: {}", e),
+            ClientError::InvalidUrl(message) => write!(f, "Invalid URL: {}", message),
+            ClientError::Tls(message) => write!(f, "TLS setup failed: {}", message),
+            ClientError::Decode(message) => write!(f, "Invalid response: {}", message),
+            ClientError::Encryption(e) => write!(f, "Encryption failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<hyper::Error> for ClientError {
+    fn from(e: hyper::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<OrderbookError> for ClientError {
+    fn from(e: OrderbookError) -> Self {
+        ClientError::Encryption(e)
+    }
+}
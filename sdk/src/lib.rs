@@ -0,0 +1,29 @@
+//! Client library for the encrypted orderbook API: an async HTTP client,
+//! the request and response types the server uses, and helpers to encrypt
+//! order values before they leave the client.
+
+pub mod client;
+pub mod encryption;
+pub mod error;
+pub mod tls;
+
+pub use client::OrderbookClient;
+pub use encryption::EncryptionKey;
+pub use error::{ClientError, ClientResult};
+
+/// Request and response types, shared with the server
+pub mod types {
+    pub use fhe_orderbook::api::types::*;
+    pub use fhe_orderbook::error::ErrorCode;
+    pub use fhe_orderbook::utils::auction::{AuctionResult, MatchingMode};
+    pub use fhe_orderbook::utils::ciphertext::Ciphertext;
+    pub use fhe_orderbook::utils::encrypted_auction::{EncryptedClearing, PriceGrid};
+    pub use fhe_orderbook::utils::fees::{FeeSchedule, MarketFees, UserFees};
+    pub use fhe_orderbook::utils::fhe_params::{IntegerWidth, ParameterProfile};
+    pub use fhe_orderbook::utils::generate_key::KeyId;
+    pub use fhe_orderbook::utils::market_data::{Candle, DisclosurePolicy, Interval, MarketTrade, TradeStats};
+    pub use fhe_orderbook::utils::oblivious::EncryptedFill;
+    pub use fhe_orderbook::utils::orders::{Fill, Order, OrderStatus, OrderType, Side};
+    pub use fhe_orderbook::utils::pegged::{PegReference, TopOfBook};
+    pub use fhe_orderbook::utils::snapshot::BookSnapshot;
+}
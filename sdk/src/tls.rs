@@ -0,0 +1,172 @@
+//! HTTPS for the client. Servers are verified against the CA certificates in a
+//! PEM file: the one named by `ORDERBOOK_CA_CERT`, the one given to
+//! `OrderbookClient::with_ca_certificates`, or else the system's CA bundle.
+
+use std::env;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use hyper::client::connect::{Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::http::uri::Scheme;
+use hyper::service::Service;
+use hyper::Uri;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+// PEM file of CA certificates to verify HTTPS servers with, e.g. the CA of a self-signed server certificate
+pub const CA_CERT_ENV: &str = "ORDERBOOK_CA_CERT";
+
+// Where the common platforms keep their CA bundle
+const SYSTEM_CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// TLS setup for the CA certificates in a PEM file
+pub fn connector_for(ca_file: &Path) -> Result<TlsConnector, String> {
+    let certs = File::open(ca_file)
+        .and_then(|file| rustls_pemfile::certs(&mut BufReader::new(file)))
+        .map_err(|e| format!("Cannot read CA certificates from {}: {}", ca_file.display(), e))?;
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(&certs);
+    if added == 0 {
+        return Err(format!("{} holds no usable CA certificate", ca_file.display()));
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+// TLS setup from `ORDERBOOK_CA_CERT`, or from the first system CA bundle found
+fn default_connector() -> Result<TlsConnector, String> {
+    if let Ok(path) = env::var(CA_CERT_ENV) {
+        return connector_for(Path::new(&path));
+    }
+    SYSTEM_CA_BUNDLES
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.exists())
+        .ok_or_else(|| format!("No system CA bundle found; set {} to a PEM file of CA certificates", CA_CERT_ENV))
+        .and_then(|path| connector_for(&path))
+}
+
+/// Connects over TCP, and over TLS for `https` URLs
+#[derive(Clone)]
+pub struct HttpsConnector {
+    http: HttpConnector,
+    // Set up by the first HTTPS connection, unless certificates were given, and
+    // shared by the clones hyper connects with; an error fails every HTTPS connection
+    tls: Arc<OnceLock<Result<TlsConnector, String>>>,
+}
+
+impl HttpsConnector {
+    pub fn new() -> Self {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        Self { http, tls: Arc::new(OnceLock::new()) }
+    }
+
+    /// Verify servers with the CA certificates in `ca_file` only
+    pub fn with_ca_certificates(mut self, ca_file: &Path) -> Result<Self, String> {
+        self.tls = Arc::new(OnceLock::from(Ok(connector_for(ca_file)?)));
+        Ok(self)
+    }
+}
+
+impl Default for HttpsConnector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Service<Uri> for HttpsConnector {
+    type Response = MaybeTlsStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<MaybeTlsStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let tls = if uri.scheme() == Some(&Scheme::HTTPS) {
+            Some(self.tls.get_or_init(default_connector).clone())
+        } else {
+            None
+        };
+        let host = uri.host().unwrap_or_default().trim_matches(|c| c == '[' || c == ']').to_string();
+        let connecting = self.http.call(uri);
+
+        Box::pin(async move {
+            let tcp = connecting.await?;
+            let Some(tls) = tls else {
+                return Ok(MaybeTlsStream::Plain(tcp));
+            };
+            let server_name = ServerName::try_from(host.as_str())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid server name {}: {}", host, e)))?;
+            let stream = tls?.connect(server_name, tcp).await?;
+            Ok(MaybeTlsStream::Tls(Box::new(stream)))
+        })
+    }
+}
+
+/// A connection to the server, encrypted for `https` URLs
+pub enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Connection for MaybeTlsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            MaybeTlsStream::Plain(stream) => stream.connected(),
+            MaybeTlsStream::Tls(stream) => stream.get_ref().0.connected(),
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use std::time::Duration;
+
+use crate::api::extract::JsonBody;
+use crate::api::types::AuctionResponse;
+use crate::error::OrderbookError;
+use crate::utils::encrypted_auction::PriceGrid;
+use crate::AppState;
+
+// How often the auction loop checks the matching mode while auctions are off
+const AUCTION_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// How often exhausted orders are closed under oblivious matching
+pub const COMPACTION_INTERVAL: Duration = Duration::from_secs(10);
+
+// Clear the current batch immediately
+pub async fn run_auction(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let result = state.execute(|orderbook| orderbook.run_auction()).await??;
+
+    Ok((StatusCode::OK, Json(AuctionResponse {
+        success: true,
+        result,
+    })))
+}
+
+// Compute the clearing price and encrypted allocations of the resting encrypted orders
+pub async fn run_encrypted_clearing(
+    State(state): State<AppState>,
+    JsonBody(grid): JsonBody<PriceGrid>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    grid.validate()?;
+
+    let clearing = state.execute(move |orderbook| orderbook.clear_encrypted_batch(&grid)).await??;
+
+    Ok((StatusCode::OK, Json(AuctionResponse {
+        success: true,
+        result: clearing,
+    })))
+}
+
+/// Background task that clears a batch every auction interval while the
+/// orderbook is in batch auction mode.
+pub async fn auction_loop(state: AppState) {
+    loop {
+        let interval = state
+            .execute(|orderbook| orderbook.matching_mode.auction_interval())
+            .await
+            .unwrap_or_default();
+
+        match interval {
+            Some(interval) => {
+                tokio::time::sleep(interval).await;
+                let result = state.execute(|orderbook| {
+                    // The mode may have changed while we were waiting
+                    if orderbook.matching_mode.auction_interval().is_some() {
+                        orderbook.run_auction().map(|_| ())
+                    } else {
+                        Ok(())
+                    }
+                }).await;
+                if let Err(e) = result.and_then(|result| result) {
+                    eprintln!("Batch auction failed: {}", e);
+                }
+            }
+            None => tokio::time::sleep(AUCTION_POLL_INTERVAL).await,
+        }
+    }
+}
+
+/// Background task that closes the exhausted orders of oblivious matching every
+/// compaction interval, since no one else can tell when they are filled.
+pub async fn compaction_loop(state: AppState) {
+    loop {
+        tokio::time::sleep(COMPACTION_INTERVAL).await;
+        match state.execute(|orderbook| orderbook.compact_oblivious_orders()).await {
+            Ok(Ok(closed)) if closed > 0 => println!("Closed {} exhausted oblivious orders", closed),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) | Err(e) => eprintln!("Oblivious order compaction failed: {}", e),
+        }
+    }
+}
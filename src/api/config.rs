@@ -4,55 +4,44 @@ use axum::{
     response::IntoResponse,
     Json,
 };
-use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
-
-use crate::utils::orderbook::Orderbook;
-
-#[derive(Serialize)]
-pub struct ConfigResponse {
-    use_encryption: bool,
-}
-
-#[derive(Deserialize)]
-pub struct ConfigRequest {
-    use_encryption: bool,
-}
-
-#[derive(Serialize)]
-pub struct ConfigUpdateResponse {
-    success: bool,
-    message: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
-}
+use crate::api::extract::JsonBody;
+use crate::api::types::{ConfigRequest, ConfigResponse, UpdateResponse};
+use crate::error::OrderbookError;
+use crate::AppState;
 
 pub async fn get_config(
-    State(orderbook): State<Arc<Mutex<Orderbook>>>,
-) -> impl IntoResponse {
-    let orderbook = orderbook.lock().unwrap();
-    
-    let response = ConfigResponse {
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let response = state.execute(|orderbook| ConfigResponse {
         use_encryption: orderbook.is_using_encryption(),
-    };
+        disclosure_policy: orderbook.market_data.disclosure_policy,
+        matching_mode: orderbook.matching_mode,
+    }).await?;
     
-    (StatusCode::OK, Json(response))
+    Ok((StatusCode::OK, Json(response)))
 }
 
 pub async fn update_config(
-    State(orderbook): State<Arc<Mutex<Orderbook>>>,
-    Json(request): Json<ConfigRequest>,
-) -> impl IntoResponse {
-    let mut orderbook = orderbook.lock().unwrap();
-    
-    // Update the encryption setting
-    orderbook.set_use_encryption(request.use_encryption);
+    State(state): State<AppState>,
+    JsonBody(request): JsonBody<ConfigRequest>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let migrated = state.execute(move |orderbook| {
+        let migrated = orderbook.configure(request.use_encryption, request.matching_mode)?;
+        if let Some(disclosure_policy) = request.disclosure_policy {
+            orderbook.market_data.disclosure_policy = disclosure_policy;
+        }
+        Ok::<usize, OrderbookError>(migrated)
+    }).await??;
     
-    let response = ConfigUpdateResponse {
+    let mut message = format!("Encryption has been {}", if request.use_encryption { "enabled" } else { "disabled" });
+    if migrated > 0 {
+        let action = if request.use_encryption { "encrypted" } else { "decrypted" };
+        message = format!("{}; {} live orders were {}", message, migrated, action);
+    }
+    let response = UpdateResponse {
         success: true,
-        message: format!("Encryption has been {}", if request.use_encryption { "enabled" } else { "disabled" }),
-        error: None,
+        message,
     };
     
-    (StatusCode::OK, Json(response))
+    Ok((StatusCode::OK, Json(response)))
 }
@@ -0,0 +1,22 @@
+//! Extractors that reject malformed requests with an `ErrorResponse`, like every
+//! other failure, instead of axum's plain-text rejections.
+
+use axum::extract::FromRequest;
+use axum::extract::FromRequestParts;
+
+use crate::error::OrderbookError;
+
+/// JSON request body
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(OrderbookError))]
+pub struct JsonBody<T>(pub T);
+
+/// Query string parameters
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(OrderbookError))]
+pub struct QueryParams<T>(pub T);
+
+/// Parameters captured from the request path
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(OrderbookError))]
+pub struct PathParams<T>(pub T);
@@ -0,0 +1,68 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::api::extract::{JsonBody, QueryParams};
+use crate::api::types::{FeesResponse, UpdateResponse};
+use crate::error::OrderbookError;
+use crate::utils::fees::FeeSchedule;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct FeesQuery {
+    user_pubkey: Option<String>,
+}
+
+// Get fees accrued per user and per market, optionally for a single user
+pub async fn get_fees(
+    State(state): State<AppState>,
+    QueryParams(query): QueryParams<FeesQuery>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let response = state.execute(move |orderbook| {
+        let engine = &orderbook.fees;
+
+        let users = match query.user_pubkey {
+            Some(user_pubkey) => engine.users
+                .get(&user_pubkey)
+                .map(|fees| HashMap::from([(user_pubkey, fees.clone())]))
+                .unwrap_or_default(),
+            None => engine.users.clone(),
+        };
+
+        FeesResponse {
+            markets: engine.markets.clone(),
+            users,
+        }
+    }).await?;
+
+    Ok((StatusCode::OK, Json(response)))
+}
+
+// Get the current fee schedule
+pub async fn get_fee_schedule(
+    State(state): State<AppState>,
+) -> Result<Json<FeeSchedule>, OrderbookError> {
+    Ok(Json(state.execute(|orderbook| orderbook.fees.schedule.clone()).await?))
+}
+
+// Replace the fee schedule; applies to fills recorded from now on
+pub async fn update_fee_schedule(
+    State(state): State<AppState>,
+    JsonBody(schedule): JsonBody<FeeSchedule>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    schedule.validate()?;
+
+    state.execute(move |orderbook| orderbook.fees.schedule = schedule).await?;
+
+    let response = UpdateResponse {
+        success: true,
+        message: "Fee schedule has been updated".to_string(),
+    };
+
+    Ok((StatusCode::OK, Json(response)))
+}
@@ -0,0 +1,90 @@
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use crate::api::extract::PathParams;
+use crate::api::types::{GenerateKeysRequest, GenerateKeysResponse, KeyInfo, KeysResponse};
+use crate::error::OrderbookError;
+use crate::utils::fhe_operations;
+use crate::utils::generate_key::KeyId;
+use crate::AppState;
+
+// List the key versions the server knows, the parameters of each and the one new orders use
+pub async fn get_keys() -> Result<impl IntoResponse, OrderbookError> {
+    let keys = fhe_operations::key_ids()
+        .into_iter()
+        .map(|id| {
+            let parameters = fhe_operations::key_parameters(id)?;
+            Ok(KeyInfo {
+                key_id: id,
+                profile: parameters.profile,
+                integer_bits: parameters.integer_bits,
+            })
+        })
+        .collect::<Result<Vec<_>, OrderbookError>>()?;
+
+    Ok(Json(KeysResponse {
+        active_key_id: fhe_operations::active_key_id()?,
+        key_ids: fhe_operations::key_ids(),
+        keys,
+    }))
+}
+
+/// Download the public key of a key version
+///
+/// Clients encrypt prices and quantities with it before sending an order, so the
+/// values never cross the network in plaintext. The body is a bincode-serialized
+/// `VersionedPublicKey`. Deriving it the first time takes a while; it is then
+/// saved next to the other keys.
+pub async fn get_public_key(PathParams(id): PathParams<KeyId>) -> Result<impl IntoResponse, OrderbookError> {
+    let bytes = tokio::task::spawn_blocking(move || fhe_operations::public_key_bytes(id))
+        .await
+        .map_err(|e| OrderbookError::Internal(format!("Public key task failed: {}", e)))??;
+
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], bytes.to_vec()))
+}
+
+/// Rotate the FHE keys
+///
+/// Generates a new key version, re-encrypts every resting and waiting stop order
+/// under it and makes it the active key. Older key versions stay loaded so past
+/// fills and closed orders remain readable. The rotation is refused while another
+/// one is running or if any live order could not be re-encrypted, e.g. because
+/// its values do not fit a narrower integer width.
+///
+/// The body may name the parameter profile and integer width of the new keys;
+/// whatever it leaves out is kept from the active keys.
+pub async fn generate_keys(
+    State(state): State<AppState>,
+    body: Result<Json<GenerateKeysRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let request = match body {
+        Ok(Json(request)) => request,
+        // Rotating without a body keeps the active parameters
+        Err(JsonRejection::MissingJsonContentType(_)) => GenerateKeysRequest::default(),
+        Err(e) => return Err(e.into()),
+    };
+    let parameters = fhe_operations::rotation_parameters(request.profile, request.integer_bits)?;
+
+    let _rotation = fhe_operations::begin_rotation()?;
+    state.execute(|orderbook| orderbook.check_key_rotation()).await??;
+
+    // Key generation takes a long time, so keep it off the async runtime and the sequencer
+    let key_id = tokio::task::spawn_blocking(move || fhe_operations::generate_key_version(parameters))
+        .await
+        .map_err(|e| OrderbookError::Internal(format!("Key generation task failed: {}", e)))??;
+
+    // Orders placed during generation used the old key, so they are re-encrypted too
+    let reencrypted = state.execute(move |orderbook| orderbook.rotate_keys(key_id)).await??;
+
+    Ok((StatusCode::OK, Json(GenerateKeysResponse {
+        success: true,
+        message: "FHE keys rotated successfully".to_string(),
+        key_id,
+        profile: parameters.profile,
+        integer_bits: parameters.integer_bits,
+        reencrypted_orders: reencrypted,
+    })))
+}
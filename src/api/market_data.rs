@@ -0,0 +1,59 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+
+use crate::api::extract::QueryParams;
+use crate::api::types::DiscloseResponse;
+use crate::error::OrderbookError;
+use crate::utils::market_data::{Interval, TradeStats};
+use crate::utils::orders::now_millis;
+use crate::AppState;
+
+// Candles returned when no limit is given
+const DEFAULT_CANDLE_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct CandlesQuery {
+    interval: Interval,
+    limit: Option<usize>,
+}
+
+// Get OHLCV candles for an interval, oldest first
+pub async fn get_candles(
+    State(state): State<AppState>,
+    QueryParams(query): QueryParams<CandlesQuery>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let limit = query.limit.unwrap_or(DEFAULT_CANDLE_LIMIT);
+    let candles = state
+        .execute(move |orderbook| orderbook.market_data.candles(query.interval, limit))
+        .await?;
+
+    Ok((StatusCode::OK, Json(candles)))
+}
+
+// Get 24h volume, VWAP, high/low and last price
+pub async fn get_stats(
+    State(state): State<AppState>,
+) -> Result<Json<TradeStats>, OrderbookError> {
+    Ok(Json(state.execute(|orderbook| orderbook.market_data.stats(now_millis())).await?))
+}
+
+// Explicitly disclose pending encrypted fills as one aggregated trade
+pub async fn disclose_trades(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let (disclosed_fills, trade) = state.execute(|orderbook| {
+        let disclosed_fills = orderbook.market_data.pending_count();
+        (disclosed_fills, orderbook.market_data.disclose_pending())
+    }).await?;
+
+    Ok((StatusCode::OK, Json(DiscloseResponse {
+        success: true,
+        disclosed_fills: if trade.is_some() { disclosed_fills } else { 0 },
+        trade,
+    })))
+}
@@ -1,4 +1,10 @@
 pub mod orders;
 pub mod types;
+pub mod extract;
 pub mod config;
-pub mod reset;
\ No newline at end of file
+pub mod reset;
+pub mod fees;
+pub mod market_data;
+pub mod auction;
+pub mod keys;
+pub mod snapshot;
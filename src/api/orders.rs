@@ -1,133 +1,201 @@
-use crate::utils::orders::{Order, Side, Fill};
-use crate::api::types::{OrderRequest, MarketOrderRequest};
+use crate::utils::orders::{Order, OrderStatus, Fill};
+use crate::utils::oblivious::EncryptedFill;
+use crate::api::extract::{JsonBody, PathParams, QueryParams};
+use crate::api::types::{
+    CancelResponse, MarketOrderRequest, OrderRequest, OrderResponse, PeggedOrderRequest, PeggedOrderResponse,
+    StopOrderRequest, StopOrderResponse,
+};
+use crate::error::OrderbookError;
+use crate::journal::{self, JournalEntry};
+use crate::rate_limit;
 use crate::utils::orderbook::Orderbook;
-use crate::utils::generate_key;
+use crate::utils::pegged::TopOfBook;
 use crate::AppState;
 use axum::{extract::State, response::IntoResponse, Json, http::StatusCode};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct FillsQuery {
+    // Only return fills with a later trade id
+    #[serde(default)]
+    after: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct EncryptedFillsQuery {
+    // Only return fills swept after this timestamp (ms)
+    #[serde(default)]
+    after: Option<u64>,
+}
+
+// Turn an order the orderbook rejected into an error response
+fn accepted(order: Order) -> Result<Order, OrderbookError> {
+    if order.status == OrderStatus::Rejected {
+        return Err(OrderbookError::OrderRejected(order.id));
+    }
+    Ok(order)
+}
+
+// Apply an order request within the limits of its account, journaling it if it changed the book
+fn submit(orderbook: &mut Orderbook, entry: JournalEntry) -> Result<Order, OrderbookError> {
+    rate_limit::admit(orderbook, &entry)?;
+    journal::submit(orderbook, entry)
+}
 
 // Get all orders (encrypted or decrypted based on request)
 pub async fn get_orders(
     state: State<AppState>
-) -> Result<Json<(Vec<Order>, Vec<Order>)>, StatusCode> {
-    let orderbook = state.lock().unwrap();
-    
-    // If the orderbook is using encryption, try to get decrypted orders for display
-    if orderbook.use_encryption {
-        match orderbook.get_decrypted_orders() {
-            Ok(orders) => Ok(Json(orders)),
-            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+) -> Result<Json<(Vec<Order>, Vec<Order>)>, OrderbookError> {
+    let orders = state.execute(|orderbook| {
+        // If the orderbook is using encryption, try to get decrypted orders for display
+        if orderbook.use_encryption {
+            orderbook.get_decrypted_orders()
+        } else {
+            // Return plaintext orders
+            Ok(orderbook.get_orders())
         }
-    } else {
-        // Return plaintext orders
-        Ok(Json(orderbook.get_orders()))
-    }
+    }).await??;
+
+    Ok(Json(orders))
+}
+
+// Get a single order by id, including orders that have left the book
+pub async fn get_order(
+    state: State<AppState>,
+    PathParams(id): PathParams<u128>,
+) -> Result<Json<Order>, OrderbookError> {
+    let order = state.execute(move |orderbook| orderbook.get_decrypted_order(id)).await??;
+    Ok(Json(order))
 }
 
-// Get all fills/matches
+// Cancel a resting order
+pub async fn cancel_order(
+    state: State<AppState>,
+    PathParams(id): PathParams<u128>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let order = state.execute(move |orderbook| submit(orderbook, JournalEntry::Cancel { id })).await??;
+
+    Ok((StatusCode::OK, Json(CancelResponse {
+        success: true,
+        id: order.id,
+        status: order.status,
+    })))
+}
+
+// Get all fills/matches of a plaintext book, or only those after a trade id so clients can poll for new ones
 pub async fn get_fills(
-    state: State<AppState>
-) -> Json<Vec<Fill>> {
-    let orderbook = state.lock().unwrap();
-    Json(orderbook.get_fills())
+    state: State<AppState>,
+    QueryParams(query): QueryParams<FillsQuery>,
+) -> Result<Json<Vec<Fill>>, OrderbookError> {
+    let after = query.after.unwrap_or(0);
+    let fills = state.execute(move |orderbook| orderbook.public_fills(after)).await??;
+    Ok(Json(fills))
+}
+
+// Get the encrypted fills produced by oblivious matching, or only those swept after a timestamp
+pub async fn get_encrypted_fills(
+    state: State<AppState>,
+    QueryParams(query): QueryParams<EncryptedFillsQuery>,
+) -> Result<Json<Vec<EncryptedFill>>, OrderbookError> {
+    let after = query.after.unwrap_or(0);
+    Ok(Json(state.execute(move |orderbook| orderbook.get_encrypted_fills(after)).await?))
 }
 
 // Add a limit order
 pub async fn add_order(
     state: State<AppState>,
-    Json(req): Json<OrderRequest>,
-) -> impl IntoResponse {
-    let side = match req.side.to_lowercase().as_str() {
-        "buy" => Side::Buy,
-        "sell" => Side::Sell,
-        _ => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ 
-            "success": false, 
-            "error": "Invalid side: must be 'buy' or 'sell'" 
-        }))),
-    };
-
-    let mut orderbook = state.lock().unwrap();
-    orderbook.count += 1;
-    let id = orderbook.count;
+    JsonBody(req): JsonBody<OrderRequest>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let result = state.execute(move |orderbook| submit(orderbook, JournalEntry::Limit(req))).await??;
+    let result = accepted(result)?;
     
-    let order = Order::new(
-        id, 
-        req.price, 
-        req.quantity, 
-        side, 
-        req.user_pubkey
-    );
-    
-    let result = orderbook.add_order(order);
-    
-    (StatusCode::OK, Json(serde_json::json!({ 
-        "success": true, 
-        "id": id,
-        "is_encrypted": result.is_encrypted
+    Ok((StatusCode::OK, Json(OrderResponse {
+        success: true,
+        id: result.id,
+        status: result.status,
+        is_encrypted: result.is_encrypted,
+    })))
+}
+
+// Get the stop orders waiting in the trigger book
+pub async fn get_stop_orders(
+    state: State<AppState>
+) -> Result<Json<Vec<Order>>, OrderbookError> {
+    Ok(Json(state.execute(|orderbook| orderbook.get_stop_orders()).await?))
+}
+
+// Add a stop-market or stop-limit order
+pub async fn add_stop_order(
+    state: State<AppState>,
+    JsonBody(req): JsonBody<StopOrderRequest>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let (result, triggered) = state.execute(move |orderbook| {
+        let result = submit(orderbook, JournalEntry::Stop(req))?;
+        let triggered = orderbook.trigger_book.get(result.id).is_none();
+        Ok::<(Order, bool), OrderbookError>((result, triggered))
+    }).await??;
+    let result = accepted(result)?;
+
+    Ok((StatusCode::OK, Json(StopOrderResponse {
+        success: true,
+        id: result.id,
+        status: result.status,
+        triggered,
+    })))
+}
+
+// Add an order pegged to the best bid, best ask or mid
+pub async fn add_pegged_order(
+    state: State<AppState>,
+    JsonBody(req): JsonBody<PeggedOrderRequest>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let (result, top_of_book) = state.execute(move |orderbook| {
+        let result = submit(orderbook, JournalEntry::Pegged(req))?;
+        Ok::<(Order, TopOfBook), OrderbookError>((result, orderbook.top_of_book))
+    }).await??;
+    let result = accepted(result)?;
+
+    Ok((StatusCode::OK, Json(PeggedOrderResponse {
+        success: true,
+        id: result.id,
+        status: result.status,
+        price: result.price,
+        top_of_book,
     })))
 }
 
 // Add a market buy order
 pub async fn market_buy(
     state: State<AppState>,
-    Json(req): Json<MarketOrderRequest>,
-) -> impl IntoResponse {
-    let mut orderbook = state.lock().unwrap();
-    
-    match orderbook.market_buy(req.quantity, req.user_pubkey) {
-        Some(order) => {
-            (StatusCode::OK, Json(serde_json::json!({
-                "success": true,
-                "id": order.id,
-                "is_encrypted": order.is_encrypted
-            })))
-        }
-        None => {
-            (StatusCode::BAD_REQUEST, Json(serde_json::json!({
-                "success": false,
-                "error": "No matching sell orders available"
-            })))
-        }
-    }
+    JsonBody(req): JsonBody<MarketOrderRequest>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let order = state
+        .execute(move |orderbook| submit(orderbook, JournalEntry::MarketBuy(req)))
+        .await??;
+    let order = accepted(order)?;
+
+    Ok((StatusCode::OK, Json(OrderResponse {
+        success: true,
+        id: order.id,
+        status: order.status,
+        is_encrypted: order.is_encrypted,
+    })))
 }
 
 // Add a market sell order
 pub async fn market_sell(
     state: State<AppState>,
-    Json(req): Json<MarketOrderRequest>,
-) -> impl IntoResponse {
-    let mut orderbook = state.lock().unwrap();
-    
-    match orderbook.market_sell(req.quantity, req.user_pubkey) {
-        Some(order) => {
-            (StatusCode::OK, Json(serde_json::json!({
-                "success": true,
-                "id": order.id,
-                "is_encrypted": order.is_encrypted
-            })))
-        }
-        None => {
-            (StatusCode::BAD_REQUEST, Json(serde_json::json!({
-                "success": false,
-                "error": "No matching buy orders available"
-            })))
-        }
-    }
-}
+    JsonBody(req): JsonBody<MarketOrderRequest>,
+) -> Result<impl IntoResponse, OrderbookError> {
+    let order = state
+        .execute(move |orderbook| submit(orderbook, JournalEntry::MarketSell(req)))
+        .await??;
+    let order = accepted(order)?;
 
-// Generate FHE keys
-pub async fn generate_keys() -> impl IntoResponse {
-    match generate_key::generate_and_save_keys() {
-        Ok(_) => {
-            (StatusCode::OK, Json(serde_json::json!({
-                "success": true,
-                "message": "FHE keys generated successfully"
-            })))
-        }
-        Err(e) => {
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({
-                "success": false,
-                "error": format!("Failed to generate FHE keys: {}", e)
-            })))
-        }
-    }
-}
\ No newline at end of file
+    Ok((StatusCode::OK, Json(OrderResponse {
+        success: true,
+        id: order.id,
+        status: order.status,
+        is_encrypted: order.is_encrypted,
+    })))
+}
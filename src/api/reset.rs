@@ -2,35 +2,50 @@ use axum::{
     extract::State,
     response::Json,
 };
+use crate::api::types::ResetResponse;
+use crate::error::OrderbookError;
 use crate::AppState;
-use serde_json::{json, Value};
+use crate::utils::fees::FeeEngine;
+use crate::utils::market_data::MarketData;
 
 /// Reset the orderbook state
 /// 
-/// This endpoint clears all orders and fills from the orderbook,
-/// but maintains the current encryption settings.
+/// This endpoint clears all orders, fills, accrued fees and market data from the
+/// orderbook, but maintains the market, the current encryption settings, fee
+/// schedule, disclosure policy and matching mode.
 pub async fn reset_orderbook(
     State(state): State<AppState>,
-) -> Json<Value> {
-    let mut orderbook = state.lock().unwrap();
-    
-    // Store the current encryption setting
-    let use_encryption = orderbook.is_using_encryption();
-    let server_key = orderbook.server_key.clone();
-    
-    // Reset the orderbook while maintaining encryption settings
-    *orderbook = if use_encryption && server_key.is_some() {
-        crate::utils::orderbook::Orderbook::new(server_key)
-    } else {
-        crate::utils::orderbook::Orderbook::new(None)
-    };
-    
-    // Restore encryption setting
-    orderbook.set_use_encryption(use_encryption);
+) -> Result<Json<ResetResponse>, OrderbookError> {
+    let use_encryption = state.execute(|orderbook| {
+        // Store the current encryption setting
+        let use_encryption = orderbook.is_using_encryption();
+        let server_key = orderbook.server_key.clone();
+        let fee_schedule = orderbook.fees.schedule.clone();
+        let disclosure_policy = orderbook.market_data.disclosure_policy;
+        let matching_mode = orderbook.matching_mode;
+        let market = orderbook.market.clone();
+
+        // Reset the orderbook while maintaining encryption settings
+        *orderbook = if use_encryption && server_key.is_some() {
+            crate::utils::orderbook::Orderbook::new(server_key)
+        } else {
+            crate::utils::orderbook::Orderbook::new(None)
+        };
+
+        // Restore encryption setting, fee schedule, disclosure policy and matching mode.
+        // The new book is empty, so there are no orders to migrate.
+        orderbook.set_use_encryption(use_encryption)?;
+        orderbook.fees = FeeEngine::new(fee_schedule);
+        orderbook.market_data = MarketData::new(disclosure_policy);
+        orderbook.matching_mode = matching_mode;
+        orderbook.market = market;
+
+        Ok::<bool, OrderbookError>(use_encryption)
+    }).await??;
     
-    Json(json!({
-        "success": true,
-        "message": "Orderbook has been reset",
-        "use_encryption": use_encryption
+    Ok(Json(ResetResponse {
+        success: true,
+        message: "Orderbook has been reset".to_string(),
+        use_encryption,
     }))
 }
@@ -0,0 +1,16 @@
+use axum::{extract::State, Json};
+use crate::error::OrderbookError;
+use crate::utils::snapshot::BookSnapshot;
+use crate::AppState;
+
+/// Take a snapshot of the book
+///
+/// Ciphertexts are included as they are stored, but encrypted orders outside
+/// oblivious matching also keep their plaintext price and quantity, so the
+/// snapshot reveals them and should be protected like the client key. Save it to the file named by
+/// `ORDERBOOK_SNAPSHOT` to have the server restore it on startup.
+pub async fn get_snapshot(
+    State(state): State<AppState>,
+) -> Result<Json<BookSnapshot>, OrderbookError> {
+    Ok(Json(state.execute(|orderbook| orderbook.snapshot()).await?))
+}
@@ -1,20 +1,208 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::error::ErrorCode;
+use crate::utils::auction::MatchingMode;
+use crate::utils::ciphertext::Ciphertext;
+use crate::utils::fees::{MarketFees, UserFees};
+use crate::utils::fhe_params::{IntegerWidth, ParameterProfile};
+use crate::utils::generate_key::KeyId;
+use crate::utils::market_data::{DisclosurePolicy, MarketTrade};
+use crate::utils::orders::OrderStatus;
+use crate::utils::pegged::{PegReference, TopOfBook};
 
-#[derive(Deserialize)]
+// Requests
+
+#[derive(Serialize, Deserialize)]
 pub struct OrderRequest {
-    pub price: u32,
+    // Plaintext price and quantity; omitted when the client sends them encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<u32>,
+    // Price and quantity encrypted by the client with the public key of a key version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_price: Option<Ciphertext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_quantity: Option<Ciphertext>,
+    pub side: String,
+    pub user_pubkey: String,
+    // Optional expiry time (milliseconds since the Unix epoch)
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    // Makes the order an iceberg that only shows this much at a time
+    #[serde(default)]
+    pub display_quantity: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StopOrderRequest {
+    // Plaintext trigger price; omitted when the client sends it encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger_price: Option<u32>,
+    // Trigger price encrypted by the client with the public key of a key version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_trigger_price: Option<Ciphertext>,
+    // Enters as a limit order at this price when triggered; as a market order if omitted
+    #[serde(default)]
+    pub limit_price: Option<u32>,
     pub quantity: u32,
     pub side: String,
     pub user_pubkey: String,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
+pub struct PeggedOrderRequest {
+    pub reference: PegReference,
+    // Ticks added to the reference price; must be 0 for midpoint orders
+    #[serde(default)]
+    pub offset: i32,
+    pub quantity: u32,
+    pub side: String,
+    pub user_pubkey: String,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct MarketOrderRequest {
     pub quantity: u32,
     pub user_pubkey: String,
 }
 
+#[derive(Serialize, Deserialize, Default)]
+pub struct GenerateKeysRequest {
+    // Parameter profile of the new keys; the active key's profile if omitted
+    #[serde(default)]
+    pub profile: Option<ParameterProfile>,
+    // Width of the new keys' encrypted integers, 16 or 32; the active key's width if omitted
+    #[serde(default)]
+    pub integer_bits: Option<IntegerWidth>,
+}
+
 #[derive(Serialize, Deserialize)]
-pub struct OrderbookConfig {
+pub struct ConfigRequest {
     pub use_encryption: bool,
+    // Leaves the current policy unchanged when omitted
+    #[serde(default)]
+    pub disclosure_policy: Option<DisclosurePolicy>,
+    #[serde(default)]
+    pub matching_mode: Option<MatchingMode>,
+}
+
+// Responses
+
+/// Body of every failed request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub code: ErrorCode,
+    pub error: String,
+    // How long to wait before retrying a throttled request, also sent as `Retry-After`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+// Response to a limit or market order
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderResponse {
+    pub success: bool,
+    pub id: u128,
+    pub status: OrderStatus,
+    pub is_encrypted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelResponse {
+    pub success: bool,
+    pub id: u128,
+    pub status: OrderStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StopOrderResponse {
+    pub success: bool,
+    pub id: u128,
+    pub status: OrderStatus,
+    // Whether the order was triggered straight away instead of waiting
+    pub triggered: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeggedOrderResponse {
+    pub success: bool,
+    pub id: u128,
+    pub status: OrderStatus,
+    // Price the order was placed at
+    pub price: u32,
+    pub top_of_book: TopOfBook,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyInfo {
+    pub key_id: KeyId,
+    pub profile: ParameterProfile,
+    pub integer_bits: IntegerWidth,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeysResponse {
+    // Key version new orders are encrypted under
+    pub active_key_id: KeyId,
+    pub key_ids: Vec<KeyId>,
+    pub keys: Vec<KeyInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateKeysResponse {
+    pub success: bool,
+    pub message: String,
+    pub key_id: KeyId,
+    pub profile: ParameterProfile,
+    pub integer_bits: IntegerWidth,
+    pub reencrypted_orders: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigResponse {
+    pub use_encryption: bool,
+    pub disclosure_policy: DisclosurePolicy,
+    pub matching_mode: MatchingMode,
+}
+
+// Response to a request that changes a setting
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetResponse {
+    pub success: bool,
+    pub message: String,
+    pub use_encryption: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeesResponse {
+    pub markets: HashMap<String, MarketFees>,
+    pub users: HashMap<String, UserFees>,
+}
+
+// Result of a batch auction or an encrypted clearing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuctionResponse<T> {
+    pub success: bool,
+    pub result: T,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiscloseResponse {
+    pub success: bool,
+    pub disclosed_fills: usize,
+    // The aggregated trade; absent when nothing was pending
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trade: Option<MarketTrade>,
 }
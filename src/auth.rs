@@ -0,0 +1,170 @@
+//! Authentication of the admin endpoints. Operators call them with a bearer
+//! token from the configuration, which is kept apart from the `user_pubkey`
+//! traders identify with, and every request to them is audited.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, State},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{AdminConfig, AdminToken};
+use crate::error::OrderbookError;
+use crate::utils::orders::now_millis;
+
+/// Admin tokens and the audit log, shared by the admin middleware
+pub struct AdminAuth {
+    tokens: Vec<AdminToken>,
+    audit_log: Option<Mutex<File>>,
+}
+
+/// A request to an admin endpoint, as written to the audit log
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    // Name of the token the request was made with; unset if it was refused for lack of one
+    pub admin: Option<String>,
+    pub remote_addr: SocketAddr,
+    pub method: String,
+    pub path: String,
+    // JSON body of the request, if it had one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<serde_json::Value>,
+    pub status: u16,
+}
+
+impl AdminAuth {
+    /// Admin tokens of the configuration, opening the audit log for appending if one is set
+    pub fn new(config: &AdminConfig) -> io::Result<Self> {
+        let audit_log = match &config.audit_log {
+            Some(path) => Some(Mutex::new(open_audit_log(path)?)),
+            None => None,
+        };
+        if config.tokens.is_empty() {
+            eprintln!("Warning: No admin token is configured, so the admin endpoints are disabled");
+        }
+        Ok(Self { tokens: config.tokens.clone(), audit_log })
+    }
+
+    // Name of the admin whose token the request carries
+    fn authenticate(&self, headers: &HeaderMap) -> Result<&str, OrderbookError> {
+        if self.tokens.is_empty() {
+            return Err(OrderbookError::Forbidden(
+                "Admin endpoints are disabled: no admin token is configured".to_string()
+            ));
+        }
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+            .ok_or_else(|| OrderbookError::Unauthorized("This endpoint requires an admin token".to_string()))?;
+
+        // Compare with every token, so the time taken does not tell which one nearly matched
+        self.tokens
+            .iter()
+            .fold(None, |found, admin| {
+                if constant_time_eq(admin.token.as_bytes(), token.as_bytes()) {
+                    Some(admin.name.as_str())
+                } else {
+                    found
+                }
+            })
+            .ok_or_else(|| OrderbookError::Unauthorized("Invalid admin token".to_string()))
+    }
+
+    fn audit(&self, entry: &AuditEntry) {
+        println!(
+            "Audit: {} {} by {} from {} -> {}",
+            entry.method,
+            entry.path,
+            entry.admin.as_deref().unwrap_or("unauthenticated client"),
+            entry.remote_addr.ip(),
+            entry.status
+        );
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to encode an audit log entry: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = audit_log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = file.write_all(&line) {
+            eprintln!("Failed to write to the audit log: {}", e);
+        }
+    }
+}
+
+fn open_audit_log(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Cannot open audit log {}: {}", path.display(), e)))?;
+    println!("Auditing admin requests to {}", path.display());
+    Ok(file)
+}
+
+// Equality that takes the same time wherever the first difference is
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Middleware of the admin endpoints. Requests without a valid admin token are
+/// refused; every request is audited, whether or not it was allowed.
+pub async fn require_admin(
+    State(auth): State<Arc<AdminAuth>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+
+    let (admin, body, response) = match auth.authenticate(request.headers()) {
+        Ok(admin) => {
+            let admin = Some(admin.to_string());
+            // Read the body so it can be audited, then hand it on to the handler
+            let (parts, body) = request.into_parts();
+            match Bytes::from_request(Request::new(body), &()).await {
+                Ok(bytes) => {
+                    let body = serde_json::from_slice(&bytes).ok();
+                    (admin, body, next.run(Request::from_parts(parts, Body::from(bytes))).await)
+                }
+                Err(rejection) => (admin, None, OrderbookError::InvalidRequest(rejection.body_text()).into_response()),
+            }
+        }
+        Err(e) => {
+            let mut response = e.into_response();
+            if response.status() == StatusCode::UNAUTHORIZED {
+                response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            (None, None, response)
+        }
+    };
+
+    auth.audit(&AuditEntry {
+        timestamp: now_millis(),
+        admin,
+        remote_addr,
+        method,
+        path,
+        request: body,
+        status: response.status().as_u16(),
+    });
+    response
+}
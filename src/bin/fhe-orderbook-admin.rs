@@ -0,0 +1,325 @@
+//! Administration tool for the encrypted orderbook. Works offline on the key
+//! store and on snapshot and journal files, so run it while the server is
+//! stopped when it changes keys.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process;
+use fhe_orderbook::cli::Args;
+use fhe_orderbook::config::ServerConfig;
+use fhe_orderbook::journal;
+use fhe_orderbook::utils::ciphertext::Ciphertext;
+use fhe_orderbook::utils::fhe_operations;
+use fhe_orderbook::utils::fhe_params::{IntegerWidth, KeyParameters, ParameterProfile};
+use fhe_orderbook::utils::generate_key::{self, KeyId};
+use fhe_orderbook::utils::key_store;
+use fhe_orderbook::utils::orderbook::Orderbook;
+use fhe_orderbook::utils::orders::Order;
+use fhe_orderbook::utils::snapshot::{self, BookSnapshot};
+
+type AdminResult = Result<(), Box<dyn Error>>;
+
+const USAGE: &str = "Usage: fhe-orderbook-admin <command>
+
+Keys:
+  keys generate [--profile P] [--integer-bits N]   Generate the first keys
+  keys inspect                                     List key versions and their parameters
+  keys rotate [--profile P] [--integer-bits N] [--snapshot FILE]
+                                                   Generate and activate a new key version,
+                                                   re-encrypting the orders in FILE
+
+Values:
+  encrypt VALUE [--key-id N] [--out FILE]          Encrypt a test value
+  decrypt FILE                                     Decrypt a ciphertext written by encrypt
+
+Books:
+  snapshot inspect [FILE]                          Show the orders in a snapshot
+  snapshot restore FILE [--out PATH]               Check a snapshot and move it onto the active keys
+  markets [FILE...]                                List the markets in snapshots
+  replay JOURNAL --out FILE [--from SNAPSHOT]      Rebuild a book from an order journal
+
+FILE defaults to the server's snapshot (storage.snapshot or $ORDERBOOK_SNAPSHOT)
+where it is optional. Keys are read from the key store the server is configured with.";
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1), &[]) {
+        Ok(args) => args,
+        Err(e) => fail(&e),
+    };
+    // Use the server's configuration, so keys and snapshots are found where it keeps them
+    let config = ServerConfig::load().unwrap_or_else(|e| fail(&e.to_string()));
+    if let Err(e) = key_store::build(config.keys.store, &config.keys.dir).and_then(generate_key::set_key_store) {
+        fail(&format!("Failed to open the FHE key store: {}", e));
+    }
+    if let Err(e) = run(&args, &config) {
+        fail(&e.to_string());
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    process::exit(1);
+}
+
+fn run(args: &Args, config: &ServerConfig) -> AdminResult {
+    match args.command().as_slice() {
+        ["keys", "generate"] => generate_keys(args),
+        ["keys", "inspect"] => inspect_keys(args),
+        ["keys", "rotate"] => rotate_keys(args),
+        ["encrypt", value] => encrypt(args, value),
+        ["decrypt", file] => decrypt(args, file),
+        ["snapshot", "inspect", rest @ ..] if rest.len() <= 1 => inspect_snapshot(args, config, rest.first().copied()),
+        ["snapshot", "restore", file] => restore_snapshot(args, config, file),
+        ["markets", files @ ..] => list_markets(args, config, files),
+        ["replay", file] => replay(args, file),
+        [] | ["help"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            Err("Unknown command".into())
+        }
+    }
+}
+
+// Parameter options of the key commands; unset ones are left to the caller's defaults
+fn parameter_options(args: &Args) -> Result<(Option<ParameterProfile>, Option<IntegerWidth>), String> {
+    let profile = args.option("profile").map(ParameterProfile::try_from).transpose()?;
+    let integer_bits = args
+        .option("integer-bits")
+        .map(|bits| {
+            let bits: u32 = bits.parse().map_err(|_| "--integer-bits must be 16 or 32".to_string())?;
+            IntegerWidth::try_from(bits)
+        })
+        .transpose()?;
+    Ok((profile, integer_bits))
+}
+
+// Load the existing keys; unlike the server, never generate them implicitly
+fn load_keys() -> AdminResult {
+    if !generate_key::keys_exist() {
+        return Err("No FHE keys found; create them with `keys generate`".into());
+    }
+    fhe_operations::init_fhe()?;
+    Ok(())
+}
+
+// An empty book using the active keys, or plaintext if encryption is not needed
+fn empty_book(encrypted: bool) -> Result<Orderbook, Box<dyn Error>> {
+    if !encrypted {
+        return Ok(Orderbook::new(None));
+    }
+    load_keys()?;
+    Ok(Orderbook::new(Some((*fhe_operations::get_server_key()?).clone())))
+}
+
+fn snapshot_path(config: &ServerConfig, file: Option<&str>) -> Result<String, String> {
+    file.map(str::to_string)
+        .or_else(|| config.storage.snapshot.as_ref().map(|path| path.display().to_string()))
+        .ok_or_else(|| "No snapshot given and the server has none configured".to_string())
+}
+
+fn generate_keys(args: &Args) -> AdminResult {
+    args.allow(&["profile", "integer-bits"])?;
+    if generate_key::keys_exist() {
+        return Err("FHE keys already exist; use `keys rotate` to replace them".into());
+    }
+    let (profile, integer_bits) = parameter_options(args)?;
+    let defaults = KeyParameters::from_env()?;
+    let parameters = KeyParameters {
+        profile: profile.unwrap_or(defaults.profile),
+        integer_bits: integer_bits.unwrap_or(defaults.integer_bits),
+    };
+    generate_key::generate_and_save_keys(generate_key::active_key_id()?, parameters)?;
+    Ok(())
+}
+
+fn inspect_keys(args: &Args) -> AdminResult {
+    args.allow(&[])?;
+    let active = generate_key::active_key_id()?;
+    // Versions are numbered from 1; a rotation may have left newer, inactive ones
+    let versions: Vec<KeyId> = (1..)
+        .take_while(|id| *id <= active || generate_key::key_version_exists(*id))
+        .filter(|id| generate_key::key_version_exists(*id))
+        .collect();
+    if versions.is_empty() {
+        println!("No FHE keys found");
+        return Ok(());
+    }
+
+    println!("Active key version: {}", active);
+    for id in versions {
+        let parameters = generate_key::load_key_parameters(id)?;
+        let protection = if generate_key::client_key_sealed(id)? { "passphrase-protected" } else { "unprotected" };
+        let marker = if id == active { " (active)" } else { "" };
+        println!("  version {}{}: {}, client key {}", id, marker, parameters, protection);
+    }
+    Ok(())
+}
+
+fn rotate_keys(args: &Args) -> AdminResult {
+    args.allow(&["profile", "integer-bits", "snapshot"])?;
+    load_keys()?;
+    let (profile, integer_bits) = parameter_options(args)?;
+    let parameters = fhe_operations::rotation_parameters(profile, integer_bits)?;
+
+    // Check the book can move onto the new keys before spending time generating them
+    let book = match args.option("snapshot") {
+        Some(path) => {
+            let mut book = empty_book(true)?;
+            book.restore(snapshot::load(path)?)?;
+            book.check_key_rotation()?;
+            Some((path, book))
+        }
+        None => None,
+    };
+
+    let key_id = fhe_operations::generate_key_version(parameters)?;
+    match book {
+        Some((path, mut book)) => {
+            let reencrypted = book.rotate_keys(key_id)?;
+            snapshot::save(&book.snapshot(), path)?;
+            println!("Re-encrypted {} orders in {}", reencrypted, path);
+        }
+        None => fhe_operations::activate_key(key_id)?,
+    }
+    println!("Key version {} is now active ({})", key_id, parameters);
+    Ok(())
+}
+
+fn encrypt(args: &Args, value: &str) -> AdminResult {
+    args.allow(&["key-id", "out"])?;
+    let value: u32 = value.parse().map_err(|_| format!("Invalid value '{}'", value))?;
+    load_keys()?;
+    let client_key = match args.option("key-id") {
+        Some(id) => fhe_operations::client_key_for(id.parse().map_err(|_| format!("Invalid key id '{}'", id))?)?,
+        None => fhe_operations::client_key()?,
+    };
+
+    let ciphertext = serde_json::to_string(&fhe_operations::encrypt_u32(value, &client_key)?)?;
+    match args.option("out") {
+        Some(path) => fs::write(path, ciphertext)?,
+        None => println!("{}", ciphertext),
+    }
+    Ok(())
+}
+
+fn decrypt(args: &Args, file: &str) -> AdminResult {
+    args.allow(&[])?;
+    let ciphertext: Ciphertext = serde_json::from_slice(&fs::read(file)?)?;
+    load_keys()?;
+    fhe_operations::verify_ciphertext(&ciphertext)?;
+    println!("{}", fhe_operations::decrypt_u32(&ciphertext)?);
+    Ok(())
+}
+
+// Print orders, decrypting the ones that hold ciphertexts
+fn print_orders(title: &str, orders: &[Order]) -> AdminResult {
+    println!("{} ({}):", title, orders.len());
+    for order in orders {
+        let (price, quantity) = fhe_operations::decrypt_order(order)?;
+        let trigger = match (&order.encrypted_trigger_price, order.trigger_price) {
+            (Some(encrypted), _) => format!(", trigger {}", fhe_operations::decrypt_u32(encrypted)?),
+            (None, Some(trigger_price)) => format!(", trigger {}", trigger_price),
+            (None, None) => String::new(),
+        };
+        let encrypted = if order.is_encrypted { ", encrypted" } else { "" };
+        println!(
+            "  #{} {:?} {} @ {} ({} filled{}, {:?}, {:?}, {}{})",
+            order.id,
+            order.side,
+            quantity,
+            price,
+            order.filled_quantity,
+            trigger,
+            order.order_type,
+            order.status,
+            order.user_pubkey,
+            encrypted
+        );
+    }
+    Ok(())
+}
+
+fn inspect_snapshot(args: &Args, config: &ServerConfig, file: Option<&str>) -> AdminResult {
+    args.allow(&[])?;
+    let path = snapshot_path(config, file)?;
+    let snapshot = snapshot::load(&path)?;
+    if snapshot.live_orders().any(|order| order.ciphertexts().next().is_some()) {
+        load_keys()?;
+    }
+
+    println!("Market: {}", snapshot.market);
+    println!("Taken at: {} ms", snapshot.taken_at);
+    println!("Encryption: {}", if snapshot.use_encryption { "enabled" } else { "disabled" });
+    println!("Matching mode: {:?}", snapshot.matching_mode);
+    println!("Fills: {} ({} encrypted)", snapshot.fills.len(), snapshot.encrypted_fills.len());
+    println!("Closed orders: {}", snapshot.closed_orders.len());
+    print_orders("Buy orders", &snapshot.buy_orders)?;
+    print_orders("Sell orders", &snapshot.sell_orders)?;
+    print_orders("Stop orders", &snapshot.stop_orders)?;
+    Ok(())
+}
+
+fn restore_snapshot(args: &Args, config: &ServerConfig, file: &str) -> AdminResult {
+    args.allow(&["out"])?;
+    let out = snapshot_path(config, args.option("out"))?;
+    let snapshot = snapshot::load(file)?;
+    let mut book = empty_book(snapshot.use_encryption)?;
+    let reencrypted = book.restore(snapshot)?;
+    snapshot::save(&book.snapshot(), &out)?;
+    println!("Wrote {} ({} orders re-encrypted); the server restores it on startup", out, reencrypted);
+    Ok(())
+}
+
+fn list_markets(args: &Args, config: &ServerConfig, files: &[&str]) -> AdminResult {
+    args.allow(&[])?;
+    let paths = match files {
+        [] => vec![snapshot_path(config, None)?],
+        files => files.iter().map(|file| file.to_string()).collect(),
+    };
+    for path in paths {
+        let snapshot: BookSnapshot = snapshot::load(&path)?;
+        let last_price = snapshot.last_trade_price.map_or("none".to_string(), |price| price.to_string());
+        println!(
+            "{}: {} bids, {} asks, {} stop orders, last price {} ({})",
+            snapshot.market,
+            snapshot.buy_orders.len(),
+            snapshot.sell_orders.len(),
+            snapshot.stop_orders.len(),
+            last_price,
+            path
+        );
+    }
+    Ok(())
+}
+
+fn replay(args: &Args, file: &str) -> AdminResult {
+    args.allow(&["out", "from"])?;
+    let out = args.option("out").ok_or("replay needs --out FILE")?;
+    let entries = journal::read(file)?;
+
+    // Start from the given snapshot, or from an empty book set up like the server's
+    let mut book = match args.option("from") {
+        Some(path) => {
+            let snapshot = snapshot::load(path)?;
+            let mut book = empty_book(snapshot.use_encryption)?;
+            book.restore(snapshot)?;
+            book
+        }
+        None => empty_book(generate_key::keys_exist())?,
+    };
+
+    let mut failed = 0;
+    for entry in &entries {
+        if let Err(e) = entry.apply(&mut book) {
+            eprintln!("Entry failed on replay: {}", e);
+            failed += 1;
+        }
+    }
+    snapshot::save(&book.snapshot(), out)?;
+    println!("Replayed {} entries ({} failed) into {}", entries.len(), failed, out);
+    Ok(())
+}
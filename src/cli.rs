@@ -0,0 +1,51 @@
+//! Argument parsing shared by the command-line tools
+
+/// Command-line arguments with `--name value` options and `--name` flags split
+/// from positional arguments
+pub struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    /// Parse `args`, treating the names in `flags` as options without a value
+    pub fn parse(args: impl Iterator<Item = String>, flags: &[&str]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut set_flags = Vec::new();
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if flags.contains(&name) => set_flags.push(name.to_string()),
+                Some(name) => {
+                    let value = args.next().ok_or_else(|| format!("--{} needs a value", name))?;
+                    options.push((name.to_string(), value));
+                }
+                None => positional.push(arg),
+            }
+        }
+        Ok(Self { positional, options, flags: set_flags })
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.iter().find(|(option, _)| option == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    // Reject options and flags the command does not take, so a typo is not silently ignored
+    pub fn allow(&self, names: &[&str]) -> Result<(), String> {
+        let mut given = self.options.iter().map(|(option, _)| option).chain(&self.flags);
+        match given.find(|name| !names.contains(&name.as_str())) {
+            Some(name) => Err(format!("Unknown option --{}", name)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn command(&self) -> Vec<&str> {
+        self.positional.iter().map(String::as_str).collect()
+    }
+}
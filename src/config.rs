@@ -0,0 +1,504 @@
+//! Server configuration, read from a TOML file and then overridden by
+//! environment variables. Every setting has a default, so the file and each
+//! of its sections are optional.
+
+use axum::http::HeaderValue;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::journal::JOURNAL_ENV;
+use crate::rate_limit::RateLimit;
+use crate::utils::auction::MatchingMode;
+use crate::utils::key_store::{KeyStoreBackend, DEFAULT_KEY_DIR, KEY_DIR_ENV, KEY_STORE_ENV};
+use crate::utils::market_data::DisclosurePolicy;
+use crate::utils::orderbook::DEFAULT_MARKET;
+
+// File the configuration is read from; `orderbook.toml` is used if it exists
+const CONFIG_ENV: &str = "ORDERBOOK_CONFIG";
+const DEFAULT_CONFIG_FILE: &str = "orderbook.toml";
+
+// Snapshot the book is restored from on startup, if the file exists
+pub const SNAPSHOT_ENV: &str = "ORDERBOOK_SNAPSHOT";
+
+const BIND_ENV: &str = "ORDERBOOK_BIND";
+// Comma-separated origins, or `*` for any
+const CORS_ORIGINS_ENV: &str = "ORDERBOOK_CORS_ORIGINS";
+const TLS_CERT_ENV: &str = "ORDERBOOK_TLS_CERT";
+const TLS_KEY_ENV: &str = "ORDERBOOK_TLS_KEY";
+const MARKET_ENV: &str = "ORDERBOOK_MARKET";
+const ENCRYPTION_ENV: &str = "ORDERBOOK_ENCRYPTION";
+// `continuous`, `oblivious` or `batch_auction:<interval_ms>`
+const MATCHING_MODE_ENV: &str = "ORDERBOOK_MATCHING_MODE";
+// `hidden` or `aggregated:<batch_size>`
+const DISCLOSURE_POLICY_ENV: &str = "ORDERBOOK_DISCLOSURE_POLICY";
+// Single admin token, replacing those in the file
+const ADMIN_TOKEN_ENV: &str = "ORDERBOOK_ADMIN_TOKEN";
+const AUDIT_LOG_ENV: &str = "ORDERBOOK_AUDIT_LOG";
+// Rate limits as `<per_second>:<burst>`
+const RATE_LIMIT_IP_ENV: &str = "ORDERBOOK_RATE_LIMIT_IP";
+const RATE_LIMIT_ACCOUNT_ENV: &str = "ORDERBOOK_RATE_LIMIT_ACCOUNT";
+const RATE_LIMIT_ENCRYPTED_ENV: &str = "ORDERBOOK_RATE_LIMIT_ENCRYPTED";
+const MAX_OPEN_ORDERS_ENV: &str = "ORDERBOOK_MAX_OPEN_ORDERS";
+
+// Name of the admin token given through the environment
+const ENV_ADMIN_NAME: &str = "admin";
+// Shortest admin token accepted, so tokens cannot be guessed
+const MIN_ADMIN_TOKEN_LEN: usize = 16;
+
+// Origin that allows requests from anywhere
+const ANY_ORIGIN: &str = "*";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: HttpConfig,
+    pub keys: KeysConfig,
+    pub storage: StorageConfig,
+    pub market: MarketConfig,
+    pub admin: AdminConfig,
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind: SocketAddr,
+    // Origins browsers may call the API from; `*` allows any
+    pub cors_origins: Vec<String>,
+    // Serve HTTPS instead of HTTP
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            cors_origins: vec![ANY_ORIGIN.to_string()],
+            tls: None,
+        }
+    }
+}
+
+/// PEM certificate chain and private key the server presents
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    pub store: KeyStoreBackend,
+    // Directory of the file and KMS stores
+    pub dir: PathBuf,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        Self { store: KeyStoreBackend::default(), dir: PathBuf::from(DEFAULT_KEY_DIR) }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    // Snapshot restored on startup if the file exists
+    pub snapshot: Option<PathBuf>,
+    // Journal every request that changes the book is appended to
+    pub journal: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarketConfig {
+    pub name: String,
+    // Whether the book is encrypted; if unset, it is whenever FHE keys can be loaded
+    pub encryption: Option<bool>,
+    pub matching_mode: MatchingMode,
+    pub disclosure_policy: DisclosurePolicy,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_MARKET.to_string(),
+            encryption: None,
+            matching_mode: MatchingMode::default(),
+            disclosure_policy: DisclosurePolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    // Tokens that may call the admin endpoints; without any, those endpoints are disabled
+    pub tokens: Vec<AdminToken>,
+    // File every admin request is appended to, besides the server log
+    pub audit_log: Option<PathBuf>,
+}
+
+/// Bearer token of an operator, and the name admin actions are audited under
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminToken {
+    pub name: String,
+    pub token: String,
+}
+
+// Keep tokens out of logs and error messages
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminToken").field("name", &self.name).field("token", &"<redacted>").finish()
+    }
+}
+
+/// Limits on what one client may send; each is off unless set
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    // Requests to any endpoint per client IP
+    pub per_ip: Option<RateLimit>,
+    // Order requests per account
+    pub per_account: Option<RateLimit>,
+    // Order requests per account while the book is encrypted, on top of `per_account`
+    pub encrypted_orders: Option<RateLimit>,
+    // Resting and stop orders an account may have at once
+    pub max_open_orders: Option<usize>,
+}
+
+impl ServerConfig {
+    /// Load the configuration file named by `ORDERBOOK_CONFIG`, or `orderbook.toml`
+    /// if it exists, apply environment overrides and validate the result
+    pub fn load() -> io::Result<Self> {
+        let mut config = match env::var(CONFIG_ENV) {
+            Ok(path) => Self::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(DEFAULT_CONFIG_FILE)?,
+            Err(_) => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Cannot read config file {}: {}", path.display(), e)))?;
+        let config = toml::from_str(&text)
+            .map_err(|e| io::Error::other(format!("Invalid config file {}: {}", path.display(), e)))?;
+        println!("Loaded configuration from {}", path.display());
+        Ok(config)
+    }
+
+    // Environment variables take precedence over the file
+    fn apply_env(&mut self) -> io::Result<()> {
+        if let Some(bind) = env_var(BIND_ENV) {
+            self.server.bind = bind.parse().map_err(|_| invalid_env(BIND_ENV, &bind, "expected an address such as 127.0.0.1:8080"))?;
+        }
+        if let Some(origins) = env_var(CORS_ORIGINS_ENV) {
+            self.server.cors_origins = origins.split(',').map(|origin| origin.trim().to_string()).collect();
+        }
+        match (env_var(TLS_CERT_ENV), env_var(TLS_KEY_ENV)) {
+            (Some(cert), Some(key)) => self.server.tls = Some(TlsConfig { cert: cert.into(), key: key.into() }),
+            (None, None) => {}
+            _ => {
+                return Err(io::Error::other(format!("{} and {} must be set together", TLS_CERT_ENV, TLS_KEY_ENV)));
+            }
+        }
+        if let Some(store) = env_var(KEY_STORE_ENV) {
+            self.keys.store = KeyStoreBackend::try_from(store.as_str()).map_err(|e| invalid_env(KEY_STORE_ENV, &store, &e))?;
+        }
+        if let Some(dir) = env_var(KEY_DIR_ENV) {
+            self.keys.dir = dir.into();
+        }
+        if let Some(snapshot) = env_var(SNAPSHOT_ENV) {
+            self.storage.snapshot = Some(snapshot.into());
+        }
+        if let Some(journal) = env_var(JOURNAL_ENV) {
+            self.storage.journal = Some(journal.into());
+        }
+        if let Some(market) = env_var(MARKET_ENV) {
+            self.market.name = market;
+        }
+        if let Some(encryption) = env_var(ENCRYPTION_ENV) {
+            self.market.encryption = Some(encryption.parse().map_err(|_| invalid_env(ENCRYPTION_ENV, &encryption, "expected true or false"))?);
+        }
+        if let Some(mode) = env_var(MATCHING_MODE_ENV) {
+            self.market.matching_mode = parse_matching_mode(&mode).ok_or_else(|| {
+                invalid_env(MATCHING_MODE_ENV, &mode, "expected continuous, oblivious or batch_auction:<interval_ms>")
+            })?;
+        }
+        if let Some(policy) = env_var(DISCLOSURE_POLICY_ENV) {
+            self.market.disclosure_policy = parse_disclosure_policy(&policy).ok_or_else(|| {
+                invalid_env(DISCLOSURE_POLICY_ENV, &policy, "expected hidden or aggregated:<batch_size>")
+            })?;
+        }
+        if let Some(token) = env_var(ADMIN_TOKEN_ENV) {
+            self.admin.tokens = vec![AdminToken { name: ENV_ADMIN_NAME.to_string(), token }];
+        }
+        if let Some(audit_log) = env_var(AUDIT_LOG_ENV) {
+            self.admin.audit_log = Some(audit_log.into());
+        }
+        for (name, limit) in [
+            (RATE_LIMIT_IP_ENV, &mut self.limits.per_ip),
+            (RATE_LIMIT_ACCOUNT_ENV, &mut self.limits.per_account),
+            (RATE_LIMIT_ENCRYPTED_ENV, &mut self.limits.encrypted_orders),
+        ] {
+            if let Some(value) = env_var(name) {
+                *limit = Some(parse_rate_limit(&value).ok_or_else(|| invalid_env(name, &value, "expected <per_second>:<burst>"))?);
+            }
+        }
+        if let Some(max_open_orders) = env_var(MAX_OPEN_ORDERS_ENV) {
+            self.limits.max_open_orders = Some(max_open_orders.parse().map_err(|_| {
+                invalid_env(MAX_OPEN_ORDERS_ENV, &max_open_orders, "expected a number of orders")
+            })?);
+        }
+        Ok(())
+    }
+
+    /// Check settings that parse but cannot work, reporting all of them at once
+    pub fn validate(&self) -> io::Result<()> {
+        let mut problems = Vec::new();
+
+        if self.server.cors_origins.is_empty() {
+            problems.push("server.cors_origins must list at least one origin, or \"*\"".to_string());
+        }
+        let any_origin = self.server.cors_origins.iter().any(|origin| origin == ANY_ORIGIN);
+        if any_origin && self.server.cors_origins.len() > 1 {
+            problems.push("server.cors_origins cannot mix \"*\" with specific origins".to_string());
+        }
+        for origin in self.server.cors_origins.iter().filter(|origin| *origin != ANY_ORIGIN) {
+            let scheme_ok = origin.starts_with("http://") || origin.starts_with("https://");
+            if !scheme_ok || origin.ends_with('/') || HeaderValue::from_str(origin).is_err() {
+                problems.push(format!(
+                    "server.cors_origins: '{}' is not an origin such as https://example.com",
+                    origin
+                ));
+            }
+        }
+        if let Some(tls) = &self.server.tls {
+            for (name, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+                if !path.is_file() {
+                    problems.push(format!("server.tls.{}: {} does not exist", name, path.display()));
+                }
+            }
+        }
+
+        if self.keys.dir.as_os_str().is_empty() && self.keys.store != KeyStoreBackend::Memory {
+            problems.push("keys.dir must not be empty".to_string());
+        }
+        if let Some(journal) = &self.storage.journal
+            && self.storage.snapshot.as_ref() == Some(journal)
+        {
+            problems.push("storage.snapshot and storage.journal must be different files".to_string());
+        }
+
+        if self.market.name.trim().is_empty() {
+            problems.push("market.name must not be empty".to_string());
+        }
+        if self.market.matching_mode == (MatchingMode::BatchAuction { interval_ms: 0 }) {
+            problems.push("market.matching_mode: the batch auction interval must be greater than zero".to_string());
+        }
+        if self.market.matching_mode == MatchingMode::Oblivious && self.market.encryption == Some(false) {
+            problems.push("market.matching_mode: oblivious matching requires encryption".to_string());
+        }
+        if self.market.disclosure_policy == (DisclosurePolicy::Aggregated { batch_size: 0 }) {
+            problems.push("market.disclosure_policy: the batch size must be greater than zero".to_string());
+        }
+
+        let mut names = HashSet::new();
+        let mut tokens = HashSet::new();
+        for admin in &self.admin.tokens {
+            if admin.name.trim().is_empty() {
+                problems.push("admin.tokens: every token needs a name".to_string());
+            } else if !names.insert(&admin.name) {
+                problems.push(format!("admin.tokens: the name '{}' is used more than once", admin.name));
+            }
+            if admin.token.len() < MIN_ADMIN_TOKEN_LEN {
+                problems.push(format!(
+                    "admin.tokens: the token of '{}' must be at least {} characters",
+                    admin.name, MIN_ADMIN_TOKEN_LEN
+                ));
+            } else if !tokens.insert(&admin.token) {
+                problems.push(format!("admin.tokens: the token of '{}' is already used by another entry", admin.name));
+            }
+        }
+        if let Some(audit_log) = &self.admin.audit_log
+            && [&self.storage.snapshot, &self.storage.journal].iter().any(|path| path.as_ref() == Some(audit_log))
+        {
+            problems.push("admin.audit_log must not be the snapshot or journal file".to_string());
+        }
+
+        for (name, limit) in [
+            ("per_ip", &self.limits.per_ip),
+            ("per_account", &self.limits.per_account),
+            ("encrypted_orders", &self.limits.encrypted_orders),
+        ] {
+            if let Some(limit) = limit
+                && !limit.is_valid()
+            {
+                problems.push(format!(
+                    "limits.{}: per_second and burst must be greater than zero",
+                    name
+                ));
+            }
+        }
+        if self.limits.max_open_orders == Some(0) {
+            problems.push("limits.max_open_orders must be greater than zero".to_string());
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(io::Error::other(format!("Invalid configuration:\n  - {}", problems.join("\n  - "))))
+    }
+
+    /// Whether browsers may call the API from any origin
+    pub fn allows_any_origin(&self) -> bool {
+        self.server.cors_origins.iter().any(|origin| origin == ANY_ORIGIN)
+    }
+}
+
+// An environment variable, treating an empty value as unset
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn invalid_env(name: &str, value: &str, expected: &str) -> io::Error {
+    io::Error::other(format!("Invalid {} '{}': {}", name, value, expected))
+}
+
+fn parse_matching_mode(value: &str) -> Option<MatchingMode> {
+    match value.split_once(':') {
+        None if value == "continuous" => Some(MatchingMode::Continuous),
+        None if value == "oblivious" => Some(MatchingMode::Oblivious),
+        Some(("batch_auction", interval_ms)) => {
+            interval_ms.parse().ok().map(|interval_ms| MatchingMode::BatchAuction { interval_ms })
+        }
+        _ => None,
+    }
+}
+
+fn parse_rate_limit(value: &str) -> Option<RateLimit> {
+    let (per_second, burst) = value.split_once(':')?;
+    Some(RateLimit { per_second: per_second.parse().ok()?, burst: burst.parse().ok()? })
+}
+
+fn parse_disclosure_policy(value: &str) -> Option<DisclosurePolicy> {
+    match value.split_once(':') {
+        None if value == "hidden" => Some(DisclosurePolicy::Hidden),
+        Some(("aggregated", batch_size)) => {
+            batch_size.parse().ok().map(|batch_size| DisclosurePolicy::Aggregated { batch_size })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> ServerConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let config = parse("");
+
+        assert_eq!(config.server.bind, SocketAddr::from(([127, 0, 0, 1], 8080)));
+        assert!(config.allows_any_origin());
+        assert_eq!(config.market.name, DEFAULT_MARKET);
+        assert_eq!(config.market.matching_mode, MatchingMode::Continuous);
+        assert!(config.limits.per_account.is_none());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn files_set_each_section() {
+        let config = parse(
+            r#"
+            [server]
+            bind = "0.0.0.0:8443"
+            cors_origins = ["https://trade.example.com"]
+
+            [market]
+            name = "SOL-USDC"
+            encryption = true
+            matching_mode = { mode = "batch_auction", interval_ms = 1000 }
+
+            [limits]
+            per_account = { per_second = 5.0, burst = 10 }
+            max_open_orders = 100
+            "#,
+        );
+
+        assert_eq!(config.server.bind.port(), 8443);
+        assert!(!config.allows_any_origin());
+        assert_eq!(config.market.name, "SOL-USDC");
+        assert_eq!(config.market.encryption, Some(true));
+        assert_eq!(config.market.matching_mode, MatchingMode::BatchAuction { interval_ms: 1000 });
+        assert_eq!(config.limits.per_account, Some(RateLimit { per_second: 5.0, burst: 10 }));
+        assert_eq!(config.limits.max_open_orders, Some(100));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        assert!(toml::from_str::<ServerConfig>("[server]\nport = 8080").is_err());
+        assert!(toml::from_str::<ServerConfig>("[markets]\nname = \"SOL-USDC\"").is_err());
+    }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let config = parse(
+            r#"
+            [server]
+            cors_origins = ["*", "trade.example.com/"]
+
+            [market]
+            encryption = false
+            matching_mode = { mode = "oblivious" }
+
+            [[admin.tokens]]
+            name = "ops"
+            token = "short"
+
+            [limits]
+            max_open_orders = 0
+            "#,
+        );
+
+        let message = config.validate().unwrap_err().to_string();
+
+        for problem in [
+            "cannot mix \"*\"",
+            "'trade.example.com/' is not an origin",
+            "oblivious matching requires encryption",
+            "the token of 'ops' must be at least",
+            "limits.max_open_orders",
+        ] {
+            assert!(message.contains(problem), "{} not in {}", problem, message);
+        }
+    }
+
+    #[test]
+    fn environment_values_parse() {
+        assert_eq!(parse_matching_mode("oblivious"), Some(MatchingMode::Oblivious));
+        assert_eq!(parse_matching_mode("batch_auction:250"), Some(MatchingMode::BatchAuction { interval_ms: 250 }));
+        assert_eq!(parse_matching_mode("batch_auction"), None);
+        assert_eq!(parse_rate_limit("0.5:4"), Some(RateLimit { per_second: 0.5, burst: 4 }));
+        assert_eq!(parse_rate_limit("5"), None);
+        assert_eq!(parse_disclosure_policy("aggregated:20"), Some(DisclosurePolicy::Aggregated { batch_size: 20 }));
+        assert_eq!(parse_disclosure_policy("aggregated"), None);
+    }
+}
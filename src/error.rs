@@ -0,0 +1,216 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::api::types::ErrorResponse;
+use crate::utils::ciphertext::MALFORMED_CIPHERTEXT;
+use crate::utils::orders::{OrderStatus, Side};
+
+/// Stable, machine-readable code of a failed request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    OrderRejected,
+    OrderNotFound,
+    OrderClosed,
+    Conflict,
+    NoLiquidity,
+    KeyUnavailable,
+    MalformedCiphertext,
+    Unauthorized,
+    Forbidden,
+    RateLimited,
+    #[serde(rename = "internal_error")]
+    Internal,
+}
+
+/// Errors returned by the orderbook and its API. Every variant has a stable,
+/// machine-readable code and always maps to the same HTTP status.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderbookError {
+    // The request is malformed or fails validation
+    InvalidRequest(String),
+    // The orderbook rejected the order, e.g. an empty quantity
+    OrderRejected(u128),
+    OrderNotFound(u128),
+    // The order has already left the book
+    OrderClosed { id: u128, status: OrderStatus },
+    // The request conflicts with the current state of the orderbook
+    Conflict(String),
+    // A market order found nothing on the opposite side to trade against
+    NoLiquidity(Side),
+    // FHE keys are missing or could not be loaded
+    KeyUnavailable(String),
+    // A ciphertext could not be decoded or does not match the parameters of its key version
+    MalformedCiphertext(String),
+    // An admin endpoint was called without a valid admin token
+    Unauthorized(String),
+    // Admin endpoints are disabled, since no admin token is configured
+    Forbidden(String),
+    // A rate limit or the open order limit was reached; retry after the given time, if any
+    RateLimited { message: String, retry_after: Option<Duration> },
+    Internal(String),
+}
+
+impl OrderbookError {
+    /// Stable code clients can match on instead of parsing messages
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            OrderbookError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            OrderbookError::OrderRejected(_) => ErrorCode::OrderRejected,
+            OrderbookError::OrderNotFound(_) => ErrorCode::OrderNotFound,
+            OrderbookError::OrderClosed { .. } => ErrorCode::OrderClosed,
+            OrderbookError::Conflict(_) => ErrorCode::Conflict,
+            OrderbookError::NoLiquidity(_) => ErrorCode::NoLiquidity,
+            OrderbookError::KeyUnavailable(_) => ErrorCode::KeyUnavailable,
+            OrderbookError::MalformedCiphertext(_) => ErrorCode::MalformedCiphertext,
+            OrderbookError::Unauthorized(_) => ErrorCode::Unauthorized,
+            OrderbookError::Forbidden(_) => ErrorCode::Forbidden,
+            OrderbookError::RateLimited { .. } => ErrorCode::RateLimited,
+            OrderbookError::Internal(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            OrderbookError::InvalidRequest(_)
+            | OrderbookError::OrderRejected(_)
+            | OrderbookError::NoLiquidity(_)
+            | OrderbookError::MalformedCiphertext(_) => StatusCode::BAD_REQUEST,
+            OrderbookError::OrderNotFound(_) => StatusCode::NOT_FOUND,
+            OrderbookError::OrderClosed { .. } | OrderbookError::Conflict(_) => StatusCode::CONFLICT,
+            OrderbookError::KeyUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            OrderbookError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            OrderbookError::Forbidden(_) => StatusCode::FORBIDDEN,
+            OrderbookError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            OrderbookError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// How long a throttled client should wait before retrying
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            OrderbookError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OrderbookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderbookError::InvalidRequest(message)
+            | OrderbookError::Conflict(message)
+            | OrderbookError::KeyUnavailable(message)
+            | OrderbookError::MalformedCiphertext(message)
+            | OrderbookError::Unauthorized(message)
+            | OrderbookError::Forbidden(message)
+            | OrderbookError::RateLimited { message, .. }
+            | OrderbookError::Internal(message) => write!(f, "{}", message),
+            OrderbookError::OrderRejected(id) => write!(f, "Order {} was rejected", id),
+            OrderbookError::OrderNotFound(id) => write!(f, "Order {} not found", id),
+            OrderbookError::OrderClosed { id, status } => write!(f, "Order {} is already {:?}", id, status),
+            OrderbookError::NoLiquidity(Side::Buy) => write!(f, "No matching sell orders available"),
+            OrderbookError::NoLiquidity(Side::Sell) => write!(f, "No matching buy orders available"),
+        }
+    }
+}
+
+impl std::error::Error for OrderbookError {}
+
+// Requests the extractors could not parse, such as bodies with a malformed ciphertext
+impl From<JsonRejection> for OrderbookError {
+    fn from(rejection: JsonRejection) -> Self {
+        let message = rejection.body_text();
+        if message.contains(MALFORMED_CIPHERTEXT) {
+            OrderbookError::MalformedCiphertext(message)
+        } else {
+            OrderbookError::InvalidRequest(message)
+        }
+    }
+}
+
+impl From<QueryRejection> for OrderbookError {
+    fn from(rejection: QueryRejection) -> Self {
+        OrderbookError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for OrderbookError {
+    fn from(rejection: PathRejection) -> Self {
+        OrderbookError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for OrderbookError {
+    fn into_response(self) -> Response {
+        let retry_after = self.retry_after();
+        let body = Json(ErrorResponse {
+            success: false,
+            code: self.code(),
+            error: self.to_string(),
+            retry_after_ms: retry_after.map(|wait| wait.as_millis() as u64),
+        });
+        let mut response = (self.status(), body).into_response();
+        if let Some(wait) = retry_after {
+            // Whole seconds, rounded up so a client retrying on time is admitted
+            let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_error_keeps_its_code_and_status() {
+        let cases = [
+            (OrderbookError::InvalidRequest("bad".to_string()), "invalid_request", StatusCode::BAD_REQUEST),
+            (OrderbookError::OrderNotFound(1), "order_not_found", StatusCode::NOT_FOUND),
+            (OrderbookError::OrderClosed { id: 1, status: OrderStatus::Filled }, "order_closed", StatusCode::CONFLICT),
+            (OrderbookError::NoLiquidity(Side::Buy), "no_liquidity", StatusCode::BAD_REQUEST),
+            (OrderbookError::KeyUnavailable("no keys".to_string()), "key_unavailable", StatusCode::SERVICE_UNAVAILABLE),
+            (OrderbookError::MalformedCiphertext("bad".to_string()), "malformed_ciphertext", StatusCode::BAD_REQUEST),
+            (OrderbookError::Unauthorized("who".to_string()), "unauthorized", StatusCode::UNAUTHORIZED),
+            (OrderbookError::Internal("oops".to_string()), "internal_error", StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+
+        for (error, code, status) in cases {
+            assert_eq!(serde_json::to_value(error.code()).unwrap(), code);
+            assert_eq!(error.status(), status);
+        }
+    }
+
+    #[test]
+    fn throttled_responses_round_the_retry_hint_up() {
+        let error = OrderbookError::RateLimited {
+            message: "Too many orders".to_string(),
+            retry_after: Some(Duration::from_millis(1_500)),
+        };
+
+        let response = error.into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+    }
+
+    #[test]
+    fn refusals_without_a_retry_hint_send_no_header() {
+        let error = OrderbookError::RateLimited { message: "Too many open orders".to_string(), retry_after: None };
+
+        let response = error.into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+}
@@ -0,0 +1,290 @@
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::api::types::{MarketOrderRequest, OrderRequest, PeggedOrderRequest, StopOrderRequest};
+use crate::error::OrderbookError;
+use crate::utils::ciphertext::Ciphertext;
+use crate::utils::fhe_operations;
+use crate::utils::orderbook::Orderbook;
+use crate::utils::orders::{Order, Side};
+use crate::utils::pegged::Peg;
+
+// File the server appends every request that changed the book to, if set
+pub const JOURNAL_ENV: &str = "ORDERBOOK_JOURNAL";
+
+static JOURNAL: OnceCell<Mutex<File>> = OnceCell::new();
+
+/// A request that changes the book, in the form the API accepted it. Only order
+/// requests and cancellations are journaled: batch auctions, encrypted clearing,
+/// the closing of exhausted oblivious orders, resets, configuration changes and
+/// key rotations are not, and expiry follows
+/// the clock of whoever applies the entries. Replaying a journal in order against
+/// the book it started from therefore rebuilds the same order ids and fills only
+/// while none of those happened in between.
+// Adjacently tagged, since internally tagged enums cannot hold u128 order ids
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "request", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Limit(OrderRequest),
+    Stop(StopOrderRequest),
+    Pegged(PeggedOrderRequest),
+    MarketBuy(MarketOrderRequest),
+    MarketSell(MarketOrderRequest),
+    Cancel { id: u128 },
+}
+
+fn parse_side(side: &str) -> Result<Side, OrderbookError> {
+    match side.to_lowercase().as_str() {
+        "buy" => Ok(Side::Buy),
+        "sell" => Ok(Side::Sell),
+        _ => Err(OrderbookError::InvalidRequest("Invalid side: must be 'buy' or 'sell'".to_string())),
+    }
+}
+
+// Price and quantity of a limit order, sent either in plaintext or both encrypted.
+// Encrypted values are checked against their key version and decrypted, since
+// fees and fills are computed on plaintext.
+fn limit_values(orderbook: &Orderbook, req: &OrderRequest) -> Result<(u32, u32), OrderbookError> {
+    match (req.price, req.quantity, &req.encrypted_price, &req.encrypted_quantity) {
+        (Some(price), Some(quantity), None, None) => Ok((price, quantity)),
+        (None, None, Some(encrypted_price), Some(encrypted_quantity)) => {
+            if !orderbook.is_using_encryption() {
+                return Err(OrderbookError::Conflict(
+                    "Encrypted orders are not accepted while encryption is disabled".to_string()
+                ));
+            }
+            fhe_operations::verify_ciphertext(encrypted_price)?;
+            fhe_operations::verify_ciphertext(encrypted_quantity)?;
+            Ok((fhe_operations::decrypt_u32(encrypted_price)?, fhe_operations::decrypt_u32(encrypted_quantity)?))
+        }
+        _ => Err(OrderbookError::InvalidRequest(
+            "Send either price and quantity, or encrypted_price and encrypted_quantity".to_string()
+        )),
+    }
+}
+
+// Trigger price of a stop order. While the book is encrypted the trigger is kept
+// only as a ciphertext under the active key, so its plaintext never reaches the
+// book or the journal. A zero trigger stays plaintext for the book to reject.
+fn stop_trigger(orderbook: &Orderbook, req: &StopOrderRequest) -> Result<(Option<u32>, Option<Ciphertext>), OrderbookError> {
+    match (req.trigger_price, &req.encrypted_trigger_price) {
+        (Some(trigger_price), None) if trigger_price > 0 && orderbook.is_using_encryption() => {
+            let client_key = fhe_operations::client_key()?;
+            Ok((None, Some(fhe_operations::encrypt_u32(trigger_price, &client_key)?)))
+        }
+        (Some(trigger_price), None) => Ok((Some(trigger_price), None)),
+        (None, Some(encrypted_trigger_price)) => {
+            if !orderbook.is_using_encryption() {
+                return Err(OrderbookError::Conflict(
+                    "Encrypted stop orders are not accepted while encryption is disabled".to_string()
+                ));
+            }
+            fhe_operations::verify_ciphertext(encrypted_trigger_price)?;
+            let client_key = fhe_operations::client_key()?;
+            Ok((None, Some(fhe_operations::reencrypt(encrypted_trigger_price, &client_key)?)))
+        }
+        _ => Err(OrderbookError::InvalidRequest(
+            "Send either trigger_price or encrypted_trigger_price".to_string()
+        )),
+    }
+}
+
+impl JournalEntry {
+    /// Account that sent the request; cancellations name only the order
+    pub fn user_pubkey(&self) -> Option<&str> {
+        match self {
+            JournalEntry::Limit(req) => Some(&req.user_pubkey),
+            JournalEntry::Stop(req) => Some(&req.user_pubkey),
+            JournalEntry::Pegged(req) => Some(&req.user_pubkey),
+            JournalEntry::MarketBuy(req) | JournalEntry::MarketSell(req) => Some(&req.user_pubkey),
+            JournalEntry::Cancel { .. } => None,
+        }
+    }
+
+    /// Apply the request to the book. Returns the order it placed or cancelled;
+    /// a request that fails leaves the book unchanged.
+    pub fn apply(&self, orderbook: &mut Orderbook) -> Result<Order, OrderbookError> {
+        match self {
+            JournalEntry::Limit(req) => {
+                let side = parse_side(&req.side)?;
+                let (price, quantity) = limit_values(orderbook, req)?;
+                orderbook.count += 1;
+                let id = orderbook.count;
+
+                let mut order = match req.display_quantity {
+                    Some(display_quantity) => Order::new_iceberg(
+                        id,
+                        price,
+                        quantity,
+                        display_quantity,
+                        side,
+                        req.user_pubkey.clone()
+                    ),
+                    None => Order::new(id, price, quantity, side, req.user_pubkey.clone()),
+                };
+                order.expires_at = req.expires_at;
+                if let (Some(encrypted_price), Some(encrypted_quantity)) = (&req.encrypted_price, &req.encrypted_quantity) {
+                    // Keep the client's ciphertexts rather than encrypting the values again
+                    let client_key = fhe_operations::client_key()?;
+                    fhe_operations::encrypt_order_from(&mut order, encrypted_price, encrypted_quantity, &client_key)?;
+                    order.is_encrypted = true;
+                }
+                Ok(orderbook.add_order(order))
+            }
+            JournalEntry::Stop(req) => {
+                let side = parse_side(&req.side)?;
+                let (trigger_price, encrypted_trigger_price) = stop_trigger(orderbook, req)?;
+                orderbook.count += 1;
+                let id = orderbook.count;
+
+                let mut order = Order::new_stop(
+                    id,
+                    0,
+                    req.limit_price,
+                    req.quantity,
+                    side,
+                    req.user_pubkey.clone()
+                );
+                order.trigger_price = trigger_price;
+                order.encrypted_trigger_price = encrypted_trigger_price;
+                order.expires_at = req.expires_at;
+                Ok(orderbook.add_order(order))
+            }
+            JournalEntry::Pegged(req) => {
+                let side = parse_side(&req.side)?;
+                let peg = Peg {
+                    reference: req.reference,
+                    offset: req.offset,
+                };
+                peg.validate()?;
+                if orderbook.is_using_encryption() {
+                    return Err(OrderbookError::Conflict(
+                        "Pegged orders are not supported while encryption is enabled".to_string()
+                    ));
+                }
+
+                orderbook.count += 1;
+                let id = orderbook.count;
+
+                let mut order = Order::new_pegged(id, peg, req.quantity, side, req.user_pubkey.clone());
+                order.expires_at = req.expires_at;
+                Ok(orderbook.add_order(order))
+            }
+            JournalEntry::MarketBuy(req) => orderbook
+                .market_buy(req.quantity, req.user_pubkey.clone())
+                .ok_or(OrderbookError::NoLiquidity(Side::Buy)),
+            JournalEntry::MarketSell(req) => orderbook
+                .market_sell(req.quantity, req.user_pubkey.clone())
+                .ok_or(OrderbookError::NoLiquidity(Side::Sell)),
+            JournalEntry::Cancel { id } => orderbook.cancel_order(*id),
+        }
+    }
+
+    // The entry as it is written to the journal: a trigger price the book keeps
+    // encrypted is journaled as the ciphertext of the order it placed
+    fn journaled(mut self, order: &Order) -> Self {
+        if let JournalEntry::Stop(req) = &mut self
+            && let Some(encrypted_trigger_price) = &order.encrypted_trigger_price
+        {
+            req.trigger_price = None;
+            req.encrypted_trigger_price = Some(encrypted_trigger_price.clone());
+        }
+        self
+    }
+}
+
+/// Open the journal named by `ORDERBOOK_JOURNAL` for appending, if it is set
+pub fn open_from_env() -> io::Result<()> {
+    match env::var(JOURNAL_ENV) {
+        Ok(path) => open(path),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Open `path` as the journal, appending to it
+pub fn open(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = JOURNAL.set(Mutex::new(file));
+    println!("Journaling order requests to {}", path.display());
+    Ok(())
+}
+
+/// Apply `entry` to the book and append it to the journal if it changed the book.
+/// Must run on the sequencer thread so the journal keeps the order requests were applied in.
+pub fn submit(orderbook: &mut Orderbook, entry: JournalEntry) -> Result<Order, OrderbookError> {
+    let order = entry.apply(orderbook)?;
+    if let Some(journal) = JOURNAL.get() {
+        let mut line = serde_json::to_vec(&entry.journaled(&order)).map_err(|e| OrderbookError::Internal(e.to_string()))?;
+        line.push(b'\n');
+        let mut file = journal.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = file.write_all(&line) {
+            eprintln!("Failed to write to the order journal: {}", e);
+        }
+    }
+    Ok(order)
+}
+
+/// Read a journal written by the server, one JSON entry per line
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<JournalEntry>> {
+    fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| io::Error::other(format!("Invalid journal entry on line {}: {}", number + 1, e)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fhe_operations::test_keys;
+
+    fn stop_request(trigger_price: u32) -> StopOrderRequest {
+        StopOrderRequest {
+            trigger_price: Some(trigger_price),
+            encrypted_trigger_price: None,
+            limit_price: None,
+            quantity: 5,
+            side: "sell".to_string(),
+            user_pubkey: "alice".to_string(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn encrypted_books_journal_only_the_trigger_ciphertext() {
+        let _keys = test_keys();
+        let mut book = Orderbook::new(Some((*fhe_operations::get_server_key().unwrap()).clone()));
+
+        let entry = JournalEntry::Stop(stop_request(95));
+        let order = entry.apply(&mut book).unwrap();
+        let line = serde_json::to_value(entry.journaled(&order)).unwrap();
+
+        assert_eq!(order.trigger_price, None);
+        assert!(line["request"].get("trigger_price").is_none());
+        assert!(line["request"]["encrypted_trigger_price"].is_object());
+        assert_eq!(book.get_stop_orders()[0].trigger_price, None);
+        assert_eq!(book.get_decrypted_order(order.id).unwrap().trigger_price, None);
+    }
+
+    #[test]
+    fn plaintext_books_journal_the_request_as_sent() {
+        let mut book = Orderbook::new(None);
+
+        let entry = JournalEntry::Stop(stop_request(95));
+        let order = entry.apply(&mut book).unwrap();
+        let line = serde_json::to_value(entry.journaled(&order)).unwrap();
+
+        assert_eq!(line["request"]["trigger_price"], 95);
+        assert_eq!(book.get_stop_orders()[0].trigger_price, Some(95));
+    }
+}
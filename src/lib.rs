@@ -0,0 +1,15 @@
+//! Encrypted orderbook matching engine. The HTTP server, the admin tool and
+//! the client SDK are all built on this library.
+
+pub mod api;
+pub mod auth;
+pub mod cli;
+pub mod config;
+pub mod error;
+pub mod journal;
+pub mod rate_limit;
+pub mod sequencer;
+pub mod utils;
+
+/// State shared by the API handlers: a handle to the sequencer that owns the book
+pub type AppState = sequencer::Sequencer;
@@ -1,80 +1,188 @@
 use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
     routing::{get, post},
-    extract::{State, Json},
     Router,
     http::{HeaderValue, Method},
 };
-use tower_http::cors::{CorsLayer, Any};
+use axum_server::tls_rustls::RustlsConfig;
+use tower_http::cors::{AllowOrigin, CorsLayer, Any};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use serde::{Deserialize, Serialize};
-use axum::response::IntoResponse;
-mod utils;
-use utils::orderbook::Orderbook;
-use utils::orders::{Order, Side};
-use utils::fhe_operations;
-mod api;
-use api::orders::{get_orders, add_order, market_buy, market_sell, get_fills, generate_keys};
-use api::config::{get_config, update_config};
-use api::reset::reset_orderbook;
-type AppState = Arc<Mutex<Orderbook>>;
+use std::sync::Arc;
+use fhe_orderbook::auth::{require_admin, AdminAuth};
+use fhe_orderbook::rate_limit::{self, limit_by_ip};
+use fhe_orderbook::config::ServerConfig;
+use fhe_orderbook::journal;
+use fhe_orderbook::sequencer::Sequencer;
+use fhe_orderbook::utils::orderbook::Orderbook;
+use fhe_orderbook::utils::fhe_operations;
+use fhe_orderbook::utils::generate_key;
+use fhe_orderbook::utils::key_store;
+use fhe_orderbook::api::orders::{get_orders, get_order, add_order, cancel_order, get_stop_orders, add_stop_order, add_pegged_order, market_buy, market_sell, get_fills, get_encrypted_fills};
+use fhe_orderbook::api::keys::{get_keys, get_public_key, generate_keys};
+use fhe_orderbook::api::config::{get_config, update_config};
+use fhe_orderbook::api::fees::{get_fees, get_fee_schedule, update_fee_schedule};
+use fhe_orderbook::api::market_data::{get_candles, get_stats, disclose_trades};
+use fhe_orderbook::api::auction::{run_auction, run_encrypted_clearing, auction_loop, compaction_loop};
+use fhe_orderbook::api::reset::reset_orderbook;
+use fhe_orderbook::api::snapshot::get_snapshot;
+use fhe_orderbook::utils::snapshot;
+
+// Orders the client encrypted carry two ciphertexts of about a megabyte each as JSON
+const ENCRYPTED_ORDER_BODY_LIMIT: usize = 8 * 1024 * 1024;
 
 #[tokio::main]
 async fn main() {
-    // Initialize FHE system if keys exist
-    if let Err(e) = fhe_operations::init_fhe() {
-        eprintln!("Warning: Failed to initialize FHE system: {}", e);
-        eprintln!("You can generate keys using the /generate-keys endpoint.");
+    let config = ServerConfig::load().unwrap_or_else(|e| exit_with(&e.to_string()));
+    if let Err(e) = key_store::build(config.keys.store, &config.keys.dir).and_then(generate_key::set_key_store) {
+        exit_with(&format!("Failed to open the FHE key store: {}", e));
     }
 
-    // Create an encrypted orderbook by default if FHE keys exist
-    let orderbook = if utils::generate_key::keys_exist() {
+    let use_encryption = match config.market.encryption {
+        Some(false) => false,
+        Some(true) => {
+            if let Err(e) = fhe_operations::init_fhe() {
+                exit_with(&format!("Encryption is required but the FHE keys could not be loaded: {}", e));
+            }
+            true
+        }
+        // Encrypt whenever keys exist or can be generated
+        None => {
+            if let Err(e) = fhe_operations::init_fhe() {
+                eprintln!("Warning: Failed to initialize FHE system: {}", e);
+                eprintln!("You can generate keys using the /generate-keys endpoint.");
+            }
+            generate_key::keys_exist()
+        }
+    };
+
+    let mut orderbook = if use_encryption {
         println!("Using encrypted orderbook with FHE");
         Orderbook::new_encrypted()
     } else {
-        println!("Using plaintext orderbook (FHE keys not found)");
+        println!("Using plaintext orderbook");
         Orderbook::new(None)
     };
+    orderbook.market = config.market.name.clone();
+    orderbook.market_data.disclosure_policy = config.market.disclosure_policy;
+    if let Err(e) = orderbook.set_matching_mode(config.market.matching_mode) {
+        exit_with(&format!("Invalid matching mode: {}", e));
+    }
+
+    // A snapshot keeps the settings it was taken with, which may have been changed through /config
+    if let Some(path) = &config.storage.snapshot
+        && path.exists()
+    {
+        let restored = snapshot::load(path).map_err(|e| e.to_string()).and_then(|snapshot| {
+            if snapshot.market != config.market.name {
+                return Err(format!("it is of market '{}', not '{}'", snapshot.market, config.market.name));
+            }
+            orderbook.restore(snapshot).map_err(|e| e.to_string())
+        });
+        match restored {
+            Ok(reencrypted) => println!("Restored the orderbook from {} ({} orders re-encrypted)", path.display(), reencrypted),
+            Err(e) => exit_with(&format!("Failed to restore the orderbook from {}: {}", path.display(), e)),
+        }
+    }
+
+    if let Some(path) = &config.storage.journal
+        && let Err(e) = journal::open(path)
+    {
+        exit_with(&format!("Failed to open the order journal: {}", e));
+    }
+
+    let admin_auth = AdminAuth::new(&config.admin)
+        .unwrap_or_else(|e| exit_with(&format!("Failed to open the audit log: {}", e)));
+    let admin = middleware::from_fn_with_state(Arc::new(admin_auth), require_admin);
+
+    rate_limit::configure(&config.limits);
+
+    // The sequencer thread owns the orderbook; handlers send it commands
+    let app_state = Sequencer::spawn(orderbook);
 
-    let app_state = Arc::new(Mutex::new(orderbook));
+    // Clear batches in the background whenever batch auction mode is enabled
+    tokio::spawn(auction_loop(app_state.clone()));
+    tokio::spawn(compaction_loop(app_state.clone()));
 
     // Set up CORS
+    let origins = if config.allows_any_origin() {
+        AllowOrigin::any()
+    } else {
+        // Validated with the rest of the configuration
+        AllowOrigin::list(config.server.cors_origins.iter().filter_map(|origin| HeaderValue::from_str(origin).ok()))
+    };
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
         .allow_headers(Any);
 
+    // Operator endpoints, marked with the `admin` layer, require an admin token
     let app = Router::new()
         // Order management
         .route("/orders", get(get_orders))
-        .route("/orders", post(add_order))
+        .route("/orders", post(add_order).layer(DefaultBodyLimit::max(ENCRYPTED_ORDER_BODY_LIMIT)))
+        .route("/orders/:id", get(get_order).delete(cancel_order))
+        .route("/stop-orders", get(get_stop_orders))
+        .route("/stop-orders", post(add_stop_order))
+        .route("/pegged-orders", post(add_pegged_order))
         .route("/market-buy", post(market_buy))
         .route("/market-sell", post(market_sell))
         .route("/fills", get(get_fills))
+        .route("/fills/encrypted", get(get_encrypted_fills))
+        
+        // Fees
+        .route("/fees", get(get_fees))
+        .route("/fees/schedule", get(get_fee_schedule))
+        .route("/fees/schedule", post(update_fee_schedule).route_layer(admin.clone()))
+        
+        // Batch auctions
+        .route("/auction/run", post(run_auction).route_layer(admin.clone()))
+        .route("/auction/encrypted", post(run_encrypted_clearing).route_layer(admin.clone()))
+        
+        // Market data
+        .route("/market-data/candles", get(get_candles))
+        .route("/market-data/stats", get(get_stats))
+        .route("/market-data/disclose", post(disclose_trades).route_layer(admin.clone()))
         
         // FHE key management
-        .route("/generate-keys", post(generate_keys))
+        .route("/keys", get(get_keys))
+        .route("/keys/:id/public", get(get_public_key))
+        .route("/generate-keys", post(generate_keys).route_layer(admin.clone()))
+        
+        // Persistence
+        .route("/snapshot", get(get_snapshot).route_layer(admin.clone()))
         
         // Configuration
         .route("/config", get(get_config))
-        .route("/config", post(update_config))
+        .route("/config", post(update_config).route_layer(admin.clone()))
         
         // Reset
-        .route("/reset", post(reset_orderbook))
+        .route("/reset", post(reset_orderbook).route_layer(admin))
         
         .with_state(app_state)
+        .layer(middleware::from_fn(limit_by_ip))
         .layer(cors);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    println!("Server listening on {}", addr);
-    
-    // Bind to the address
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    
-    // Run the server
-    axum::Server::from_tcp(listener.into_std().unwrap())
-        .unwrap()
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let addr = config.server.bind;
+    let served = match &config.server.tls {
+        Some(tls) => {
+            let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+                .await
+                .unwrap_or_else(|e| exit_with(&format!("Failed to load the TLS certificate and key: {}", e)));
+            println!("Server listening on https://{}", addr);
+            axum_server::bind_rustls(addr, rustls).serve(app.into_make_service_with_connect_info::<SocketAddr>()).await
+        }
+        None => {
+            println!("Server listening on http://{}", addr);
+            axum_server::bind(addr).serve(app.into_make_service_with_connect_info::<SocketAddr>()).await
+        }
+    };
+    if let Err(e) = served {
+        exit_with(&format!("Server failed on {}: {}", addr, e));
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
@@ -0,0 +1,239 @@
+//! Rate limits that keep one client from stalling the server. Every request
+//! counts against its client IP, and order requests against their account
+//! (`user_pubkey`) too. Orders on an encrypted book each cost many FHE
+//! comparisons on the sequencer thread, so they also draw on a separate budget.
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::api::auction::COMPACTION_INTERVAL;
+use crate::config::LimitsConfig;
+use crate::error::OrderbookError;
+use crate::journal::JournalEntry;
+use crate::utils::auction::MatchingMode;
+use crate::utils::orderbook::Orderbook;
+
+// Clients tracked per limit before those with a full budget are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+static LIMITER: OnceCell<RateLimiter> = OnceCell::new();
+
+/// A token bucket: `burst` requests at once, refilled at `per_second`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn is_valid(&self) -> bool {
+        self.per_second.is_finite() && self.per_second > 0.0 && self.burst > 0
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// A bucket per client, created full the first time the client is seen
+struct Buckets<K> {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Hash + Eq> Buckets<K> {
+    fn new(limit: RateLimit) -> Self {
+        Self { limit, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    // Tokens in the bucket at `now`, after refilling since its last update
+    fn level(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.limit.per_second;
+        (bucket.tokens + refill).min(f64::from(self.limit.burst))
+    }
+
+    // Ok if a bucket holding `tokens` can spare one, or else how long until it can
+    fn spare(&self, tokens: f64) -> Result<(), Duration> {
+        if tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - tokens) / self.limit.per_second));
+        }
+        Ok(())
+    }
+
+    // Check that `key` has a token, without taking it
+    fn check(&self, key: &K) -> Result<(), Duration> {
+        let now = Instant::now();
+        let buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match buckets.get(key) {
+            Some(bucket) => self.spare(self.level(bucket, now)),
+            None => Ok(()),
+        }
+    }
+
+    // Take a token for `key`, or return how long until one is available
+    fn take(&self, key: K) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&key) {
+            let burst = f64::from(self.limit.burst);
+            buckets.retain(|_, bucket| self.level(bucket, now) < burst);
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket { tokens: f64::from(self.limit.burst), updated: now });
+        let tokens = self.level(bucket, now);
+        self.spare(tokens)?;
+        *bucket = Bucket { tokens: tokens - 1.0, updated: now };
+        Ok(())
+    }
+}
+
+struct RateLimiter {
+    per_ip: Option<Buckets<IpAddr>>,
+    per_account: Option<Buckets<String>>,
+    encrypted_orders: Option<Buckets<String>>,
+    max_open_orders: Option<usize>,
+}
+
+/// Enforce the configured limits. Without this, nothing is limited.
+pub fn configure(config: &LimitsConfig) {
+    let limiter = RateLimiter {
+        per_ip: config.per_ip.map(Buckets::new),
+        per_account: config.per_account.map(Buckets::new),
+        encrypted_orders: config.encrypted_orders.map(Buckets::new),
+        max_open_orders: config.max_open_orders,
+    };
+    let _ = LIMITER.set(limiter);
+}
+
+fn throttled(message: String, retry_after: Duration) -> OrderbookError {
+    OrderbookError::RateLimited { message, retry_after: Some(retry_after) }
+}
+
+/// Middleware counting every request against the budget of its client IP
+pub async fn limit_by_ip(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if let Some(per_ip) = LIMITER.get().and_then(|limiter| limiter.per_ip.as_ref())
+        && let Err(wait) = per_ip.take(remote_addr.ip())
+    {
+        return throttled(format!("Too many requests from {}", remote_addr.ip()), wait).into_response();
+    }
+    next.run(request).await
+}
+
+/// Check an order request against the limits of its account before it is
+/// applied. Runs on the sequencer thread, where the encryption mode and the
+/// account's open orders are known; replaying a journal is never limited.
+/// Every limit is checked before a token is taken from either budget, so an
+/// order refused by one limit does not use up the others.
+pub fn admit(orderbook: &Orderbook, entry: &JournalEntry) -> Result<(), OrderbookError> {
+    let (Some(limiter), Some(user_pubkey)) = (LIMITER.get(), entry.user_pubkey()) else {
+        return Ok(());
+    };
+
+    let rests = matches!(entry, JournalEntry::Limit(_) | JournalEntry::Stop(_) | JournalEntry::Pegged(_));
+    if let Some(max_open_orders) = limiter.max_open_orders
+        && rests
+    {
+        let open = orderbook.open_order_count(user_pubkey);
+        if open >= max_open_orders {
+            let mut message = format!(
+                "Account {} has {} open orders and at most {} are allowed; cancel some, or wait for them to fill or expire",
+                user_pubkey, open, max_open_orders
+            );
+            // Filled oblivious orders only leave the book when they are compacted
+            let retry_after = (orderbook.matching_mode == MatchingMode::Oblivious).then_some(COMPACTION_INTERVAL);
+            if let Some(interval) = retry_after {
+                message.push_str(&format!(". Filled orders are closed every {}s", interval.as_secs()));
+            }
+            return Err(OrderbookError::RateLimited { message, retry_after });
+        }
+    }
+
+    let account = user_pubkey.to_string();
+    let budgets = [
+        (limiter.per_account.as_ref(), "orders"),
+        (limiter.encrypted_orders.as_ref().filter(|_| orderbook.is_using_encryption()), "encrypted orders"),
+    ];
+    for (buckets, kind) in budgets {
+        if let Some(buckets) = buckets {
+            buckets
+                .check(&account)
+                .map_err(|wait| throttled(format!("Too many {} from account {}", kind, user_pubkey), wait))?;
+        }
+    }
+    for (buckets, _) in budgets {
+        if let Some(buckets) = buckets {
+            // Cannot fail, since the sequencer thread is the only one drawing on these budgets
+            let _ = buckets.take(account.clone());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buckets(per_second: f64, burst: u32) -> Buckets<&'static str> {
+        Buckets::new(RateLimit { per_second, burst })
+    }
+
+    #[test]
+    fn buckets_allow_a_burst_then_throttle() {
+        let buckets = buckets(2.0, 3);
+        for _ in 0..3 {
+            assert!(buckets.take("alice").is_ok());
+        }
+
+        let wait = buckets.take("alice").unwrap_err();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        assert!(buckets.take("bob").is_ok());
+    }
+
+    #[test]
+    fn checking_a_bucket_takes_nothing() {
+        let buckets = buckets(0.001, 1);
+        for _ in 0..3 {
+            assert!(buckets.check(&"alice").is_ok());
+        }
+
+        assert!(buckets.take("alice").is_ok());
+        assert!(buckets.check(&"alice").is_err());
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let buckets = buckets(1_000.0, 1);
+        assert!(buckets.take("alice").is_ok());
+
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(buckets.take("alice").is_ok());
+    }
+
+    #[test]
+    fn limits_need_a_positive_rate_and_burst() {
+        assert!(RateLimit { per_second: 0.5, burst: 1 }.is_valid());
+        assert!(!RateLimit { per_second: 0.0, burst: 1 }.is_valid());
+        assert!(!RateLimit { per_second: f64::INFINITY, burst: 1 }.is_valid());
+        assert!(!RateLimit { per_second: 1.0, burst: 0 }.is_valid());
+    }
+}
@@ -0,0 +1,62 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use tokio::sync::oneshot;
+
+use crate::error::OrderbookError;
+use crate::utils::orderbook::Orderbook;
+
+// A unit of work run against the orderbook on the sequencer thread
+type Command = Box<dyn FnOnce(&mut Orderbook) + Send>;
+
+/// Handle to the sequencer thread, which owns the orderbook and applies
+/// commands one at a time in the order they were sent. FHE encryption,
+/// decryption and comparisons all happen on that thread, so they never block
+/// the async runtime. Cloning the handle is cheap.
+#[derive(Clone)]
+pub struct Sequencer {
+    commands: mpsc::Sender<Command>,
+}
+
+impl Sequencer {
+    /// Start the sequencer thread with the given orderbook
+    pub fn spawn(orderbook: Orderbook) -> Self {
+        let (commands, receiver) = mpsc::channel::<Command>();
+
+        thread::Builder::new()
+            .name("sequencer".to_string())
+            .spawn(move || run(orderbook, receiver))
+            .expect("Failed to spawn sequencer thread");
+
+        Self { commands }
+    }
+
+    /// Run `command` with exclusive access to the orderbook and wait for its result.
+    /// A command that panics is reported as an internal error; the sequencer
+    /// keeps serving later commands.
+    pub async fn execute<F, R>(&self, command: F) -> Result<R, OrderbookError>
+    where
+        F: FnOnce(&mut Orderbook) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(Box::new(move |orderbook| {
+                let _ = reply.send(command(orderbook));
+            }))
+            .map_err(|_| OrderbookError::Internal("Sequencer has stopped".to_string()))?;
+
+        response
+            .await
+            .map_err(|_| OrderbookError::Internal("Request failed inside the matching engine".to_string()))
+    }
+}
+
+// Apply commands until every handle has been dropped
+fn run(mut orderbook: Orderbook, commands: mpsc::Receiver<Command>) {
+    while let Ok(command) = commands.recv() {
+        if panic::catch_unwind(AssertUnwindSafe(|| command(&mut orderbook))).is_err() {
+            eprintln!("Sequencer command panicked; continuing with the next command");
+        }
+    }
+}
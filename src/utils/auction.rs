@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use super::orders::{Order, Side};
+
+// Clearing compares every order's price with every other's, and each comparison
+// of encrypted prices is a homomorphic operation whose result is decrypted. An
+// encrypted batch clears at most this many of the oldest orders on each side;
+// the rest wait for the next batch.
+pub const MAX_ENCRYPTED_BATCH_ORDERS: usize = 16;
+
+/// How incoming orders are matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "mode")]
+pub enum MatchingMode {
+    // Match every order against the book as soon as it arrives
+    #[default]
+    Continuous,
+    // Collect orders and clear them together at a uniform price every `interval_ms`
+    BatchAuction { interval_ms: u64 },
+    // Sweep every encrypted order against the whole opposite side with encrypted
+    // selects, so the match pattern is never revealed
+    Oblivious,
+}
+
+impl MatchingMode {
+    pub fn auction_interval(&self) -> Option<Duration> {
+        match self {
+            MatchingMode::Continuous | MatchingMode::Oblivious => None,
+            MatchingMode::BatchAuction { interval_ms } => Some(Duration::from_millis(*interval_ms)),
+        }
+    }
+}
+
+/// Outcome of clearing one batch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuctionResult {
+    pub clearing_price: Option<u32>,
+    pub matched_volume: u64,
+    pub trade_ids: Vec<u64>,
+}
+
+/// The order whose limit price clears the batch, and the volume matched at that price
+pub struct Clearing<'a> {
+    pub price_order: &'a Order,
+    pub volume: u64,
+}
+
+// Total remaining quantity of the orders selected by `include`
+fn total<'a>(orders: impl Iterator<Item = &'a Order>, mut include: impl FnMut(&Order) -> bool) -> u64 {
+    orders
+        .filter(|order| include(order))
+        .map(|order| order.remaining_quantity() as u64)
+        .sum()
+}
+
+/// Find the clearing price of a batch. Every resting limit price is a candidate;
+/// the candidate that matches the most volume wins, then the one that leaves the
+/// smallest imbalance, then the lowest price. Market orders are never candidates,
+/// since their sentinel prices are not prices anyone asked for; they take part at
+/// whichever price is chosen. `ge(a, b)` reports whether `a`'s price
+/// is greater than or equal to `b`'s, so prices are only ever compared, never read.
+pub fn find_clearing<'a, F>(buys: &'a [Order], sells: &'a [Order], ge: F) -> Option<Clearing<'a>>
+where
+    F: Fn(&Order, &Order) -> bool,
+{
+    let mut best: Option<(&Order, u64, u64)> = None;
+
+    for candidate in buys.iter().chain(sells.iter()).filter(|order| !order.is_market()) {
+        let demand = total(buys.iter(), |buy| ge(buy, candidate));
+        let supply = total(sells.iter(), |sell| ge(candidate, sell));
+        let volume = demand.min(supply);
+        let imbalance = demand.abs_diff(supply);
+
+        let better = match best {
+            None => true,
+            Some((best_order, best_volume, best_imbalance)) => {
+                volume > best_volume
+                    || (volume == best_volume && imbalance < best_imbalance)
+                    || (volume == best_volume
+                        && imbalance == best_imbalance
+                        && !ge(candidate, best_order))
+            }
+        };
+        if better {
+            best = Some((candidate, volume, imbalance));
+        }
+    }
+
+    best.filter(|(_, volume, _)| *volume > 0)
+        .map(|(price_order, volume, _)| Clearing { price_order, volume })
+}
+
+/// Allocate `volume` across one side of the batch at the clearing price. Orders
+/// priced strictly better than the clearing price fill first; the remainder is
+/// shared pro-rata among orders at the marginal price. Returns the quantity
+/// allocated to each order, by index.
+pub fn allocate<F>(orders: &[Order], side: Side, clearing: &Clearing, ge: F) -> Vec<u32>
+where
+    F: Fn(&Order, &Order) -> bool,
+{
+    let price_order = clearing.price_order;
+    let mut better = Vec::new();
+    let mut marginal = Vec::new();
+
+    for (i, order) in orders.iter().enumerate() {
+        let (eligible, at_price) = match side {
+            Side::Buy => (ge(order, price_order), ge(price_order, order)),
+            Side::Sell => (ge(price_order, order), ge(order, price_order)),
+        };
+        if eligible && at_price {
+            marginal.push(i);
+        } else if eligible {
+            better.push(i);
+        }
+    }
+
+    let mut allocations = vec![0; orders.len()];
+    let better_total = total(better.iter().map(|&i| &orders[i]), |_| true);
+
+    if better_total >= clearing.volume {
+        pro_rata(orders, &better, clearing.volume, &mut allocations);
+    } else {
+        for &i in &better {
+            allocations[i] = orders[i].remaining_quantity();
+        }
+        pro_rata(orders, &marginal, clearing.volume - better_total, &mut allocations);
+    }
+
+    allocations
+}
+
+// Share `amount` across the given orders in proportion to their remaining
+// quantity. Units lost to rounding go to the earliest orders first.
+fn pro_rata(orders: &[Order], indices: &[usize], amount: u64, allocations: &mut [u32]) {
+    let eligible = total(indices.iter().map(|&i| &orders[i]), |_| true);
+    if eligible == 0 {
+        return;
+    }
+
+    let mut allocated = 0;
+    for &i in indices {
+        let share = (orders[i].remaining_quantity() as u128 * amount as u128 / eligible as u128) as u32;
+        allocations[i] = share;
+        allocated += share as u64;
+    }
+
+    let mut by_time: Vec<usize> = indices.to_vec();
+    by_time.sort_by_key(|&i| orders[i].id);
+    for &i in &by_time {
+        if allocated >= amount {
+            break;
+        }
+        if allocations[i] < orders[i].remaining_quantity() {
+            allocations[i] += 1;
+            allocated += 1;
+        }
+    }
+}
+
+/// Pair buy and sell allocations into trades, in time priority on both sides.
+/// Returns `(buy index, sell index, quantity)` for each trade.
+pub fn pair_allocations(
+    buys: &[Order],
+    buy_allocations: &[u32],
+    sells: &[Order],
+    sell_allocations: &[u32],
+) -> Vec<(usize, usize, u32)> {
+    let queue = |orders: &[Order], allocations: &[u32]| {
+        let mut queue: Vec<(usize, u32)> = allocations
+            .iter()
+            .enumerate()
+            .filter(|(_, quantity)| **quantity > 0)
+            .map(|(i, quantity)| (i, *quantity))
+            .collect();
+        queue.sort_by_key(|(i, _)| orders[*i].id);
+        queue
+    };
+
+    let buy_queue = queue(buys, buy_allocations);
+    let sell_queue = queue(sells, sell_allocations);
+
+    let mut trades = Vec::new();
+    let (mut b, mut s) = (0, 0);
+    let (mut buy_left, mut sell_left) = (
+        buy_queue.first().map_or(0, |(_, q)| *q),
+        sell_queue.first().map_or(0, |(_, q)| *q),
+    );
+
+    while b < buy_queue.len() && s < sell_queue.len() {
+        let quantity = buy_left.min(sell_left);
+        trades.push((buy_queue[b].0, sell_queue[s].0, quantity));
+        buy_left -= quantity;
+        sell_left -= quantity;
+
+        if buy_left == 0 {
+            b += 1;
+            buy_left = buy_queue.get(b).map_or(0, |(_, q)| *q);
+        }
+        if sell_left == 0 {
+            s += 1;
+            sell_left = sell_queue.get(s).map_or(0, |(_, q)| *q);
+        }
+    }
+
+    trades
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u128, price: u32, quantity: u32, side: Side) -> Order {
+        Order::new(id, price, quantity, side, "trader".to_string())
+    }
+
+    fn ge(a: &Order, b: &Order) -> bool {
+        a.price >= b.price
+    }
+
+    #[test]
+    fn clears_at_the_price_that_matches_the_most_volume() {
+        let buys = [order(1, 102, 5, Side::Buy), order(2, 100, 10, Side::Buy)];
+        let sells = [order(3, 99, 6, Side::Sell), order(4, 100, 6, Side::Sell), order(5, 101, 4, Side::Sell)];
+
+        let clearing = find_clearing(&buys, &sells, ge).unwrap();
+
+        assert_eq!((clearing.price_order.price, clearing.volume), (100, 12));
+    }
+
+    #[test]
+    fn breaks_ties_by_imbalance_then_lowest_price() {
+        // Every price matches 5, but only 101 leaves no demand unmatched
+        let buys = [order(1, 101, 5, Side::Buy), order(2, 100, 3, Side::Buy)];
+        let sells = [order(3, 99, 5, Side::Sell)];
+        assert_eq!(find_clearing(&buys, &sells, ge).unwrap().price_order.price, 101);
+
+        let buys = [order(1, 101, 5, Side::Buy)];
+        let sells = [order(2, 99, 5, Side::Sell)];
+        assert_eq!(find_clearing(&buys, &sells, ge).unwrap().price_order.price, 99);
+    }
+
+    #[test]
+    fn market_orders_take_part_without_setting_the_price() {
+        let buys = [Order::new_market(1, 5, Side::Buy, "trader".to_string())];
+        let sells = [order(2, 100, 8, Side::Sell)];
+
+        let clearing = find_clearing(&buys, &sells, ge).unwrap();
+
+        assert_eq!((clearing.price_order.id, clearing.volume), (2, 5));
+    }
+
+    #[test]
+    fn uncrossed_batches_do_not_clear() {
+        let buys = [order(1, 99, 5, Side::Buy)];
+        let sells = [order(2, 100, 5, Side::Sell)];
+
+        assert!(find_clearing(&buys, &sells, ge).is_none());
+        assert!(find_clearing(&buys, &[], ge).is_none());
+    }
+
+    #[test]
+    fn better_priced_orders_fill_before_the_marginal_ones() {
+        let buys = [order(1, 102, 5, Side::Buy), order(2, 100, 10, Side::Buy)];
+        let sells = [order(3, 99, 6, Side::Sell), order(4, 100, 6, Side::Sell), order(5, 101, 4, Side::Sell)];
+        let clearing = find_clearing(&buys, &sells, ge).unwrap();
+
+        let buy_allocations = allocate(&buys, Side::Buy, &clearing, ge);
+        let sell_allocations = allocate(&sells, Side::Sell, &clearing, ge);
+
+        assert_eq!(buy_allocations, vec![5, 7]);
+        assert_eq!(sell_allocations, vec![6, 6, 0]);
+        assert_eq!(
+            pair_allocations(&buys, &buy_allocations, &sells, &sell_allocations),
+            vec![(0, 0, 5), (1, 0, 1), (1, 1, 6)]
+        );
+    }
+
+    #[test]
+    fn marginal_orders_share_pro_rata_with_rounding_to_the_earliest() {
+        let buys = [order(3, 100, 4, Side::Buy), order(1, 100, 3, Side::Buy), order(2, 100, 3, Side::Buy)];
+        let sells = [order(4, 100, 5, Side::Sell)];
+        let clearing = find_clearing(&buys, &sells, ge).unwrap();
+
+        assert_eq!(allocate(&buys, Side::Buy, &clearing, ge), vec![2, 2, 1]);
+    }
+}
@@ -0,0 +1,117 @@
+use serde::de::{self, Deserializer};
+use serde::ser::{self, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use super::encrypted_uint::EncryptedUint;
+use super::fhe_params::IntegerWidth;
+use super::generate_key::KeyId;
+
+/// An encrypted integer kept in deserialized form, so matching can use it
+/// directly instead of decoding bytes on every operation. It records the
+/// version of the keys it was encrypted under, and serializes as that key id,
+/// the integer width and the ciphertext's bincode bytes.
+#[derive(Clone)]
+pub struct Ciphertext {
+    key_id: KeyId,
+    value: EncryptedUint,
+}
+
+// Start of the message of every error decoding a ciphertext, so they can be told apart
+pub const MALFORMED_CIPHERTEXT: &str = "malformed ciphertext";
+
+// Serialized form of a ciphertext
+#[derive(Serialize, Deserialize)]
+struct EncodedCiphertext {
+    key_id: KeyId,
+    // Ciphertexts serialized before widths were configurable are 32-bit
+    #[serde(default)]
+    integer_bits: IntegerWidth,
+    data: Vec<u8>,
+}
+
+impl Ciphertext {
+    pub fn new(key_id: KeyId, value: EncryptedUint) -> Self {
+        Self { key_id, value }
+    }
+
+    /// Version of the keys this value was encrypted under
+    pub fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+}
+
+impl Deref for Ciphertext {
+    type Target = EncryptedUint;
+
+    fn deref(&self) -> &EncryptedUint {
+        &self.value
+    }
+}
+
+// The ciphertext itself is opaque, so only its key version and width are printed
+impl fmt::Debug for Ciphertext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Ciphertext(key {}, {} bits)", self.key_id, self.value.width().bits())
+    }
+}
+
+impl Serialize for Ciphertext {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let data = match &self.value {
+            EncryptedUint::U16(value) => bincode::serialize(value),
+            EncryptedUint::U32(value) => bincode::serialize(value),
+        }
+        .map_err(ser::Error::custom)?;
+        EncodedCiphertext { key_id: self.key_id, integer_bits: self.value.width(), data }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ciphertext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let malformed = |e: &dyn fmt::Display| de::Error::custom(format!("{}: {}", MALFORMED_CIPHERTEXT, e));
+        let encoded = EncodedCiphertext::deserialize(deserializer).map_err(|e| malformed(&e))?;
+        let value = match encoded.integer_bits {
+            IntegerWidth::U16 => bincode::deserialize(&encoded.data).map(EncryptedUint::U16),
+            IntegerWidth::U32 => bincode::deserialize(&encoded.data).map(EncryptedUint::U32),
+        }
+        .map_err(|e| malformed(&e))?;
+        Ok(Self::new(encoded.key_id, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::OrderbookError;
+    use crate::utils::fhe_operations::{self, test_keys};
+
+    #[test]
+    fn ciphertexts_round_trip_with_their_key_version() {
+        let _keys = test_keys();
+        let ciphertext = fhe_operations::encrypt_u32(42, &fhe_operations::client_key().unwrap()).unwrap();
+
+        let decoded: Ciphertext = serde_json::from_str(&serde_json::to_string(&ciphertext).unwrap()).unwrap();
+
+        assert_eq!(decoded.key_id(), ciphertext.key_id());
+        assert_eq!(fhe_operations::decrypt_u32(&decoded).unwrap(), 42);
+    }
+
+    #[test]
+    fn malformed_ciphertexts_are_errors() {
+        for encoded in [r#"{"key_id": 1, "data": [1, 2, 3]}"#, r#"{"key_id": 1}"#, r#""abc""#] {
+            let error = serde_json::from_str::<Ciphertext>(encoded).unwrap_err();
+            assert!(error.to_string().starts_with(MALFORMED_CIPHERTEXT), "{}", error);
+        }
+    }
+
+    #[test]
+    fn ciphertexts_under_unknown_keys_are_errors() {
+        let _keys = test_keys();
+        let ciphertext = fhe_operations::encrypt_u32(42, &fhe_operations::client_key().unwrap()).unwrap();
+        let unknown = Ciphertext::new(KeyId::MAX, (*ciphertext).clone());
+
+        assert!(matches!(fhe_operations::decrypt_u32(&unknown), Err(OrderbookError::KeyUnavailable(_))));
+        assert!(matches!(fhe_operations::verify_ciphertext(&unknown), Err(OrderbookError::KeyUnavailable(_))));
+    }
+}
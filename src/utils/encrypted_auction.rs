@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+use tfhe::ClientKey;
+use crate::error::OrderbookError;
+use super::ciphertext::Ciphertext;
+use super::encrypted_uint::EncryptedUint;
+use super::fhe_operations;
+use super::generate_key::KeyId;
+use super::orders::{Order, Side};
+
+// Upper bound on grid points, since every point costs one comparison per order
+pub const MAX_GRID_POINTS: u32 = 256;
+
+/// Discrete prices at which encrypted demand and supply are evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceGrid {
+    pub min_price: u32,
+    pub max_price: u32,
+    pub tick: u32,
+}
+
+impl PriceGrid {
+    pub fn validate(&self) -> Result<(), OrderbookError> {
+        // Price 0 is reserved to mean "the batch does not cross"
+        if self.min_price == 0 {
+            return Err(OrderbookError::InvalidRequest("Price grid must start above 0".to_string()));
+        }
+        if self.tick == 0 || self.max_price < self.min_price {
+            return Err(OrderbookError::InvalidRequest("Price grid needs a positive tick and max_price >= min_price".to_string()));
+        }
+        if (self.max_price - self.min_price) / self.tick + 1 > MAX_GRID_POINTS {
+            return Err(OrderbookError::InvalidRequest(format!("Price grid may have at most {} points", MAX_GRID_POINTS)));
+        }
+        Ok(())
+    }
+
+    pub fn points(&self) -> Vec<u32> {
+        (self.min_price..=self.max_price).step_by(self.tick as usize).collect()
+    }
+}
+
+/// An order's share of the cleared volume, readable only with the client key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedAllocation {
+    pub order_id: u128,
+    pub user_pubkey: String,
+    pub side: Side,
+    pub encrypted_quantity: Ciphertext,
+}
+
+/// Result of clearing a batch homomorphically. Only the clearing price is
+/// decrypted; matched volume and allocations stay encrypted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedClearing {
+    pub clearing_price: Option<u32>,
+    pub allocations: Vec<EncryptedAllocation>,
+}
+
+// An encrypted order's price and remaining quantity
+struct EncryptedEntry<'a> {
+    order: &'a Order,
+    price: EncryptedUint,
+    quantity: EncryptedUint,
+}
+
+fn load_entries(orders: &[Order]) -> Result<Vec<EncryptedEntry<'_>>, OrderbookError> {
+    let mut entries = Vec::with_capacity(orders.len());
+    for order in orders {
+        let (price, quantity) = match (&order.encrypted_price, &order.encrypted_quantity) {
+            (Some(price), Some(quantity)) => (price, quantity),
+            _ => return Err(OrderbookError::Conflict(format!("Order {} is not encrypted", order.id))),
+        };
+        entries.push(EncryptedEntry {
+            order,
+            price: (**price).clone(),
+            // Fills are recorded in plaintext, so subtract them from the original quantity
+            quantity: &**quantity - order.filled_quantity,
+        });
+    }
+    // Allocation walks orders in time priority
+    entries.sort_by_key(|entry| entry.order.id);
+    Ok(entries)
+}
+
+// Sum of `quantity * bit` over the entries, where each bit is an encrypted 0 or 1.
+// The sum saturates at `max` rather than wrapping, so volume beyond the integer
+// width is understated and never allocated, instead of turning into a small number.
+fn masked_sum(
+    entries: &[EncryptedEntry],
+    bits: &[EncryptedUint],
+    zero: &EncryptedUint,
+    max: &EncryptedUint,
+) -> Result<EncryptedUint, OrderbookError> {
+    let mut sum = zero.clone();
+    for (entry, bit) in entries.iter().zip(bits) {
+        let mut next = sum.clone();
+        next.add_encrypted(&(&entry.quantity * bit)?)?;
+        // Unsigned addition wrapped exactly when the result is below the previous sum
+        sum = next.lt(&sum)?.if_then_else(max, &next)?;
+    }
+    Ok(sum)
+}
+
+/// Clear a batch of encrypted orders at a uniform price without decrypting any
+/// order. Cumulative demand and supply are evaluated homomorphically at every
+/// grid point, the point with the greatest matched volume (lowest on ties) is
+/// selected obliviously, and only that price is decrypted. Allocations follow
+/// price-time priority: orders priced strictly better than the clearing price
+/// fill first, then orders at the clearing price in arrival order.
+pub fn clear_batch(
+    buys: &[Order],
+    sells: &[Order],
+    grid: &PriceGrid,
+    client_key: &ClientKey,
+) -> Result<EncryptedClearing, OrderbookError> {
+    grid.validate()?;
+    let key_id = fhe_operations::ensure_server_key()?;
+
+    let buys = load_entries(buys)?;
+    let sells = load_entries(sells)?;
+
+    let width = fhe_operations::key_parameters(key_id)?.integer_bits;
+    if grid.max_price > width.max_value() {
+        return Err(OrderbookError::InvalidRequest(format!(
+            "Price grid exceeds the {}-bit encrypted integers of the active keys",
+            width.bits()
+        )));
+    }
+    let zero = EncryptedUint::trivial(width, 0)?;
+    let max = EncryptedUint::trivial(width, width.max_value())?;
+    let mut best_volume = zero.clone();
+    let mut best_price = zero.clone();
+
+    for point in grid.points() {
+        let buy_bits = buys
+            .iter()
+            .map(|entry| entry.price.ge(point))
+            .collect::<Result<Vec<EncryptedUint>, OrderbookError>>()?;
+        let sell_bits = sells
+            .iter()
+            .map(|entry| entry.price.le(point))
+            .collect::<Result<Vec<EncryptedUint>, OrderbookError>>()?;
+
+        let demand = masked_sum(&buys, &buy_bits, &zero, &max)?;
+        let supply = masked_sum(&sells, &sell_bits, &zero, &max)?;
+        let volume = demand.min(&supply)?;
+
+        // Strictly greater keeps the lowest price among equal volumes
+        let improves = volume.gt(&best_volume)?;
+        best_volume = improves.if_then_else(&volume, &best_volume)?;
+        best_price = improves.if_then_else(&EncryptedUint::trivial(width, point)?, &best_price)?;
+    }
+
+    let clearing_price: u32 = best_price.decrypt(client_key);
+    if clearing_price == 0 {
+        return Ok(EncryptedClearing { clearing_price: None, allocations: Vec::new() });
+    }
+
+    let mut allocations = allocate(&buys, Side::Buy, clearing_price, &best_volume, key_id)?;
+    allocations.extend(allocate(&sells, Side::Sell, clearing_price, &best_volume, key_id)?);
+
+    Ok(EncryptedClearing {
+        clearing_price: Some(clearing_price),
+        allocations,
+    })
+}
+
+// Allocate the encrypted matched volume across one side of the batch
+fn allocate(
+    entries: &[EncryptedEntry],
+    side: Side,
+    clearing_price: u32,
+    volume: &EncryptedUint,
+    key_id: KeyId,
+) -> Result<Vec<EncryptedAllocation>, OrderbookError> {
+    let mut better = Vec::with_capacity(entries.len());
+    let mut marginal = Vec::with_capacity(entries.len());
+    for entry in entries {
+        better.push(match side {
+            Side::Buy => entry.price.gt(clearing_price)?,
+            Side::Sell => entry.price.lt(clearing_price)?,
+        });
+        marginal.push(entry.price.eq(clearing_price)?);
+    }
+
+    let mut remaining = volume.clone();
+    let mut allocated: Vec<EncryptedUint> = Vec::with_capacity(entries.len());
+
+    // Every order is visited in both passes so the work done never depends on prices
+    for pass in [&better, &marginal] {
+        for (i, (entry, bit)) in entries.iter().zip(pass.iter()).enumerate() {
+            let wanted = (&entry.quantity * bit)?;
+            let fill = wanted.min(&remaining)?;
+            remaining.sub_encrypted(&fill)?;
+            match allocated.get_mut(i) {
+                Some(total) => total.add_encrypted(&fill)?,
+                None => allocated.push(fill),
+            }
+        }
+    }
+
+    Ok(entries
+        .iter()
+        .zip(allocated)
+        .map(|(entry, quantity)| EncryptedAllocation {
+            order_id: entry.order.id,
+            user_pubkey: entry.order.user_pubkey.clone(),
+            side: side.clone(),
+            encrypted_quantity: Ciphertext::new(key_id, quantity),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fhe_operations::test_keys;
+
+    fn encrypted(id: u128, price: u32, quantity: u32, side: Side) -> Order {
+        let mut order = Order::new(id, price, quantity, side, format!("trader-{}", id));
+        fhe_operations::encrypt_order(&mut order, &fhe_operations::client_key().unwrap()).unwrap();
+        order.is_encrypted = true;
+        order
+    }
+
+    #[test]
+    fn grids_must_be_bounded_and_above_zero() {
+        let grid = |min_price, max_price, tick| PriceGrid { min_price, max_price, tick };
+        assert_eq!(grid(99, 102, 1).points(), vec![99, 100, 101, 102]);
+        assert!(grid(1, MAX_GRID_POINTS, 1).validate().is_ok());
+        assert!(grid(1, MAX_GRID_POINTS + 1, 1).validate().is_err());
+        assert!(grid(0, 10, 1).validate().is_err());
+        assert!(grid(10, 20, 0).validate().is_err());
+        assert!(grid(20, 10, 1).validate().is_err());
+    }
+
+    // Even a single grid point takes minutes of homomorphic multiplications
+    #[test]
+    #[ignore = "slow; run with --ignored"]
+    fn clears_the_crossing_volume_at_the_grid_price() {
+        let _keys = test_keys();
+        let buys = [encrypted(1, 100, 5, Side::Buy)];
+        let sells = [encrypted(2, 100, 3, Side::Sell)];
+        let grid = PriceGrid { min_price: 100, max_price: 100, tick: 1 };
+
+        let clearing = clear_batch(&buys, &sells, &grid, &fhe_operations::client_key().unwrap()).unwrap();
+
+        assert_eq!(clearing.clearing_price, Some(100));
+        let allocations: Vec<(u128, u32)> = clearing.allocations
+            .iter()
+            .map(|allocation| (allocation.order_id, fhe_operations::decrypt_u32(&allocation.encrypted_quantity).unwrap()))
+            .collect();
+        assert_eq!(allocations, vec![(1, 3), (2, 3)]);
+    }
+}
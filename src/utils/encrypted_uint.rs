@@ -0,0 +1,221 @@
+use std::ops::{Mul, Sub};
+use tfhe::conformance::ParameterSetConformant;
+use tfhe::integer::ciphertext::IntegerCiphertext;
+use tfhe::prelude::*;
+use tfhe::{ClientKey, FheUint16, FheUint32, PublicKey};
+use crate::error::OrderbookError;
+use super::fhe_params::{IntegerWidth, KeyParameters};
+
+/// An encrypted unsigned integer of the width its keys were generated for.
+/// Operations mirror TFHE's integer types. Both operands of an operation must
+/// have the same width, which ciphertexts of the same key version always do;
+/// operations on ciphertexts of different widths fail.
+#[derive(Clone)]
+pub enum EncryptedUint {
+    U16(FheUint16),
+    U32(FheUint32),
+}
+
+/// Right-hand side of a comparison: another ciphertext or a plaintext value
+pub enum Operand<'a> {
+    Encrypted(&'a EncryptedUint),
+    Clear(u32),
+}
+
+impl<'a> From<&'a EncryptedUint> for Operand<'a> {
+    fn from(value: &'a EncryptedUint) -> Self {
+        Operand::Encrypted(value)
+    }
+}
+
+impl From<u32> for Operand<'_> {
+    fn from(value: u32) -> Self {
+        Operand::Clear(value)
+    }
+}
+
+fn width_mismatch(lhs: &EncryptedUint, rhs: &EncryptedUint) -> OrderbookError {
+    OrderbookError::InvalidRequest(format!(
+        "Cannot combine {}-bit and {}-bit encrypted integers",
+        lhs.width().bits(),
+        rhs.width().bits()
+    ))
+}
+
+// Apply an operation to two ciphertexts of the same width
+macro_rules! same_width {
+    ($lhs:expr, $rhs:expr, |$a:ident, $b:ident| $op:expr) => {
+        match ($lhs, $rhs) {
+            (EncryptedUint::U16($a), EncryptedUint::U16($b)) => Ok(EncryptedUint::U16($op)),
+            (EncryptedUint::U32($a), EncryptedUint::U32($b)) => Ok(EncryptedUint::U32($op)),
+            (lhs, rhs) => Err(width_mismatch(lhs, rhs)),
+        }
+    };
+}
+
+// Encrypted comparison returning an encrypted 0 or 1 of the same width
+macro_rules! comparison {
+    ($name:ident) => {
+        pub fn $name<'a>(&self, rhs: impl Into<Operand<'a>>) -> Result<Self, OrderbookError> {
+            match (self, rhs.into()) {
+                (_, Operand::Encrypted(rhs)) => same_width!(self, rhs, |a, b| a.$name(b)),
+                (EncryptedUint::U32(a), Operand::Clear(rhs)) => Ok(EncryptedUint::U32(a.$name(rhs))),
+                (EncryptedUint::U16(a), Operand::Clear(rhs)) => Ok(match u16::try_from(rhs) {
+                    Ok(rhs) => EncryptedUint::U16(a.$name(rhs)),
+                    // Every 16-bit value is below the scalar, so the result is
+                    // the same as comparing 0 with 1
+                    Err(_) => EncryptedUint::U16(FheUint16::encrypt_trivial(u16::from(0u32.$name(&1u32)))),
+                }),
+            }
+        }
+    };
+}
+
+impl EncryptedUint {
+    /// Encrypt `value`, which must fit in `width`
+    pub fn encrypt(width: IntegerWidth, value: u32, client_key: &ClientKey) -> Result<Self, OrderbookError> {
+        Ok(match width {
+            IntegerWidth::U16 => EncryptedUint::U16(FheUint16::encrypt(narrow(value)?, client_key)),
+            IntegerWidth::U32 => EncryptedUint::U32(FheUint32::encrypt(value, client_key)),
+        })
+    }
+
+    /// Encrypt `value` with a public key, which needs no secret key material.
+    /// `value` must fit in `width`.
+    pub fn encrypt_public(width: IntegerWidth, value: u32, public_key: &PublicKey) -> Result<Self, OrderbookError> {
+        let encrypted = match width {
+            IntegerWidth::U16 => FheUint16::try_encrypt(narrow(value)?, public_key).map(EncryptedUint::U16),
+            IntegerWidth::U32 => FheUint32::try_encrypt(value, public_key).map(EncryptedUint::U32),
+        };
+        encrypted.map_err(|e| OrderbookError::Internal(format!("Public key encryption failed: {}", e)))
+    }
+
+    /// Trivially encrypt a public constant, which must fit in `width`.
+    /// Needs a server key installed on the current thread.
+    pub fn trivial(width: IntegerWidth, value: u32) -> Result<Self, OrderbookError> {
+        Ok(match width {
+            IntegerWidth::U16 => EncryptedUint::U16(FheUint16::encrypt_trivial(narrow(value)?)),
+            IntegerWidth::U32 => EncryptedUint::U32(FheUint32::encrypt_trivial(value)),
+        })
+    }
+
+    // Trivial encryption of zero with the same width as `self`
+    pub fn zero_like(&self) -> Self {
+        match self {
+            EncryptedUint::U16(_) => EncryptedUint::U16(FheUint16::encrypt_trivial(0u16)),
+            EncryptedUint::U32(_) => EncryptedUint::U32(FheUint32::encrypt_trivial(0u32)),
+        }
+    }
+
+    pub fn decrypt(&self, client_key: &ClientKey) -> u32 {
+        match self {
+            EncryptedUint::U16(value) => u32::from(FheDecrypt::<u16>::decrypt(value, client_key)),
+            EncryptedUint::U32(value) => value.decrypt(client_key),
+        }
+    }
+
+    pub fn width(&self) -> IntegerWidth {
+        match self {
+            EncryptedUint::U16(_) => IntegerWidth::U16,
+            EncryptedUint::U32(_) => IntegerWidth::U32,
+        }
+    }
+
+    /// Check that the ciphertext is well formed for keys generated with `parameters`:
+    /// it has their number of blocks, and every block their dimension, moduli and
+    /// bootstrapping order. Results of operations may carry less than a full
+    /// block, so a lower degree than a fresh encryption is accepted.
+    pub fn is_conformant(&self, parameters: &KeyParameters) -> bool {
+        let conformance = parameters.conformance();
+        let expected = &conformance.shortint_params;
+        let radix = match self {
+            EncryptedUint::U16(value) => value.clone().into_raw_parts().0,
+            EncryptedUint::U32(value) => value.clone().into_raw_parts().0,
+        };
+        radix.blocks().len() == conformance.num_blocks_per_integer
+            && radix.blocks().iter().all(|block| {
+                block.ct.is_conformant(&expected.ct_params)
+                    && block.message_modulus == expected.message_modulus
+                    && block.carry_modulus == expected.carry_modulus
+                    && block.pbs_order == expected.pbs_order
+                    && block.degree.0 <= expected.degree.0
+            })
+    }
+
+    comparison!(ge);
+    comparison!(gt);
+    comparison!(le);
+    comparison!(lt);
+    comparison!(eq);
+
+    pub fn min(&self, rhs: &Self) -> Result<Self, OrderbookError> {
+        same_width!(self, rhs, |a, b| a.min(b))
+    }
+
+    /// Select `then` where `self` encrypts 1 and `otherwise` where it encrypts 0
+    pub fn if_then_else(&self, then: &Self, otherwise: &Self) -> Result<Self, OrderbookError> {
+        match (self, then, otherwise) {
+            (EncryptedUint::U16(c), EncryptedUint::U16(a), EncryptedUint::U16(b)) => Ok(EncryptedUint::U16(c.if_then_else(a, b))),
+            (EncryptedUint::U32(c), EncryptedUint::U32(a), EncryptedUint::U32(b)) => Ok(EncryptedUint::U32(c.if_then_else(a, b))),
+            (c, EncryptedUint::U16(_), EncryptedUint::U16(_)) | (c, EncryptedUint::U32(_), EncryptedUint::U32(_)) => {
+                Err(width_mismatch(c, then))
+            }
+            _ => Err(width_mismatch(then, otherwise)),
+        }
+    }
+
+    /// Add `rhs` in place, wrapping around like TFHE's integers
+    pub fn add_encrypted(&mut self, rhs: &Self) -> Result<(), OrderbookError> {
+        match (self, rhs) {
+            (EncryptedUint::U16(a), EncryptedUint::U16(b)) => *a += b,
+            (EncryptedUint::U32(a), EncryptedUint::U32(b)) => *a += b,
+            (lhs, rhs) => return Err(width_mismatch(lhs, rhs)),
+        }
+        Ok(())
+    }
+
+    /// Subtract `rhs` in place, wrapping around like TFHE's integers
+    pub fn sub_encrypted(&mut self, rhs: &Self) -> Result<(), OrderbookError> {
+        match (self, rhs) {
+            (EncryptedUint::U16(a), EncryptedUint::U16(b)) => *a -= b,
+            (EncryptedUint::U32(a), EncryptedUint::U32(b)) => *a -= b,
+            (lhs, rhs) => return Err(width_mismatch(lhs, rhs)),
+        }
+        Ok(())
+    }
+}
+
+// Convert a value for a 16-bit ciphertext, rejecting values that do not fit
+fn narrow(value: u32) -> Result<u16, OrderbookError> {
+    u16::try_from(value).map_err(|_| {
+        OrderbookError::InvalidRequest(format!("{} does not fit in 16-bit encrypted integers", value))
+    })
+}
+
+impl Sub<&EncryptedUint> for &EncryptedUint {
+    type Output = Result<EncryptedUint, OrderbookError>;
+
+    fn sub(self, rhs: &EncryptedUint) -> Self::Output {
+        same_width!(self, rhs, |a, b| a - b)
+    }
+}
+
+// Subtraction wraps around, so a plaintext that does not fit is reduced to the width
+impl Sub<u32> for &EncryptedUint {
+    type Output = EncryptedUint;
+
+    fn sub(self, rhs: u32) -> EncryptedUint {
+        match self {
+            EncryptedUint::U16(a) => EncryptedUint::U16(a - rhs as u16),
+            EncryptedUint::U32(a) => EncryptedUint::U32(a - rhs),
+        }
+    }
+}
+
+impl Mul<&EncryptedUint> for &EncryptedUint {
+    type Output = Result<EncryptedUint, OrderbookError>;
+
+    fn mul(self, rhs: &EncryptedUint) -> Self::Output {
+        same_width!(self, rhs, |a, b| a * b)
+    }
+}
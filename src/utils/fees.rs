@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::error::OrderbookError;
+
+// Basis points in one whole (100%)
+const BPS_DENOMINATOR: i128 = 10_000;
+
+/// Fee rates that apply once a user's traded notional reaches `min_volume`.
+/// A negative `maker_bps` is a rebate paid to the maker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: u64,
+    pub maker_bps: i32,
+    pub taker_bps: i32,
+}
+
+/// Volume-tiered maker/taker fee schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub tiers: Vec<FeeTier>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            tiers: vec![
+                FeeTier { min_volume: 0, maker_bps: 10, taker_bps: 20 },
+                FeeTier { min_volume: 100_000, maker_bps: 5, taker_bps: 15 },
+                FeeTier { min_volume: 1_000_000, maker_bps: -2, taker_bps: 10 },
+            ],
+        }
+    }
+}
+
+impl FeeSchedule {
+    /// Check that the schedule is usable: tiers start at zero volume, are strictly
+    /// ascending, and no maker rebate exceeds the taker fee of the same tier.
+    pub fn validate(&self) -> Result<(), OrderbookError> {
+        let invalid = |message: String| Err(OrderbookError::InvalidRequest(message));
+
+        let Some(first) = self.tiers.first() else {
+            return invalid("Fee schedule must have at least one tier".to_string());
+        };
+        if first.min_volume != 0 {
+            return invalid("The first fee tier must start at volume 0".to_string());
+        }
+
+        for pair in self.tiers.windows(2) {
+            if pair[1].min_volume <= pair[0].min_volume {
+                return invalid("Fee tiers must be sorted by strictly increasing min_volume".to_string());
+            }
+        }
+
+        for tier in &self.tiers {
+            if !(-(BPS_DENOMINATOR as i32)..=BPS_DENOMINATOR as i32).contains(&tier.maker_bps)
+                || !(0..=BPS_DENOMINATOR as i32).contains(&tier.taker_bps)
+            {
+                return invalid(format!("Fee rates out of range in tier starting at {}", tier.min_volume));
+            }
+            if tier.maker_bps + tier.taker_bps < 0 {
+                return invalid(format!(
+                    "Maker rebate exceeds taker fee in tier starting at {}",
+                    tier.min_volume
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Find the tier that applies to a user with the given traded volume
+    pub fn tier_for_volume(&self, volume: u64) -> &FeeTier {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| volume >= tier.min_volume)
+            .unwrap_or(&self.tiers[0])
+    }
+}
+
+/// Fees charged on a single fill, in quote units
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FillFees {
+    pub maker_fee_bps: i32,
+    pub taker_fee_bps: i32,
+    pub maker_fee: i64,
+    pub taker_fee: i64,
+}
+
+/// Fees accrued by a single user
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserFees {
+    pub volume: u64,
+    pub maker_fees: i64,
+    pub taker_fees: i64,
+    pub total_fees: i64,
+}
+
+/// Fees accrued across a market
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarketFees {
+    pub volume: u64,
+    pub maker_fees: i64,
+    pub taker_fees: i64,
+    pub net_revenue: i64,
+}
+
+/// Applies the fee schedule to fills and keeps running totals per user and market
+#[derive(Debug, Clone, Default)]
+pub struct FeeEngine {
+    pub schedule: FeeSchedule,
+    pub users: HashMap<String, UserFees>,
+    pub markets: HashMap<String, MarketFees>,
+}
+
+impl FeeEngine {
+    pub fn new(schedule: FeeSchedule) -> Self {
+        Self {
+            schedule,
+            users: HashMap::new(),
+            markets: HashMap::new(),
+        }
+    }
+
+    // Fee for a notional amount at a rate; charges round up, rebates round down
+    fn fee_for(notional: u64, bps: i32) -> i64 {
+        let raw = notional as i128 * bps as i128;
+        let fee = if raw >= 0 {
+            (raw + BPS_DENOMINATOR - 1) / BPS_DENOMINATOR
+        } else {
+            raw / BPS_DENOMINATOR
+        };
+        fee as i64
+    }
+
+    /// Compute the fees for a fill and accrue them. Each user's tier is based on
+    /// the volume they traded before this fill.
+    pub fn apply(&mut self, market: &str, maker: &str, taker: &str, price: u32, quantity: u32) -> FillFees {
+        let notional = price as u64 * quantity as u64;
+
+        let maker_volume = self.users.get(maker).map_or(0, |u| u.volume);
+        let taker_volume = self.users.get(taker).map_or(0, |u| u.volume);
+        let maker_fee_bps = self.schedule.tier_for_volume(maker_volume).maker_bps;
+        let taker_fee_bps = self.schedule.tier_for_volume(taker_volume).taker_bps;
+
+        let fees = FillFees {
+            maker_fee_bps,
+            taker_fee_bps,
+            maker_fee: Self::fee_for(notional, maker_fee_bps),
+            taker_fee: Self::fee_for(notional, taker_fee_bps),
+        };
+
+        let maker_entry = self.users.entry(maker.to_string()).or_default();
+        maker_entry.volume += notional;
+        maker_entry.maker_fees += fees.maker_fee;
+        maker_entry.total_fees += fees.maker_fee;
+
+        let taker_entry = self.users.entry(taker.to_string()).or_default();
+        taker_entry.volume += notional;
+        taker_entry.taker_fees += fees.taker_fee;
+        taker_entry.total_fees += fees.taker_fee;
+
+        let market_entry = self.markets.entry(market.to_string()).or_default();
+        market_entry.volume += notional;
+        market_entry.maker_fees += fees.maker_fee;
+        market_entry.taker_fees += fees.taker_fee;
+        market_entry.net_revenue += fees.maker_fee + fees.taker_fee;
+
+        fees
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiers_follow_traded_volume() {
+        let schedule = FeeSchedule::default();
+        assert_eq!(schedule.tier_for_volume(0).taker_bps, 20);
+        assert_eq!(schedule.tier_for_volume(99_999).taker_bps, 20);
+        assert_eq!(schedule.tier_for_volume(100_000).taker_bps, 15);
+        assert_eq!(schedule.tier_for_volume(5_000_000).maker_bps, -2);
+    }
+
+    #[test]
+    fn tier_is_chosen_from_volume_before_the_fill() {
+        let mut engine = FeeEngine::default();
+        // 100 x 1000 moves both users into the second tier, but only for later fills
+        let first = engine.apply("default", "maker", "taker", 100, 1_000);
+        let second = engine.apply("default", "maker", "taker", 100, 1_000);
+
+        assert_eq!((first.maker_fee_bps, first.taker_fee_bps), (10, 20));
+        assert_eq!((first.maker_fee, first.taker_fee), (100, 200));
+        assert_eq!((second.maker_fee_bps, second.taker_fee_bps), (5, 15));
+        assert_eq!(engine.users["taker"].volume, 200_000);
+        assert_eq!(engine.markets["default"].net_revenue, 100 + 200 + 50 + 150);
+    }
+
+    #[test]
+    fn charges_round_up_and_rebates_round_down() {
+        assert_eq!(FeeEngine::fee_for(1, 10), 1);
+        assert_eq!(FeeEngine::fee_for(999, 10), 1);
+        assert_eq!(FeeEngine::fee_for(1_001, 10), 2);
+        assert_eq!(FeeEngine::fee_for(999, -2), 0);
+        assert_eq!(FeeEngine::fee_for(10_000, -2), -2);
+    }
+
+    #[test]
+    fn invalid_schedules_are_refused() {
+        let tier = |min_volume, maker_bps, taker_bps| FeeTier { min_volume, maker_bps, taker_bps };
+        let invalid = [
+            vec![],
+            vec![tier(10, 0, 0)],
+            vec![tier(0, 0, 0), tier(0, 0, 0)],
+            vec![tier(0, -5, 2)],
+            vec![tier(0, 0, 20_000)],
+        ];
+        for tiers in invalid {
+            assert!(FeeSchedule { tiers }.validate().is_err());
+        }
+        assert!(FeeSchedule::default().validate().is_ok());
+    }
+}
@@ -1,106 +1,576 @@
-use tfhe::prelude::*;
-use tfhe::{FheUint32, ServerKey, ClientKey, set_server_key};
-use std::sync::Arc;
+use tfhe::{ServerKey, ClientKey, CompressedPublicKey, PublicKey, set_server_key};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
 use once_cell::sync::OnceCell;
-use crate::utils::generate_key;
-use crate::utils::orders::{Order, Side};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use crate::error::OrderbookError;
+use crate::utils::ciphertext::Ciphertext;
+use crate::utils::encrypted_uint::EncryptedUint;
+use crate::utils::fhe_params::{IntegerWidth, KeyParameters, ParameterProfile};
+use crate::utils::generate_key::{self, KeyId};
+use crate::utils::iceberg;
+use crate::utils::orders::{Order, OrderType, Side};
 use std::io;
 
-// Global server key for FHE operations
-static SERVER_KEY: OnceCell<Arc<ServerKey>> = OnceCell::new();
+/// A client key together with the version and parameters of the keys it belongs to
+pub struct VersionedClientKey {
+    pub id: KeyId,
+    pub parameters: KeyParameters,
+    key: ClientKey,
+}
+
+impl Deref for VersionedClientKey {
+    type Target = ClientKey;
+
+    fn deref(&self) -> &ClientKey {
+        &self.key
+    }
+}
+
+/// A public key of one key version, in the compressed form clients download.
+/// It encrypts values for that version but cannot decrypt anything.
+#[derive(Serialize, Deserialize)]
+pub struct VersionedPublicKey {
+    pub id: KeyId,
+    pub parameters: KeyParameters,
+    key: CompressedPublicKey,
+}
+
+impl VersionedPublicKey {
+    /// Derive the public key of a client key. This takes a while.
+    pub fn new(client_key: &VersionedClientKey) -> Self {
+        Self { id: client_key.id, parameters: client_key.parameters, key: CompressedPublicKey::new(&client_key.key) }
+    }
+
+    /// Expand the key so it can encrypt. Expanding is slow and the result is
+    /// large, so expand once and encrypt every value with it.
+    pub fn expand(self) -> ExpandedPublicKey {
+        ExpandedPublicKey { id: self.id, parameters: self.parameters, key: self.key.decompress() }
+    }
+}
+
+/// A public key ready to encrypt values for its key version
+pub struct ExpandedPublicKey {
+    pub id: KeyId,
+    pub parameters: KeyParameters,
+    key: PublicKey,
+}
+
+// One version of the FHE keys. Each key is read from disk the first time it is needed.
+struct KeyVersion {
+    id: KeyId,
+    parameters: KeyParameters,
+    server_key: OnceCell<Arc<ServerKey>>,
+    client_key: OnceCell<Arc<VersionedClientKey>>,
+    // Serialized `VersionedPublicKey`, derived from the client key on first request
+    public_key: OnceCell<Arc<Vec<u8>>>,
+}
+
+impl KeyVersion {
+    fn new(id: KeyId, parameters: KeyParameters) -> Self {
+        Self {
+            id,
+            parameters,
+            server_key: OnceCell::new(),
+            client_key: OnceCell::new(),
+            public_key: OnceCell::new(),
+        }
+    }
+
+    fn server_key(&self) -> Result<Arc<ServerKey>, OrderbookError> {
+        self.server_key
+            .get_or_try_init(|| generate_key::load_server_key(self.id).map(Arc::new))
+            .cloned()
+            .map_err(|e| OrderbookError::KeyUnavailable(format!("Failed to load server key {}: {}", self.id, e)))
+    }
+
+    fn client_key(&self) -> Result<Arc<VersionedClientKey>, OrderbookError> {
+        self.client_key
+            .get_or_try_init(|| {
+                generate_key::load_client_key(self.id).map(|key| {
+                    Arc::new(VersionedClientKey { id: self.id, parameters: self.parameters, key })
+                })
+            })
+            .cloned()
+            .map_err(|e| OrderbookError::KeyUnavailable(format!("Failed to load client key {}: {}", self.id, e)))
+    }
+
+    fn public_key(&self) -> Result<Arc<Vec<u8>>, OrderbookError> {
+        self.public_key
+            .get_or_try_init(|| {
+                let unavailable = |e: io::Error| {
+                    OrderbookError::KeyUnavailable(format!("Failed to load public key {}: {}", self.id, e))
+                };
+                if let Some(bytes) = generate_key::load_public_key(self.id).map_err(unavailable)? {
+                    return Ok(Arc::new(bytes));
+                }
+                println!("Deriving public key (version {})...", self.id);
+                let public_key = VersionedPublicKey::new(&*self.client_key()?);
+                let bytes = bincode::serialize(&public_key)
+                    .map_err(|e| OrderbookError::Internal(format!("Failed to serialize public key: {}", e)))?;
+                generate_key::save_public_key(self.id, &bytes).map_err(unavailable)?;
+                Ok(Arc::new(bytes))
+            })
+            .cloned()
+    }
+}
+
+// Every known key version. Older versions stay available so ciphertexts
+// encrypted before a rotation, such as past fills, can still be decrypted.
+struct KeyRing {
+    active: Option<KeyId>,
+    versions: BTreeMap<KeyId, Arc<KeyVersion>>,
+}
+
+static KEY_RING: RwLock<KeyRing> = RwLock::new(KeyRing { active: None, versions: BTreeMap::new() });
+
+// Set while a key rotation is running
+static ROTATING: AtomicBool = AtomicBool::new(false);
+
+// Thread pool that evaluates independent FHE operations in parallel
+static FHE_POOL: OnceCell<ThreadPool> = OnceCell::new();
+
+// Number of FHE worker threads; defaults to one per CPU
+const FHE_THREADS_ENV: &str = "FHE_THREADS";
+
+thread_local! {
+    // Server key currently installed on this thread
+    static INSTALLED_SERVER_KEY: RefCell<Option<Arc<ServerKey>>> = const { RefCell::new(None) };
+}
 
 // Initialize the FHE system by loading keys
 pub fn init_fhe() -> io::Result<()> {
     // Ensure keys exist or generate them
     generate_key::ensure_keys_exist()?;
     
-    // Load server key and store in global state
-    let server_key = generate_key::load_server_key()?;
-    let _ = SERVER_KEY.set(Arc::new(server_key));
+    // Register every key version up to the active one with the parameters it was generated with
+    let active = generate_key::active_key_id()?;
+    let mut versions = Vec::new();
+    for id in (1..=active).filter(|id| generate_key::key_version_exists(*id)) {
+        match KEY_RING.read().unwrap_or_else(PoisonError::into_inner).versions.get(&id) {
+            Some(version) => versions.push(version.clone()),
+            None => versions.push(Arc::new(KeyVersion::new(id, generate_key::load_key_parameters(id)?))),
+        }
+    }
+    
+    // Load the active server key and check it produces ciphertexts of the recorded
+    // parameters, so keys and their metadata can never disagree once orders exist
+    let active_version = versions
+        .iter()
+        .find(|version| version.id == active)
+        .ok_or_else(|| io::Error::other(format!("Active key version {} not found", active)))?;
+    let server_key = active_version.server_key().map_err(io::Error::other)?;
+    set_server_key((*server_key).clone());
+    let probe = EncryptedUint::trivial(active_version.parameters.integer_bits, 0).map_err(io::Error::other)?;
+    if !probe.is_conformant(&active_version.parameters) {
+        return Err(io::Error::other(format!(
+            "Server key {} does not match its recorded parameters ({})",
+            active, active_version.parameters
+        )));
+    }
+    println!("Active FHE keys: version {} ({})", active, active_version.parameters);
+    
+    let mut ring = KEY_RING.write().unwrap_or_else(PoisonError::into_inner);
+    for version in versions {
+        ring.versions.entry(version.id).or_insert(version);
+    }
+    ring.active = Some(active);
     
     Ok(())
 }
 
-// Get the server key for FHE operations
-pub fn get_server_key() -> Arc<ServerKey> {
-    SERVER_KEY.get().expect("Server key not initialized").clone()
+// Look up a registered key version
+fn key_version(id: KeyId) -> Result<Arc<KeyVersion>, OrderbookError> {
+    KEY_RING
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .versions
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| OrderbookError::KeyUnavailable(format!("Key version {} is not available", id)))
 }
 
-// Encrypt a u32 value using FHE
-pub fn encrypt_u32(value: u32, client_key: &ClientKey) -> Vec<u8> {
-    let encrypted = FheUint32::encrypt(value, client_key);
-    
-    // Serialize the encrypted value directly
-    bincode::serialize(&encrypted).expect("Failed to serialize ciphertext")
+/// Version of the keys new orders are encrypted with
+pub fn active_key_id() -> Result<KeyId, OrderbookError> {
+    KEY_RING
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .active
+        .ok_or_else(|| OrderbookError::KeyUnavailable("Server key not initialized".to_string()))
 }
 
-// Decrypt a u32 value using FHE
-pub fn decrypt_u32(encrypted_bytes: &[u8], client_key: &ClientKey) -> u32 {
-    let encrypted: FheUint32 = bincode::deserialize(encrypted_bytes)
-        .expect("Failed to deserialize ciphertext");
-    
-    encrypted.decrypt(client_key)
+/// Every registered key version, oldest first
+pub fn key_ids() -> Vec<KeyId> {
+    KEY_RING.read().unwrap_or_else(PoisonError::into_inner).versions.keys().copied().collect()
+}
+
+/// Parameters a registered key version was generated with
+pub fn key_parameters(id: KeyId) -> Result<KeyParameters, OrderbookError> {
+    Ok(key_version(id)?.parameters)
+}
+
+/// Check that a ciphertext is well formed for the parameters of the keys it
+/// claims to be encrypted under
+pub fn verify_ciphertext(ciphertext: &Ciphertext) -> Result<(), OrderbookError> {
+    let parameters = key_parameters(ciphertext.key_id())?;
+    if !ciphertext.is_conformant(&parameters) {
+        return Err(OrderbookError::MalformedCiphertext(format!(
+            "Ciphertext does not match the parameters of key {} ({})",
+            ciphertext.key_id(),
+            parameters
+        )));
+    }
+    Ok(())
+}
+
+// Get the active server key for FHE operations
+pub fn get_server_key() -> Result<Arc<ServerKey>, OrderbookError> {
+    server_key_for(active_key_id()?)
+}
+
+// Server key of a key version, for evaluating on its ciphertexts
+pub fn server_key_for(id: KeyId) -> Result<Arc<ServerKey>, OrderbookError> {
+    key_version(id)?.server_key()
+}
+
+// Install the active server key for FHE operations on the current thread and
+// return its version. Each thread only pays for the copy when the key changes.
+pub fn ensure_server_key() -> Result<KeyId, OrderbookError> {
+    let key_id = active_key_id()?;
+    let server_key = key_version(key_id)?.server_key()?;
+    INSTALLED_SERVER_KEY.with(|installed| {
+        let mut installed = installed.borrow_mut();
+        if !installed.as_ref().is_some_and(|key| Arc::ptr_eq(key, &server_key)) {
+            set_server_key((*server_key).clone());
+            *installed = Some(server_key);
+        }
+    });
+    Ok(key_id)
+}
+
+// Get the FHE thread pool, sized from `FHE_THREADS` when it is set
+pub fn fhe_pool() -> Result<&'static ThreadPool, OrderbookError> {
+    FHE_POOL.get_or_try_init(|| {
+        let threads = env::var(FHE_THREADS_ENV)
+            .ok()
+            .and_then(|threads| threads.parse().ok())
+            .unwrap_or(0);
+        ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("fhe-{}", i))
+            .build()
+            .map_err(|e| OrderbookError::Internal(format!("Failed to start FHE thread pool: {}", e)))
+    })
+}
+
+// Get the active client key, used to encrypt orders and decrypt results. It is
+// loaded once and shared by every later call.
+pub fn client_key() -> Result<Arc<VersionedClientKey>, OrderbookError> {
+    client_key_for(active_key_id()?)
+}
+
+// Get the client key of a specific key version
+pub fn client_key_for(id: KeyId) -> Result<Arc<VersionedClientKey>, OrderbookError> {
+    key_version(id)?.client_key()
+}
+
+/// Serialized public key of a key version, for clients that encrypt their own
+/// orders. It is derived and saved the first time it is requested.
+pub fn public_key_bytes(id: KeyId) -> Result<Arc<Vec<u8>>, OrderbookError> {
+    key_version(id)?.public_key()
+}
+
+/// Marks a key rotation as running until it is dropped
+pub struct RotationGuard;
+
+impl Drop for RotationGuard {
+    fn drop(&mut self) {
+        ROTATING.store(false, Ordering::SeqCst);
+    }
+}
+
+// Claim the right to rotate keys; only one rotation may run at a time
+pub fn begin_rotation() -> Result<RotationGuard, OrderbookError> {
+    if ROTATING.swap(true, Ordering::SeqCst) {
+        return Err(OrderbookError::Conflict("A key rotation is already in progress".to_string()));
+    }
+    Ok(RotationGuard)
+}
+
+/// Generate and save a new key version after the newest one, with the given
+/// parameters. It is registered alongside the current keys but not used until
+/// it is activated.
+pub fn generate_key_version(parameters: KeyParameters) -> Result<KeyId, OrderbookError> {
+    // The key ring is empty if the keys were never loaded, so ask the store as well
+    let stored = generate_key::newest_stored_key_id()
+        .map_err(|e| OrderbookError::KeyUnavailable(format!("Failed to read the key store: {}", e)))?;
+    let id = key_ids().last().copied().max(stored).map_or(1, |newest| newest + 1);
+    let (client_key, server_key) = generate_key::generate_and_save_keys(id, parameters).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => OrderbookError::Conflict(e.to_string()),
+        _ => OrderbookError::Internal(format!("Failed to generate FHE keys: {}", e)),
+    })?;
+
+    let version = KeyVersion::new(id, parameters);
+    let _ = version.server_key.set(Arc::new(server_key));
+    let _ = version.client_key.set(Arc::new(VersionedClientKey { id, parameters, key: client_key }));
+    KEY_RING.write().unwrap_or_else(PoisonError::into_inner).versions.insert(id, Arc::new(version));
+    Ok(id)
+}
+
+/// Parameters for the next key version: whatever is not given is kept from the
+/// active keys, or taken from the environment when there are none yet
+pub fn rotation_parameters(profile: Option<ParameterProfile>, integer_bits: Option<IntegerWidth>) -> Result<KeyParameters, OrderbookError> {
+    let active = match active_key_id() {
+        Ok(id) => key_parameters(id)?,
+        Err(_) => KeyParameters::from_env().map_err(|e| OrderbookError::InvalidRequest(e.to_string()))?,
+    };
+    Ok(KeyParameters {
+        profile: profile.unwrap_or(active.profile),
+        integer_bits: integer_bits.unwrap_or(active.integer_bits),
+    })
+}
+
+/// Make a registered key version the one new orders are encrypted with
+pub fn activate_key(id: KeyId) -> Result<(), OrderbookError> {
+    key_version(id)?;
+    generate_key::set_active_key_id(id)
+        .map_err(|e| OrderbookError::Internal(format!("Failed to record active key: {}", e)))?;
+    KEY_RING.write().unwrap_or_else(PoisonError::into_inner).active = Some(id);
+    Ok(())
+}
+
+// Encrypt a u32 value using FHE, at the integer width of the key version.
+// Values that do not fit the width are rejected.
+pub fn encrypt_u32(value: u32, client_key: &VersionedClientKey) -> Result<Ciphertext, OrderbookError> {
+    let encrypted = EncryptedUint::encrypt(client_key.parameters.integer_bits, value, &client_key.key)?;
+    Ok(Ciphertext::new(client_key.id, encrypted))
+}
+
+// Encrypt a u32 value with a key version's public key, as clients do
+pub fn encrypt_u32_public(value: u32, public_key: &ExpandedPublicKey) -> Result<Ciphertext, OrderbookError> {
+    let encrypted = EncryptedUint::encrypt_public(public_key.parameters.integer_bits, value, &public_key.key)?;
+    Ok(Ciphertext::new(public_key.id, encrypted))
+}
+
+// Decrypt a u32 value using FHE, with the keys it was encrypted under
+pub fn decrypt_u32(encrypted: &Ciphertext) -> Result<u32, OrderbookError> {
+    let client_key = client_key_for(encrypted.key_id())?;
+    if encrypted.width() != client_key.parameters.integer_bits {
+        return Err(OrderbookError::Conflict(format!(
+            "Ciphertext is {}-bit but key {} uses {}-bit integers",
+            encrypted.width().bits(),
+            client_key.id,
+            client_key.parameters.integer_bits.bits()
+        )));
+    }
+    Ok(encrypted.decrypt(&client_key.key))
+}
+
+// Encrypt a value again under another key version
+pub fn reencrypt(encrypted: &Ciphertext, client_key: &VersionedClientKey) -> Result<Ciphertext, OrderbookError> {
+    if encrypted.key_id() == client_key.id {
+        return Ok(encrypted.clone());
+    }
+    encrypt_u32(decrypt_u32(encrypted)?, client_key)
+}
+
+// Whether the order is a market buy, priced at `u32::MAX` to cross every sell
+fn is_market_buy(order: &Order) -> bool {
+    order.side == Side::Buy
+        && order.price == u32::MAX
+        && matches!(order.order_type, OrderType::Market | OrderType::StopMarket)
+}
+
+// Price an order is encrypted at. A market buy's sentinel does not fit narrower
+// integers, so it becomes the largest price they hold, which still crosses every sell.
+fn encryptable_price(order: &Order, client_key: &VersionedClientKey) -> u32 {
+    if is_market_buy(order) {
+        return client_key.parameters.integer_bits.max_value();
+    }
+    order.price
 }
 
 // Encrypt an order's price and quantity
-pub fn encrypt_order(order: &mut Order, client_key: &ClientKey) {
+pub fn encrypt_order(order: &mut Order, client_key: &VersionedClientKey) -> Result<(), OrderbookError> {
     // Set the server key for operations
-    set_server_key((*get_server_key()).clone());
+    ensure_server_key()?;
     
-    // Store the original values as strings in the encrypted_price and encrypted_quantity fields
-    order.encrypted_price = Some(encrypt_u32(order.price, client_key));
-    order.encrypted_quantity = Some(encrypt_u32(order.quantity, client_key));
+    order.encrypted_price = Some(encrypt_u32(encryptable_price(order, client_key), client_key)?);
+    order.encrypted_quantity = Some(encrypt_u32(order.quantity, client_key)?);
+    iceberg::encrypt_iceberg(order, client_key)
+}
+
+// Encrypt an order with the price and quantity ciphertexts its client sent,
+// moved onto the given key version if they were encrypted under another
+pub fn encrypt_order_from(
+    order: &mut Order,
+    price: &Ciphertext,
+    quantity: &Ciphertext,
+    client_key: &VersionedClientKey,
+) -> Result<(), OrderbookError> {
+    ensure_server_key()?;
+
+    order.encrypted_price = Some(reencrypt(price, client_key)?);
+    order.encrypted_quantity = Some(reencrypt(quantity, client_key)?);
+    iceberg::encrypt_iceberg(order, client_key)
 }
 
 // Decrypt an order's price and quantity
-pub fn decrypt_order(order: &Order, client_key: &ClientKey) -> (u32, u32) {
+pub fn decrypt_order(order: &Order) -> Result<(u32, u32), OrderbookError> {
     let price = match &order.encrypted_price {
-        Some(encrypted) => decrypt_u32(encrypted, client_key),
+        Some(encrypted) => decrypt_u32(encrypted)?,
         None => order.price,
     };
     
     let quantity = match &order.encrypted_quantity {
-        Some(encrypted) => decrypt_u32(encrypted, client_key),
+        Some(encrypted) => decrypt_u32(encrypted)?,
         None => order.quantity,
     };
     
-    (price, quantity)
+    Ok((price, quantity))
 }
 
-// Homomorphically compare two encrypted prices
-pub fn compare_prices(price1: &[u8], price2: &[u8]) -> bool {
-    // For simplicity in this demo, we'll decrypt the prices and compare them directly
-    // In a real FHE implementation, we would perform the comparison on encrypted data
-    
-    // Load the client key for decryption
-    let client_key = generate_key::load_client_key().expect("Failed to load client key");
-    
-    // Deserialize the encrypted prices
-    let price1: FheUint32 = bincode::deserialize(price1).expect("Failed to deserialize price1");
-    let price2: FheUint32 = bincode::deserialize(price2).expect("Failed to deserialize price2");
-    
-    // Decrypt the prices
-    let price1_val: u32 = price1.decrypt(&client_key);
-    let price2_val: u32 = price2.decrypt(&client_key);
-    
-    // For buy orders, we want price1 >= price2
-    price1_val >= price2_val
+// Copy of an order with every value decrypted and every ciphertext dropped, for
+// moving a live order onto a plaintext book
+pub fn decrypt_live_order(order: &Order) -> Result<Order, OrderbookError> {
+    let mut decrypted = order.clone();
+    if let Some(price) = &order.encrypted_price
+        && !is_market_buy(order)
+    {
+        decrypted.price = decrypt_u32(price)?;
+    }
+    if let Some(quantity) = &order.encrypted_quantity {
+        decrypted.quantity = decrypt_u32(quantity)?;
+    }
+    if let Some(trigger_price) = &order.encrypted_trigger_price {
+        decrypted.trigger_price = Some(decrypt_u32(trigger_price)?);
+    }
+    if let Some(visible_quantity) = &order.encrypted_visible_quantity {
+        decrypted.visible_quantity = decrypt_u32(visible_quantity)?;
+    }
+    decrypted.encrypted_price = None;
+    decrypted.encrypted_quantity = None;
+    decrypted.encrypted_trigger_price = None;
+    decrypted.encrypted_display_quantity = None;
+    decrypted.encrypted_visible_quantity = None;
+    decrypted.encrypted_reserve_quantity = None;
+    decrypted.is_encrypted = false;
+    Ok(decrypted)
+}
+
+// Copy of an order with every ciphertext encrypted under the given key version
+pub fn reencrypt_order(order: &Order, client_key: &VersionedClientKey) -> Result<Order, OrderbookError> {
+    let mut reencrypted = order.clone();
+    for ciphertext in reencrypted.ciphertexts_mut() {
+        *ciphertext = reencrypt(ciphertext, client_key)?;
+    }
+    // The new keys may be wider, so move a market buy to their largest price
+    if is_market_buy(order) && order.encrypted_price.is_some() {
+        reencrypted.encrypted_price = Some(encrypt_u32(encryptable_price(order, client_key), client_key)?);
+    }
+    Ok(reencrypted)
+}
+
+// Encrypt a stop order's trigger price and drop the plaintext copy
+pub fn encrypt_trigger(order: &mut Order, client_key: &VersionedClientKey) -> Result<(), OrderbookError> {
+    if let Some(trigger_price) = order.trigger_price {
+        order.encrypted_trigger_price = Some(encrypt_u32(trigger_price, client_key)?);
+        order.trigger_price = None;
+    }
+    Ok(())
+}
+
+/// Check whether a trade at `last_price` activates a stop with an encrypted
+/// trigger price. The comparison runs under encryption and only the resulting
+/// activation bit is decrypted, never the trigger price itself.
+pub fn trigger_activated(trigger: &Ciphertext, side: &Side, last_price: u32, client_key: &ClientKey) -> Result<bool, OrderbookError> {
+    ensure_server_key()?;
+
+    let activated = match side {
+        Side::Buy => trigger.le(last_price)?,
+        Side::Sell => trigger.ge(last_price)?,
+    };
+    let bit: u32 = activated.decrypt(client_key);
+    Ok(bit == 1)
+}
+
+fn encrypted_price(order: &Order) -> Result<&Ciphertext, OrderbookError> {
+    match &order.encrypted_price {
+        Some(price) => Ok(price),
+        None => Err(OrderbookError::Conflict(format!("Order {} is not encrypted", order.id))),
+    }
+}
+
+/// Check which resting orders an incoming encrypted order crosses. All price
+/// pairs are compared homomorphically as one batch on the FHE thread pool, and
+/// only the resulting bits are decrypted. Results follow the order of `resting`,
+/// so the caller can apply them in priority order.
+pub fn crossing_batch(incoming: &Order, resting: &[Order], client_key: &ClientKey) -> Result<Vec<bool>, OrderbookError> {
+    let incoming_price = encrypted_price(incoming)?;
+
+    fhe_pool()?.install(|| {
+        resting
+            .par_iter()
+            .map(|order| {
+                ensure_server_key()?;
+                let price = encrypted_price(order)?;
+                let crosses = match incoming.side {
+                    Side::Buy => incoming_price.ge(&**price)?,
+                    Side::Sell => price.ge(&**incoming_price)?,
+                };
+                let bit: u32 = crosses.decrypt(client_key);
+                Ok(bit == 1)
+            })
+            .collect()
+    })
+}
+
+// Homomorphically check whether `price1 >= price2`. Only the resulting bit is
+// decrypted, never either price.
+pub fn compare_prices(price1: &Ciphertext, price2: &Ciphertext) -> Result<bool, OrderbookError> {
+    let key_id = ensure_server_key()?;
+    let client_key = client_key_for(key_id)?;
+    let bit: u32 = price1.ge(&**price2)?.decrypt(&client_key.key);
+    Ok(bit == 1)
 }
 
 // Match buy and sell orders using FHE
-pub fn match_orders(buy_order: &Order, sell_order: &Order) -> bool {
+pub fn match_orders(buy_order: &Order, sell_order: &Order) -> Result<bool, OrderbookError> {
     if buy_order.side != Side::Buy || sell_order.side != Side::Sell {
-        return false;
-    }
-    
-    // Ensure both orders have encrypted data
-    if buy_order.encrypted_price.is_none() || sell_order.encrypted_price.is_none() {
-        return false;
+        return Ok(false);
     }
     
     // Compare prices: buy price >= sell price for a match
-    compare_prices(
-        buy_order.encrypted_price.as_ref().unwrap(),
-        sell_order.encrypted_price.as_ref().unwrap()
-    )
+    match (&buy_order.encrypted_price, &sell_order.encrypted_price) {
+        (Some(buy_price), Some(sell_price)) => compare_prices(buy_price, sell_price),
+        // Both orders need encrypted data
+        _ => Ok(false),
+    }
+}
+
+/// Fast-test keys with 16-bit integers in an in-memory store, generated once per
+/// test binary. The active key is global and some tests rotate it, so tests
+/// that use the key ring hold the returned guard while they run.
+#[cfg(test)]
+pub(crate) fn test_keys() -> std::sync::MutexGuard<'static, ()> {
+    use crate::utils::key_store::MemoryKeyStore;
+    use std::sync::{Mutex, Once};
+
+    static LOCK: Mutex<()> = Mutex::new(());
+    static KEYS: Once = Once::new();
+
+    let guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    KEYS.call_once(|| {
+        generate_key::set_key_store(Box::new(MemoryKeyStore::default())).expect("key store already set");
+        let parameters = KeyParameters { profile: ParameterProfile::FastTest, integer_bits: IntegerWidth::U16 };
+        let id = generate_key_version(parameters).expect("failed to generate test keys");
+        activate_key(id).expect("failed to activate test keys");
+    });
+    guard
 }
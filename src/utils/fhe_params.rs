@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::io;
+use tfhe::integer::parameters::RadixCiphertextConformanceParams;
+use tfhe::shortint::parameters::{
+    PBSParameters, PARAM_MESSAGE_2_CARRY_2_KS_PBS, PARAM_MESSAGE_2_CARRY_2_PBS_KS,
+    PARAM_MULTI_BIT_MESSAGE_2_CARRY_2_GROUP_3_KS_PBS,
+};
+use tfhe::{Config, ConfigBuilder};
+
+// Parameter profile of keys generated when none exist yet
+const PROFILE_ENV: &str = "FHE_PARAMETER_PROFILE";
+// Width of the encrypted integers of keys generated when none exist yet
+const INTEGER_BITS_ENV: &str = "FHE_INTEGER_BITS";
+
+// Every profile encrypts integers as blocks of 2 message bits
+const BITS_PER_BLOCK: usize = 2;
+
+/// Named set of TFHE parameters keys are generated with. All profiles use
+/// parameter sets TFHE publishes for 128-bit security; they trade evaluation
+/// speed against noise margin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParameterProfile {
+    // Multi-bit bootstrapping, which evaluates fastest, for tests and benchmarks
+    FastTest,
+    // TFHE's default integer parameters, which every key before profiles used
+    #[default]
+    Default,
+    // Larger LWE dimension with less noise, at the cost of slower operations
+    HighSecurity,
+}
+
+impl ParameterProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParameterProfile::FastTest => "fast-test",
+            ParameterProfile::Default => "default",
+            ParameterProfile::HighSecurity => "high-security",
+        }
+    }
+
+    fn block_parameters(&self) -> PBSParameters {
+        match self {
+            ParameterProfile::FastTest => PARAM_MULTI_BIT_MESSAGE_2_CARRY_2_GROUP_3_KS_PBS.into(),
+            ParameterProfile::Default => PARAM_MESSAGE_2_CARRY_2_KS_PBS.into(),
+            ParameterProfile::HighSecurity => PARAM_MESSAGE_2_CARRY_2_PBS_KS.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParameterProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl TryFrom<&str> for ParameterProfile {
+    type Error = String;
+
+    fn try_from(name: &str) -> Result<Self, String> {
+        [ParameterProfile::FastTest, ParameterProfile::Default, ParameterProfile::HighSecurity]
+            .into_iter()
+            .find(|profile| profile.name() == name)
+            .ok_or_else(|| format!("Unknown parameter profile '{}': expected fast-test, default or high-security", name))
+    }
+}
+
+/// Width of the encrypted integers prices and quantities are stored in.
+/// Narrower integers are faster to compare but only hold smaller values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum IntegerWidth {
+    U16,
+    #[default]
+    U32,
+}
+
+impl IntegerWidth {
+    pub fn bits(&self) -> u32 {
+        match self {
+            IntegerWidth::U16 => 16,
+            IntegerWidth::U32 => 32,
+        }
+    }
+
+    // Largest value an integer of this width holds
+    pub fn max_value(&self) -> u32 {
+        match self {
+            IntegerWidth::U16 => u16::MAX as u32,
+            IntegerWidth::U32 => u32::MAX,
+        }
+    }
+
+    fn blocks(&self) -> usize {
+        self.bits() as usize / BITS_PER_BLOCK
+    }
+}
+
+impl TryFrom<u32> for IntegerWidth {
+    type Error = String;
+
+    fn try_from(bits: u32) -> Result<Self, String> {
+        match bits {
+            16 => Ok(IntegerWidth::U16),
+            32 => Ok(IntegerWidth::U32),
+            other => Err(format!("Unsupported integer width {}: expected 16 or 32", other)),
+        }
+    }
+}
+
+impl From<IntegerWidth> for u32 {
+    fn from(width: IntegerWidth) -> u32 {
+        width.bits()
+    }
+}
+
+/// Parameters a key version was generated with, stored with its keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct KeyParameters {
+    pub profile: ParameterProfile,
+    pub integer_bits: IntegerWidth,
+}
+
+impl KeyParameters {
+    /// Parameters for the first keys, from `FHE_PARAMETER_PROFILE` and `FHE_INTEGER_BITS`
+    pub fn from_env() -> io::Result<Self> {
+        let mut parameters = Self::default();
+        if let Ok(profile) = env::var(PROFILE_ENV) {
+            parameters.profile = ParameterProfile::try_from(profile.trim()).map_err(io::Error::other)?;
+        }
+        if let Ok(bits) = env::var(INTEGER_BITS_ENV) {
+            let bits: u32 = bits.trim().parse().map_err(|_| {
+                io::Error::other(format!("{} must be 16 or 32", INTEGER_BITS_ENV))
+            })?;
+            parameters.integer_bits = IntegerWidth::try_from(bits).map_err(io::Error::other)?;
+        }
+        Ok(parameters)
+    }
+
+    /// TFHE configuration to generate keys with
+    pub fn config(&self) -> Config {
+        ConfigBuilder::all_disabled()
+            .enable_custom_integers(self.profile.block_parameters(), None)
+            .build()
+    }
+
+    /// What a well-formed ciphertext under these parameters looks like
+    pub fn conformance(&self) -> RadixCiphertextConformanceParams {
+        RadixCiphertextConformanceParams::from_pbs_parameters(
+            self.profile.block_parameters(),
+            self.integer_bits.blocks(),
+        )
+    }
+}
+
+impl fmt::Display for KeyParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} profile, {}-bit integers", self.profile, self.integer_bits.bits())
+    }
+}
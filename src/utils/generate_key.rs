@@ -1,71 +1,213 @@
-use tfhe::prelude::*;
-use tfhe::{ClientKey, ServerKey, ConfigBuilder, generate_keys};
-use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
-
-const SERVER_KEY_PATH: &str = "keys/server_key.bin";
-const CLIENT_KEY_PATH: &str = "keys/client_key.bin";
-
-/// Generate and save FHE keys for the encrypted orderbook
-pub fn generate_and_save_keys() -> io::Result<()> {
-    println!("Generating FHE keys...");
-    
-    // Create keys directory if it doesn't exist
-    if !Path::new("keys").exists() {
-        fs::create_dir("keys")?;
+use tfhe::{ClientKey, ServerKey, generate_keys};
+use once_cell::sync::OnceCell;
+use std::io;
+use super::fhe_params::KeyParameters;
+use super::key_store::{self, KeyStore};
+
+/// Version number of a set of FHE keys
+pub type KeyId = u32;
+
+const SERVER_KEY_NAME: &str = "server_key.bin";
+const CLIENT_KEY_NAME: &str = "client_key.bin";
+const PARAMETERS_NAME: &str = "key_params.json";
+const PUBLIC_KEY_NAME: &str = "public_key.bin";
+// Holds the version of the keys new orders are encrypted with
+const ACTIVE_KEY_NAME: &str = "active_key";
+
+// Store the keys are kept in, chosen from the environment on first use unless set before
+static KEY_STORE: OnceCell<Box<dyn KeyStore>> = OnceCell::new();
+
+/// Keep keys in `store` instead of the one chosen from the environment.
+/// Must be called before any key is read or written.
+pub fn set_key_store(store: Box<dyn KeyStore>) -> io::Result<()> {
+    let description = store.describe();
+    KEY_STORE
+        .set(store)
+        .map_err(|_| io::Error::other("The key store is already in use and cannot be replaced"))?;
+    println!("Using FHE key store: {}", description);
+    Ok(())
+}
+
+fn key_store() -> io::Result<&'static dyn KeyStore> {
+    KEY_STORE
+        .get_or_try_init(|| {
+            let store = key_store::from_env()?;
+            println!("Using FHE key store: {}", store.describe());
+            Ok(store)
+        })
+        .map(|store| store.as_ref())
+}
+
+// Version 1 keeps the original file names, so existing key directories still load
+fn server_key_name(id: KeyId) -> String {
+    match id {
+        1 => SERVER_KEY_NAME.to_string(),
+        _ => format!("server_key.v{}.bin", id),
+    }
+}
+
+fn client_key_name(id: KeyId) -> String {
+    match id {
+        1 => CLIENT_KEY_NAME.to_string(),
+        _ => format!("client_key.v{}.bin", id),
     }
+}
+
+fn parameters_name(id: KeyId) -> String {
+    match id {
+        1 => PARAMETERS_NAME.to_string(),
+        _ => format!("key_params.v{}.json", id),
+    }
+}
+
+fn public_key_name(id: KeyId) -> String {
+    match id {
+        1 => PUBLIC_KEY_NAME.to_string(),
+        _ => format!("public_key.v{}.bin", id),
+    }
+}
+
+/// Generate and save a new version of the FHE keys for the encrypted orderbook
+pub fn generate_and_save_keys(id: KeyId, parameters: KeyParameters) -> io::Result<(ClientKey, ServerKey)> {
+    let store = key_store()?;
+    // Data encrypted under an existing version would become unreadable
+    if version_stored(store, id) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Key version {} already exists in {}", id, store.describe()),
+        ));
+    }
+    println!("Generating FHE keys (version {}, {})...", id, parameters);
     
-    // Configure and generate FHE keys with minimal parameters for faster generation
-    // For testing only - in production, you would use stronger parameters
-    println!("Using minimal FHE parameters for faster key generation (testing only)");
-    // In TFHE 0.4.0, we need to use a different approach
-    let config = ConfigBuilder::all_disabled()
-        .enable_default_integers() // Enable integer operations
-        .build();
-    let (client_key, server_key) = generate_keys(config);
+    let (client_key, server_key) = generate_keys(parameters.config());
     
     // Serialize and save keys
     println!("Serializing and saving keys...");
     let server_key_bytes = bincode::serialize(&server_key)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        .map_err(io::Error::other)?;
+    
+    let mut client_key_bytes = bincode::serialize(&client_key)
+        .map_err(io::Error::other)?;
+    
+    // The client key can decrypt every order, so encrypt it at rest when a passphrase is set
+    match key_store::passphrase() {
+        Some(passphrase) => client_key_bytes = key_store::seal_with_passphrase(&client_key_bytes, &passphrase)?,
+        None if !store.protects_secrets() => {
+            eprintln!("Warning: FHE_KEY_PASSPHRASE is not set; the client key is stored unencrypted")
+        }
+        None => {}
+    }
     
-    let client_key_bytes = bincode::serialize(&client_key)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let parameters_bytes = serde_json::to_vec(&parameters)
+        .map_err(io::Error::other)?;
     
-    fs::write(SERVER_KEY_PATH, server_key_bytes)?;
-    fs::write(CLIENT_KEY_PATH, client_key_bytes)?;
+    store.write(&server_key_name(id), &server_key_bytes, false)?;
+    store.write(&client_key_name(id), &client_key_bytes, true)?;
+    store.write(&parameters_name(id), &parameters_bytes, false)?;
     
     println!("Keys generated and saved successfully");
-    Ok(())
+    Ok((client_key, server_key))
 }
 
-/// Load the server key for FHE operations
-pub fn load_server_key() -> io::Result<ServerKey> {
-    println!("Loading server key...");
-    let key_bytes = fs::read(SERVER_KEY_PATH)?;
+/// Load the server key of a key version for FHE operations
+pub fn load_server_key(id: KeyId) -> io::Result<ServerKey> {
+    println!("Loading server key (version {})...", id);
+    let key_bytes = key_store()?.read(&server_key_name(id))?;
     bincode::deserialize(&key_bytes)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        .map_err(io::Error::other)
 }
 
-/// Load the client key for FHE operations
-pub fn load_client_key() -> io::Result<ClientKey> {
-    println!("Loading client key...");
-    let key_bytes = fs::read(CLIENT_KEY_PATH)?;
+/// Load the client key of a key version for FHE operations
+pub fn load_client_key(id: KeyId) -> io::Result<ClientKey> {
+    println!("Loading client key (version {})...", id);
+    let stored = key_store()?.read(&client_key_name(id))?;
+    let passphrase = key_store::passphrase();
+    if passphrase.is_some() && !key_store::is_sealed(&stored) {
+        eprintln!("Warning: client key {} is stored unencrypted", id);
+    }
+    let key_bytes = key_store::unseal(&stored, passphrase.as_deref())?;
     bincode::deserialize(&key_bytes)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        .map_err(io::Error::other)
+}
+
+/// Check whether the stored client key of a key version is encrypted with a passphrase
+pub fn client_key_sealed(id: KeyId) -> io::Result<bool> {
+    Ok(key_store::is_sealed(&key_store()?.read(&client_key_name(id))?))
+}
+
+/// Load the parameters a key version was generated with. Keys generated before
+/// parameters were recorded used the default profile with 32-bit integers.
+pub fn load_key_parameters(id: KeyId) -> io::Result<KeyParameters> {
+    match key_store()?.read(&parameters_name(id)) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KeyParameters::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Load the serialized public key of a key version, if it has been derived yet
+pub fn load_public_key(id: KeyId) -> io::Result<Option<Vec<u8>>> {
+    match key_store()?.read(&public_key_name(id)) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Save the serialized public key of a key version. It is not secret.
+pub fn save_public_key(id: KeyId, bytes: &[u8]) -> io::Result<()> {
+    key_store()?.write(&public_key_name(id), bytes, false)
+}
+
+/// Version of the keys new orders are encrypted with, 1 until keys are first rotated
+pub fn active_key_id() -> io::Result<KeyId> {
+    match key_store()?.read(ACTIVE_KEY_NAME) {
+        Ok(id) => String::from_utf8_lossy(&id).trim().parse().map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(1),
+        Err(e) => Err(e),
+    }
+}
+
+/// Record the version of the keys new orders are encrypted with
+pub fn set_active_key_id(id: KeyId) -> io::Result<()> {
+    key_store()?.write(ACTIVE_KEY_NAME, id.to_string().as_bytes(), false)
+}
+
+/// Check if the files of a key version exist
+pub fn key_version_exists(id: KeyId) -> bool {
+    key_store().is_ok_and(|store| store.exists(&server_key_name(id)) && store.exists(&client_key_name(id)))
+}
+
+// Whether any file of a key version is in the store
+fn version_stored(store: &dyn KeyStore, id: KeyId) -> bool {
+    [server_key_name(id), client_key_name(id), parameters_name(id)]
+        .iter()
+        .any(|name| store.exists(name))
+}
+
+/// Newest key version in the store, whether or not it has been loaded.
+/// Versions are numbered from 1 without gaps.
+pub fn newest_stored_key_id() -> io::Result<Option<KeyId>> {
+    let store = key_store()?;
+    let mut newest = None;
+    let mut id = 1;
+    while version_stored(store, id) {
+        newest = Some(id);
+        id += 1;
+    }
+    Ok(newest.max(store.exists(ACTIVE_KEY_NAME).then(active_key_id).transpose()?))
 }
 
-/// Check if FHE keys exist
+/// Check if the active FHE keys exist
 pub fn keys_exist() -> bool {
-    Path::new(SERVER_KEY_PATH).exists() && Path::new(CLIENT_KEY_PATH).exists()
+    active_key_id().is_ok_and(key_version_exists)
 }
 
 /// Main function to generate keys if they don't exist
 pub fn ensure_keys_exist() -> io::Result<()> {
     if !keys_exist() {
         println!("FHE keys not found. Generating new keys...");
-        generate_and_save_keys()?;
+        generate_and_save_keys(active_key_id()?, KeyParameters::from_env()?)?;
     } else {
         println!("FHE keys already exist");
     }
@@ -0,0 +1,123 @@
+use crate::error::OrderbookError;
+use super::ciphertext::Ciphertext;
+use super::encrypted_uint::EncryptedUint;
+use super::fhe_operations::{self, VersionedClientKey};
+use super::generate_key::KeyId;
+use super::orders::Order;
+
+// Encrypted quantities of an iceberg order: display size, visible slice and reserve
+struct EncryptedIceberg {
+    key_id: KeyId,
+    display: EncryptedUint,
+    visible: EncryptedUint,
+    reserve: EncryptedUint,
+}
+
+fn load(order: &Order) -> Result<EncryptedIceberg, OrderbookError> {
+    match (
+        &order.encrypted_display_quantity,
+        &order.encrypted_visible_quantity,
+        &order.encrypted_reserve_quantity,
+    ) {
+        (Some(display), Some(visible), Some(reserve)) => Ok(EncryptedIceberg {
+            key_id: display.key_id(),
+            display: (**display).clone(),
+            visible: (**visible).clone(),
+            reserve: (**reserve).clone(),
+        }),
+        _ => Err(OrderbookError::Internal(format!("Order {} has no encrypted iceberg quantities", order.id))),
+    }
+}
+
+fn store(order: &mut Order, iceberg: EncryptedIceberg) {
+    order.encrypted_display_quantity = Some(Ciphertext::new(iceberg.key_id, iceberg.display));
+    order.encrypted_visible_quantity = Some(Ciphertext::new(iceberg.key_id, iceberg.visible));
+    order.encrypted_reserve_quantity = Some(Ciphertext::new(iceberg.key_id, iceberg.reserve));
+}
+
+// Encrypt an iceberg order's display size and split its remaining quantity into
+// the current visible slice and the reserve
+pub fn encrypt_iceberg(order: &mut Order, client_key: &VersionedClientKey) -> Result<(), OrderbookError> {
+    if let Some(display_quantity) = order.display_quantity {
+        let remaining = order.remaining_quantity();
+        let visible = order.visible_quantity.min(remaining);
+        order.encrypted_display_quantity = Some(fhe_operations::encrypt_u32(display_quantity, client_key)?);
+        order.encrypted_visible_quantity = Some(fhe_operations::encrypt_u32(visible, client_key)?);
+        order.encrypted_reserve_quantity = Some(fhe_operations::encrypt_u32(remaining - visible, client_key)?);
+    }
+    Ok(())
+}
+
+/// Recompute the encrypted visible slice and reserve from the order's encrypted
+/// remaining quantity, e.g. after it traded as the aggressor.
+pub fn reset_encrypted(order: &mut Order, remaining: &EncryptedUint) -> Result<(), OrderbookError> {
+    let mut iceberg = load(order)?;
+    iceberg.visible = iceberg.display.min(remaining)?;
+    iceberg.reserve = (remaining - &iceberg.visible)?;
+    store(order, iceberg);
+    Ok(())
+}
+
+/// Take `amount` from the encrypted visible slice, then refill the slice from
+/// the reserve if it is empty. The refill is an encrypted select, so it is
+/// computed whether or not the slice actually ran out.
+pub fn consume_encrypted(order: &mut Order, amount: &EncryptedUint) -> Result<(), OrderbookError> {
+    let mut iceberg = load(order)?;
+    let zero = iceberg.visible.zero_like();
+
+    iceberg.visible.sub_encrypted(amount)?;
+    let empty = iceberg.visible.eq(0u32)?;
+    let refill = empty.if_then_else(&iceberg.display.min(&iceberg.reserve)?, &zero)?;
+    iceberg.visible.add_encrypted(&refill)?;
+    iceberg.reserve.sub_encrypted(&refill)?;
+
+    store(order, iceberg);
+    Ok(())
+}
+
+// Take a plaintext fill amount from an encrypted iceberg's visible slice
+pub fn consume_clear(order: &mut Order, amount: u32) -> Result<(), OrderbookError> {
+    let key_id = fhe_operations::ensure_server_key()?;
+    let amount = EncryptedUint::trivial(fhe_operations::key_parameters(key_id)?.integer_bits, amount)?;
+    consume_encrypted(order, &amount)
+}
+
+/// Recompute the encrypted visible slice and reserve from the order's original
+/// encrypted quantity less its plaintext fills, e.g. after a batch auction that
+/// traded into the reserve.
+pub fn refresh_encrypted(order: &mut Order) -> Result<(), OrderbookError> {
+    fhe_operations::ensure_server_key()?;
+    let quantity = order.encrypted_quantity
+        .as_ref()
+        .ok_or_else(|| OrderbookError::Conflict(format!("Order {} is not encrypted", order.id)))?;
+    let remaining = &**quantity - order.filled_quantity;
+    reset_encrypted(order, &remaining)
+}
+
+// Encrypted quantity an iceberg order can trade right now
+pub fn visible_quantity(order: &Order) -> Result<EncryptedUint, OrderbookError> {
+    Ok(load(order)?.visible)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fhe_operations::test_keys;
+    use crate::utils::orders::Side;
+
+    fn slice_and_reserve(order: &Order) -> (u32, u32) {
+        let decrypt = |ciphertext: &Option<Ciphertext>| fhe_operations::decrypt_u32(ciphertext.as_ref().unwrap()).unwrap();
+        (decrypt(&order.encrypted_visible_quantity), decrypt(&order.encrypted_reserve_quantity))
+    }
+
+    #[test]
+    fn encrypted_slices_refill_from_the_reserve_once_used_up() {
+        let _keys = test_keys();
+        let mut order = Order::new_iceberg(1, 100, 10, 3, Side::Buy, "alice".to_string());
+        encrypt_iceberg(&mut order, &fhe_operations::client_key().unwrap()).unwrap();
+        assert_eq!(slice_and_reserve(&order), (3, 7));
+
+        consume_clear(&mut order, 3).unwrap();
+        assert_eq!(slice_and_reserve(&order), (3, 4));
+    }
+}
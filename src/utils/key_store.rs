@@ -0,0 +1,312 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+// Backend to keep keys in: `file` (default), `memory` or `kms`
+pub const KEY_STORE_ENV: &str = "FHE_KEY_STORE";
+// Directory the file and KMS stores write to
+pub const KEY_DIR_ENV: &str = "FHE_KEY_DIR";
+pub const DEFAULT_KEY_DIR: &str = "keys";
+// Passphrase the client key is encrypted with at rest
+const PASSPHRASE_ENV: &str = "FHE_KEY_PASSPHRASE";
+// Master key of the KMS stand-in, 64 hex characters
+const KMS_MASTER_KEY_ENV: &str = "FHE_KMS_MASTER_KEY";
+
+// Prefix of key files sealed with a passphrase
+const SEALED_MAGIC: &[u8] = b"fhe-sealed-v1";
+// Prefix of entries wrapped by the KMS stand-in
+const KMS_MAGIC: &[u8] = b"fhe-kms-v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Storage backend for FHE key material. Entries are opaque bytes stored under
+/// a name such as `server_key.v2.bin`; `secret` marks entries that must never
+/// be readable by anyone but the server.
+pub trait KeyStore: Send + Sync {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>>;
+    fn write(&self, name: &str, bytes: &[u8], secret: bool) -> io::Result<()>;
+    fn exists(&self, name: &str) -> bool;
+    /// Human-readable location, for log messages
+    fn describe(&self) -> String;
+    /// Whether secrets are protected without a passphrase, e.g. never written to disk
+    fn protects_secrets(&self) -> bool {
+        false
+    }
+}
+
+/// Where keys are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStoreBackend {
+    // Files in the key directory
+    #[default]
+    File,
+    // Process memory, for tests and throwaway servers
+    Memory,
+    // Files in the key directory, with secrets wrapped by a master key
+    Kms,
+}
+
+impl TryFrom<&str> for KeyStoreBackend {
+    type Error = String;
+
+    fn try_from(name: &str) -> Result<Self, String> {
+        match name {
+            "file" => Ok(KeyStoreBackend::File),
+            "memory" => Ok(KeyStoreBackend::Memory),
+            "kms" => Ok(KeyStoreBackend::Kms),
+            other => Err(format!("Unknown key store '{}': expected file, memory or kms", other)),
+        }
+    }
+}
+
+/// Keys kept as files in a directory. Secret files are only readable by their owner.
+pub struct FileKeyStore {
+    dir: PathBuf,
+}
+
+impl FileKeyStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.dir.join(name))
+    }
+
+    fn write(&self, name: &str, bytes: &[u8], secret: bool) -> io::Result<()> {
+        let mut dir = DirBuilder::new();
+        dir.recursive(true);
+        #[cfg(unix)]
+        dir.mode(0o700);
+        dir.create(&self.dir)?;
+
+        let path = self.dir.join(name);
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if secret {
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        // The mode only applies to new files, so tighten existing ones as well
+        #[cfg(unix)]
+        if secret {
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        #[cfg(not(unix))]
+        let _ = secret;
+        file.write_all(bytes)
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.dir.join(name).exists()
+    }
+
+    fn describe(&self) -> String {
+        format!("directory {}", self.dir.display())
+    }
+}
+
+/// Keys kept in process memory only, for tests and throwaway servers.
+/// Everything is lost when the process exits.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    entries: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", name)))
+    }
+
+    fn write(&self, name: &str, bytes: &[u8], _secret: bool) -> io::Result<()> {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner).contains_key(name)
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn protects_secrets(&self) -> bool {
+        true
+    }
+}
+
+/// Stand-in for an external key management service. Secret entries are
+/// envelope-encrypted with a master key that is never written to disk, the
+/// way a KMS would wrap them, and stored in an underlying store.
+pub struct KmsKeyStore {
+    inner: Box<dyn KeyStore>,
+    master_key: Key,
+}
+
+impl KmsKeyStore {
+    pub fn new(inner: Box<dyn KeyStore>, master_key: [u8; 32]) -> Self {
+        Self { inner, master_key: master_key.into() }
+    }
+}
+
+impl KeyStore for KmsKeyStore {
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let bytes = self.inner.read(name)?;
+        match bytes.strip_prefix(KMS_MAGIC) {
+            Some(wrapped) => open(&self.master_key, wrapped),
+            None => Ok(bytes),
+        }
+    }
+
+    fn write(&self, name: &str, bytes: &[u8], secret: bool) -> io::Result<()> {
+        if !secret {
+            return self.inner.write(name, bytes, false);
+        }
+        let mut wrapped = KMS_MAGIC.to_vec();
+        wrapped.extend_from_slice(&seal(&self.master_key, bytes)?);
+        self.inner.write(name, &wrapped, true)
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.inner.exists(name)
+    }
+
+    fn describe(&self) -> String {
+        format!("KMS stand-in over {}", self.inner.describe())
+    }
+
+    fn protects_secrets(&self) -> bool {
+        true
+    }
+}
+
+/// Build the key store selected by `FHE_KEY_STORE`, rooted at `FHE_KEY_DIR`
+pub fn from_env() -> io::Result<Box<dyn KeyStore>> {
+    let dir = env::var(KEY_DIR_ENV).unwrap_or_else(|_| DEFAULT_KEY_DIR.to_string());
+    let backend = match env::var(KEY_STORE_ENV) {
+        Ok(backend) => KeyStoreBackend::try_from(backend.as_str()).map_err(io::Error::other)?,
+        Err(_) => KeyStoreBackend::default(),
+    };
+    build(backend, dir)
+}
+
+/// Build a key store of the given backend, rooted at `dir`
+pub fn build(backend: KeyStoreBackend, dir: impl AsRef<Path>) -> io::Result<Box<dyn KeyStore>> {
+    let dir = dir.as_ref();
+    match backend {
+        KeyStoreBackend::File => Ok(Box::new(FileKeyStore::new(dir))),
+        KeyStoreBackend::Memory => Ok(Box::new(MemoryKeyStore::default())),
+        KeyStoreBackend::Kms => {
+            let master_key = env::var(KMS_MASTER_KEY_ENV)
+                .map_err(|_| io::Error::other(format!("{} must be set for the kms key store", KMS_MASTER_KEY_ENV)))?;
+            Ok(Box::new(KmsKeyStore::new(Box::new(FileKeyStore::new(dir)), parse_master_key(&master_key)?)))
+        }
+    }
+}
+
+fn parse_master_key(hex: &str) -> io::Result<[u8; 32]> {
+    let invalid = || io::Error::other(format!("{} must be 64 hex characters", KMS_MASTER_KEY_ENV));
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+// Passphrase for the client key at rest, if one is configured
+pub fn passphrase() -> Option<String> {
+    env::var(PASSPHRASE_ENV).ok().filter(|passphrase| !passphrase.is_empty())
+}
+
+/// Encrypt key material with a key derived from `passphrase` with Argon2id.
+/// The random salt and nonce are stored alongside the ciphertext.
+pub fn seal_with_passphrase(bytes: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt)?;
+
+    let mut sealed = SEALED_MAGIC.to_vec();
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&seal(&key, bytes)?);
+    Ok(sealed)
+}
+
+/// Decrypt key material sealed with `seal_with_passphrase`. Material that was
+/// never sealed is returned unchanged, so keys written before encryption at
+/// rest was introduced still load.
+pub fn unseal(bytes: &[u8], passphrase: Option<&str>) -> io::Result<Vec<u8>> {
+    let Some(sealed) = bytes.strip_prefix(SEALED_MAGIC) else {
+        return Ok(bytes.to_vec());
+    };
+    let passphrase = passphrase.ok_or_else(|| {
+        io::Error::other(format!("The client key is encrypted; set {} to load it", PASSPHRASE_ENV))
+    })?;
+    if sealed.len() < SALT_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Sealed key is truncated"));
+    }
+    let (salt, ciphertext) = sealed.split_at(SALT_LEN);
+    open(&derive_key(passphrase, salt)?, ciphertext)
+}
+
+// Whether key material was sealed with a passphrase
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(SEALED_MAGIC)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> io::Result<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::other(format!("Failed to derive key from passphrase: {}", e)))?;
+    Ok(key)
+}
+
+// Encrypt with a random nonce, stored in front of the ciphertext
+fn seal(key: &Key, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = ChaCha20Poly1305::new(key)
+        .encrypt(&nonce, bytes)
+        .map_err(|_| io::Error::other("Failed to encrypt key material"))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn open(key: &Key, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Sealed key is truncated"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Wrong passphrase or corrupted key material"))
+}
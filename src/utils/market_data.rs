@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use super::orders::Fill;
+
+// Number of candles kept per interval
+const MAX_CANDLES: usize = 1440;
+// Rolling window used for trade statistics
+const STATS_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+// Fills aggregated into one disclosed trade when no batch size is configured
+pub const DEFAULT_DISCLOSURE_BATCH_SIZE: usize = 10;
+
+/// Candle interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Interval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 4] = [
+        Interval::OneMinute,
+        Interval::FiveMinutes,
+        Interval::OneHour,
+        Interval::OneDay,
+    ];
+
+    pub fn millis(&self) -> u64 {
+        match self {
+            Interval::OneMinute => 60 * 1000,
+            Interval::FiveMinutes => 5 * 60 * 1000,
+            Interval::OneHour => 60 * 60 * 1000,
+            Interval::OneDay => 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+/// Which trades from an encrypted orderbook may feed market data.
+/// Plaintext orderbooks always publish every fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "mode")]
+pub enum DisclosurePolicy {
+    // Nothing is published until the operator explicitly discloses pending fills
+    Hidden,
+    // Every `batch_size` fills are published as a single aggregated trade
+    Aggregated { batch_size: usize },
+}
+
+impl Default for DisclosurePolicy {
+    fn default() -> Self {
+        DisclosurePolicy::Aggregated { batch_size: DEFAULT_DISCLOSURE_BATCH_SIZE }
+    }
+}
+
+/// A trade as seen by the public market data feed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MarketTrade {
+    pub timestamp: u64,
+    pub price: u32,
+    pub quantity: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candle {
+    pub open_time: u64,
+    pub close_time: u64,
+    pub open: u32,
+    pub high: u32,
+    pub low: u32,
+    pub close: u32,
+    pub volume: u64,
+    pub quote_volume: u64,
+    pub trade_count: u64,
+}
+
+impl Candle {
+    fn new(open_time: u64, interval: Interval, trade: &MarketTrade) -> Self {
+        Self {
+            open_time,
+            close_time: open_time + interval.millis() - 1,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.quantity,
+            quote_volume: trade.price as u64 * trade.quantity,
+            trade_count: 1,
+        }
+    }
+
+    fn update(&mut self, trade: &MarketTrade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.quantity;
+        self.quote_volume += trade.price as u64 * trade.quantity;
+        self.trade_count += 1;
+    }
+}
+
+/// Rolling 24h trade statistics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeStats {
+    pub last_price: Option<u32>,
+    pub high_24h: Option<u32>,
+    pub low_24h: Option<u32>,
+    pub volume_24h: u64,
+    pub quote_volume_24h: u64,
+    pub vwap_24h: Option<u32>,
+    pub trade_count_24h: u64,
+}
+
+/// Builds candles and statistics from the disclosed trade feed
+#[derive(Debug, Clone, Default)]
+pub struct MarketData {
+    pub disclosure_policy: DisclosurePolicy,
+    candles: HashMap<Interval, VecDeque<Candle>>,
+    recent_trades: VecDeque<MarketTrade>,
+    last_price: Option<u32>,
+    // Fills from an encrypted orderbook that have not been disclosed yet
+    pending: Vec<MarketTrade>,
+}
+
+impl MarketData {
+    pub fn new(disclosure_policy: DisclosurePolicy) -> Self {
+        Self {
+            disclosure_policy,
+            ..Self::default()
+        }
+    }
+
+    // Publish a fill from a plaintext orderbook
+    pub fn record_public_fill(&mut self, fill: &Fill) {
+        self.publish(MarketTrade {
+            timestamp: fill.timestamp,
+            price: fill.price,
+            quantity: fill.quantity as u64,
+        });
+    }
+
+    // Buffer a fill from an encrypted orderbook and publish it according to the disclosure policy
+    pub fn record_private_fill(&mut self, fill: &Fill) {
+        self.pending.push(MarketTrade {
+            timestamp: fill.timestamp,
+            price: fill.price,
+            quantity: fill.quantity as u64,
+        });
+
+        if let DisclosurePolicy::Aggregated { batch_size } = self.disclosure_policy
+            && self.pending.len() >= batch_size.max(1)
+        {
+            self.disclose_pending();
+        }
+    }
+
+    /// Publish every pending fill as one aggregated trade at their volume-weighted
+    /// price. Returns the disclosed trade, if there was anything to disclose.
+    pub fn disclose_pending(&mut self) -> Option<MarketTrade> {
+        let quantity: u64 = self.pending.iter().map(|t| t.quantity).sum();
+        if quantity == 0 {
+            self.pending.clear();
+            return None;
+        }
+
+        let notional: u128 = self.pending.iter().map(|t| t.price as u128 * t.quantity as u128).sum();
+        let timestamp = self.pending.iter().map(|t| t.timestamp).max().unwrap_or(0);
+        let trade = MarketTrade {
+            timestamp,
+            price: (notional / quantity as u128) as u32,
+            quantity,
+        };
+
+        self.pending.clear();
+        self.publish(trade);
+        Some(trade)
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn publish(&mut self, trade: MarketTrade) {
+        self.last_price = Some(trade.price);
+
+        for interval in Interval::ALL {
+            let open_time = trade.timestamp - trade.timestamp % interval.millis();
+            let candles = self.candles.entry(interval).or_default();
+
+            match candles.iter_mut().rev().find(|c| c.open_time <= open_time) {
+                Some(candle) if candle.open_time == open_time => candle.update(&trade),
+                _ => {
+                    // Keep candles ordered by open time even if a trade arrives late
+                    let position = candles.partition_point(|c| c.open_time < open_time);
+                    candles.insert(position, Candle::new(open_time, interval, &trade));
+                    if candles.len() > MAX_CANDLES {
+                        candles.pop_front();
+                    }
+                }
+            }
+        }
+
+        self.recent_trades.push_back(trade);
+        let cutoff = trade.timestamp.saturating_sub(STATS_WINDOW_MS);
+        while self.recent_trades.front().is_some_and(|t| t.timestamp < cutoff) {
+            self.recent_trades.pop_front();
+        }
+    }
+
+    // Most recent candles for an interval, oldest first
+    pub fn candles(&self, interval: Interval, limit: usize) -> Vec<Candle> {
+        self.candles
+            .get(&interval)
+            .map(|candles| {
+                let skip = candles.len().saturating_sub(limit);
+                candles.iter().skip(skip).cloned().collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Statistics over the 24 hours before `now`
+    pub fn stats(&self, now: u64) -> TradeStats {
+        let cutoff = now.saturating_sub(STATS_WINDOW_MS);
+        let window = self.recent_trades.iter().filter(|t| t.timestamp >= cutoff);
+
+        let mut stats = TradeStats {
+            last_price: self.last_price,
+            ..TradeStats::default()
+        };
+        for trade in window {
+            stats.high_24h = Some(stats.high_24h.map_or(trade.price, |h| h.max(trade.price)));
+            stats.low_24h = Some(stats.low_24h.map_or(trade.price, |l| l.min(trade.price)));
+            stats.volume_24h += trade.quantity;
+            stats.quote_volume_24h += trade.price as u64 * trade.quantity;
+            stats.trade_count_24h += 1;
+        }
+        stats.vwap_24h = stats.quote_volume_24h
+            .checked_div(stats.volume_24h)
+            .map(|vwap| vwap as u32);
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fees::FillFees;
+    use crate::utils::orders::Side;
+
+    // Start of a day, so every interval's candle opens there
+    const DAY: u64 = 1_700_006_400_000;
+
+    fn fill(timestamp: u64, price: u32, quantity: u32) -> Fill {
+        Fill {
+            trade_id: 1,
+            timestamp,
+            buy_order_id: 1,
+            sell_order_id: 2,
+            price,
+            quantity,
+            buyer_pubkey: "alice".to_string(),
+            seller_pubkey: "bob".to_string(),
+            aggressor_side: Side::Sell,
+            maker_order_id: 1,
+            taker_order_id: 2,
+            fees: FillFees { maker_fee_bps: 0, taker_fee_bps: 0, maker_fee: 0, taker_fee: 0 },
+        }
+    }
+
+    #[test]
+    fn public_fills_build_candles() {
+        let mut market_data = MarketData::default();
+        market_data.record_public_fill(&fill(DAY, 100, 2));
+        market_data.record_public_fill(&fill(DAY + 1_000, 104, 1));
+        market_data.record_public_fill(&fill(DAY + 2_000, 98, 3));
+        market_data.record_public_fill(&fill(DAY + 60_000, 101, 1));
+
+        let minutes = market_data.candles(Interval::OneMinute, 10);
+        assert_eq!(minutes.len(), 2);
+        let first = &minutes[0];
+        assert_eq!((first.open, first.high, first.low, first.close), (100, 104, 98, 98));
+        assert_eq!((first.volume, first.quote_volume, first.trade_count), (6, 200 + 104 + 294, 3));
+        assert_eq!(minutes[1].open_time, DAY + 60_000);
+
+        let days = market_data.candles(Interval::OneDay, 10);
+        assert_eq!((days.len(), days[0].volume, days[0].close), (1, 7, 101));
+    }
+
+    #[test]
+    fn aggregated_disclosure_publishes_batches_at_their_vwap() {
+        let mut market_data = MarketData::new(DisclosurePolicy::Aggregated { batch_size: 2 });
+        market_data.record_private_fill(&fill(DAY, 100, 1));
+        assert_eq!(market_data.stats(DAY).trade_count_24h, 0);
+        assert_eq!(market_data.pending_count(), 1);
+
+        market_data.record_private_fill(&fill(DAY + 1, 103, 2));
+        let stats = market_data.stats(DAY + 1);
+        assert_eq!((stats.trade_count_24h, stats.volume_24h, stats.last_price), (1, 3, Some(102)));
+        assert_eq!(market_data.pending_count(), 0);
+    }
+
+    #[test]
+    fn hidden_disclosure_waits_for_the_operator() {
+        let mut market_data = MarketData::new(DisclosurePolicy::Hidden);
+        for i in 0..20 {
+            market_data.record_private_fill(&fill(DAY + i, 100, 1));
+        }
+        assert_eq!(market_data.stats(DAY + 20).trade_count_24h, 0);
+
+        let trade = market_data.disclose_pending().unwrap();
+        assert_eq!((trade.price, trade.quantity, trade.timestamp), (100, 20, DAY + 19));
+        assert_eq!(market_data.stats(DAY + 20).trade_count_24h, 1);
+        assert!(market_data.disclose_pending().is_none());
+    }
+}
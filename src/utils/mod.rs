@@ -1,4 +1,17 @@
 pub mod orders;
 pub mod orderbook;
 pub mod generate_key;
+pub mod key_store;
 pub mod fhe_operations;
+pub mod ciphertext;
+pub mod encrypted_uint;
+pub mod fhe_params;
+pub mod fees;
+pub mod market_data;
+pub mod auction;
+pub mod encrypted_auction;
+pub mod oblivious;
+pub mod iceberg;
+pub mod triggers;
+pub mod pegged;
+pub mod snapshot;
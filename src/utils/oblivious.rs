@@ -0,0 +1,123 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::error::OrderbookError;
+use super::ciphertext::Ciphertext;
+use super::encrypted_uint::EncryptedUint;
+use super::fhe_operations;
+use super::iceberg;
+use super::orders::{Order, Side, now_millis};
+use tfhe::ClientKey;
+
+/// Encrypted result of comparing an incoming order with one resting order.
+/// One is produced for every pair swept, whether or not they traded, so the
+/// number of fills reveals nothing about the match pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedFill {
+    pub buy_order_id: u128,
+    pub sell_order_id: u128,
+    pub timestamp: u64,
+    // Sell price of the pair, used as the execution price
+    pub encrypted_price: Ciphertext,
+    // Quantity traded, an encryption of 0 when the pair did not cross
+    pub encrypted_quantity: Ciphertext,
+}
+
+fn encrypted_fields(order: &Order) -> Result<(&Ciphertext, &Ciphertext), OrderbookError> {
+    match (&order.encrypted_price, &order.encrypted_quantity) {
+        (Some(price), Some(quantity)) => Ok((price, quantity)),
+        _ => Err(OrderbookError::Conflict(format!("Order {} is not encrypted", order.id))),
+    }
+}
+
+/// Match an incoming encrypted order against every resting order on the opposite
+/// side. Each pair is compared homomorphically and the traded amount is chosen
+/// with an encrypted select, so every resting order is visited and rewritten in
+/// the same order no matter which ones cross. The encrypted quantities of the
+/// incoming and resting orders are replaced with their encrypted remainders.
+/// Resting icebergs only trade their encrypted visible slice, which is refilled
+/// from the reserve homomorphically. Replenished slices cannot lose time
+/// priority here, since moving them would reveal that they traded.
+/// The price comparisons are independent of each other, so they are evaluated
+/// in parallel on the FHE thread pool before the quantities are swept in order.
+pub fn sweep(incoming: &mut Order, resting: &mut [Order]) -> Result<Vec<EncryptedFill>, OrderbookError> {
+    let key_id = fhe_operations::ensure_server_key()?;
+
+    let (incoming_price, incoming_quantity) = encrypted_fields(incoming)?;
+    let incoming_price = incoming_price.clone();
+    let mut incoming_quantity = (**incoming_quantity).clone();
+    let zero = incoming_quantity.zero_like();
+    let now = now_millis();
+    let mut fills = Vec::with_capacity(resting.len());
+
+    let crossing = fhe_operations::fhe_pool()?.install(|| {
+        resting
+            .par_iter()
+            .map(|order| {
+                fhe_operations::ensure_server_key()?;
+                let (resting_price, _) = encrypted_fields(order)?;
+                match incoming.side {
+                    Side::Buy => incoming_price.ge(&**resting_price),
+                    Side::Sell => resting_price.ge(&*incoming_price),
+                }
+            })
+            .collect::<Result<Vec<EncryptedUint>, OrderbookError>>()
+    })?;
+
+    for (order, crosses) in resting.iter_mut().zip(crossing) {
+        let (resting_price, resting_quantity) = encrypted_fields(order)?;
+        let resting_price = resting_price.clone();
+        let mut resting_quantity = (**resting_quantity).clone();
+
+        let (buy_order_id, sell_order_id, sell_price) = match incoming.side {
+            Side::Buy => (incoming.id, order.id, &resting_price),
+            Side::Sell => (order.id, incoming.id, &incoming_price),
+        };
+
+        let available = if order.is_iceberg() {
+            iceberg::visible_quantity(order)?
+        } else {
+            resting_quantity.clone()
+        };
+
+        let amount = crosses.if_then_else(&incoming_quantity.min(&available)?, &zero)?;
+        incoming_quantity.sub_encrypted(&amount)?;
+        resting_quantity.sub_encrypted(&amount)?;
+
+        order.encrypted_quantity = Some(Ciphertext::new(key_id, resting_quantity));
+        if order.is_iceberg() {
+            iceberg::consume_encrypted(order, &amount)?;
+        }
+        order.updated_at = now;
+
+        fills.push(EncryptedFill {
+            buy_order_id,
+            sell_order_id,
+            timestamp: now,
+            encrypted_price: sell_price.clone(),
+            encrypted_quantity: Ciphertext::new(key_id, amount),
+        });
+    }
+
+    if incoming.is_iceberg() {
+        iceberg::reset_encrypted(incoming, &incoming_quantity)?;
+    }
+    incoming.encrypted_quantity = Some(Ciphertext::new(key_id, incoming_quantity));
+    incoming.updated_at = now;
+
+    Ok(fills)
+}
+
+/// Which orders have no encrypted quantity left. Each remainder is compared with
+/// zero on the FHE thread pool and only the resulting bit is decrypted.
+pub fn exhausted(orders: &[Order], client_key: &ClientKey) -> Result<Vec<bool>, OrderbookError> {
+    fhe_operations::fhe_pool()?.install(|| {
+        orders
+            .par_iter()
+            .map(|order| {
+                fhe_operations::ensure_server_key()?;
+                let (_, quantity) = encrypted_fields(order)?;
+                Ok(quantity.eq(0u32)?.decrypt(client_key) == 1)
+            })
+            .collect()
+    })
+}
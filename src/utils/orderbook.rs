@@ -1,14 +1,48 @@
-use super::orders::{Order, Side, Fill};
+use super::orders::{Order, OrderStatus, Side, Fill, now_millis};
 use super::fhe_operations;
-use super::generate_key;
-use std::sync::Arc;
-use tfhe::{ClientKey, ServerKey};
+use super::fees::FeeEngine;
+use super::market_data::MarketData;
+use super::auction::{self, AuctionResult, MatchingMode};
+use super::encrypted_auction::{self, EncryptedClearing, PriceGrid};
+use super::oblivious::{self, EncryptedFill};
+use super::iceberg;
+use super::triggers::{self, TradedRange, TriggerBook};
+use super::pegged::{self, TopOfBook};
+use super::snapshot::{BookSnapshot, SNAPSHOT_VERSION};
+use crate::error::OrderbookError;
+use std::cmp::Reverse;
+use super::generate_key::KeyId;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tfhe::ServerKey;
+
+// Market name used until the orderbook supports multiple markets
+pub const DEFAULT_MARKET: &str = "default";
+
+// Encrypted fills kept before the oldest are dropped; each holds two ciphertexts
+// of tens of kilobytes, and a sweep adds one per order on the opposite side
+pub const MAX_ENCRYPTED_FILLS: usize = 1_000;
 
 pub struct Orderbook {
+    pub market: String,
     pub count: u128,
     pub buy_orders: Vec<Order>,
     pub sell_orders: Vec<Order>,
     pub fills: Vec<Fill>,
+    // Fills produced by oblivious matching, readable only with the client key
+    pub encrypted_fills: Vec<EncryptedFill>,
+    pub next_trade_id: u64,
+    // Orders that have left the book (filled, cancelled, expired or rejected)
+    pub closed_orders: HashMap<u128, Order>,
+    pub fees: FeeEngine,
+    pub market_data: MarketData,
+    pub matching_mode: MatchingMode,
+    // Stop orders waiting for their trigger price
+    pub trigger_book: TriggerBook,
+    pub last_trade_price: Option<u32>,
+    // Prices traded since stop orders were last checked
+    pub traded_range: Option<TradedRange>,
+    // Best bid and ask of the resting limit orders, kept up to date for pegged orders
+    pub top_of_book: TopOfBook,
     pub server_key: Option<ServerKey>,
     pub use_encryption: bool,
 }
@@ -17,10 +51,21 @@ impl Orderbook {
     pub fn new(server_key: Option<ServerKey>) -> Self {
         let has_encryption = server_key.is_some();
         Self {
+            market: DEFAULT_MARKET.to_string(),
             count: 0,
             buy_orders: Vec::new(),
             sell_orders: Vec::new(),
             fills: Vec::new(),
+            encrypted_fills: Vec::new(),
+            next_trade_id: 1,
+            closed_orders: HashMap::new(),
+            fees: FeeEngine::default(),
+            market_data: MarketData::default(),
+            matching_mode: MatchingMode::default(),
+            trigger_book: TriggerBook::default(),
+            last_trade_price: None,
+            traded_range: None,
+            top_of_book: TopOfBook::default(),
             server_key,
             use_encryption: has_encryption,
         }
@@ -29,15 +74,167 @@ impl Orderbook {
     pub fn is_using_encryption(&self) -> bool {
         self.use_encryption
     }
+
+    /// Resting and waiting stop orders of an account
+    pub fn open_order_count(&self, user_pubkey: &str) -> usize {
+        self.buy_orders
+            .iter()
+            .chain(&self.sell_orders)
+            .chain(&self.trigger_book.orders)
+            .filter(|order| order.user_pubkey == user_pubkey)
+            .count()
+    }
     
-    pub fn set_use_encryption(&mut self, use_encryption: bool) -> bool {
-        // We can only enable encryption if we have a server key
-        if use_encryption && self.server_key.is_none() {
-            return false;
+    /// Turn encryption on or off, migrating every resting and waiting stop order
+    /// so the book never mixes encrypted and plaintext orders. Enabling encrypts
+    /// them under the active key; disabling decrypts them and drops their
+    /// ciphertexts. The book is left untouched unless every order migrates.
+    /// Closed orders and fills keep their ciphertexts. Returns the number of
+    /// orders migrated.
+    pub fn set_use_encryption(&mut self, use_encryption: bool) -> Result<usize, OrderbookError> {
+        self.configure(use_encryption, None)
+    }
+
+    // Switch matching modes. Leaving batch auction mode clears the pending batch
+    // first so the book is never left crossed under continuous matching.
+    pub fn set_matching_mode(&mut self, matching_mode: MatchingMode) -> Result<(), OrderbookError> {
+        self.configure(self.use_encryption, Some(matching_mode))?;
+        Ok(())
+    }
+
+    /// Change encryption, and the matching mode if one is given, as a whole.
+    /// Each change is checked against the configuration the book will end up
+    /// with before either is made, so a refused change leaves the book as it was.
+    /// Returns the number of orders migrated.
+    pub fn configure(&mut self, use_encryption: bool, matching_mode: Option<MatchingMode>) -> Result<usize, OrderbookError> {
+        let matching_mode = matching_mode.unwrap_or(self.matching_mode);
+        self.check_encryption(use_encryption, matching_mode)?;
+        self.check_matching_mode(matching_mode, use_encryption)?;
+
+        // Migrating encryption changes all orders or none, and so does an auction.
+        // A batch cleared on the way to continuous matching is cleared while the
+        // book is in plaintext if it ever is, where the auction cannot fail, so
+        // a failure never leaves one step done without the other
+        let mut clear_batch = self.matching_mode != MatchingMode::Continuous && matching_mode == MatchingMode::Continuous;
+        if clear_batch && !self.use_encryption {
+            self.run_auction()?;
+            clear_batch = false;
         }
-        
+        let migrated = self.migrate_encryption(use_encryption)?;
+        if clear_batch {
+            self.run_auction()?;
+        }
+        self.matching_mode = matching_mode;
+        Ok(migrated)
+    }
+
+    // Refuse turning encryption on or off when the book, under the matching mode
+    // it will have, could not follow
+    fn check_encryption(&self, use_encryption: bool, matching_mode: MatchingMode) -> Result<(), OrderbookError> {
+        if use_encryption == self.use_encryption {
+            return Ok(());
+        }
+
+        if use_encryption {
+            // We can only enable encryption if we have a server key, e.g. one generated since startup
+            if self.server_key.is_none() && fhe_operations::get_server_key().is_err() {
+                return Err(OrderbookError::KeyUnavailable(
+                    "Cannot enable encryption without FHE keys; generate them with /generate-keys".to_string()
+                ));
+            }
+            // Pegged orders are priced from the plaintext top of book
+            if self.has_pegged_orders() {
+                return Err(OrderbookError::Conflict("Cannot enable encryption while pegged orders are resting".to_string()));
+            }
+        } else if matching_mode == MatchingMode::Oblivious {
+            // Obliviously matched orders only hold encrypted remainders
+            return Err(OrderbookError::Conflict(
+                "Cannot disable encryption while oblivious matching is enabled".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    // Refuse a matching mode the book, with the encryption setting it will have, cannot switch to
+    fn check_matching_mode(&self, matching_mode: MatchingMode, use_encryption: bool) -> Result<(), OrderbookError> {
+        if matching_mode == (MatchingMode::BatchAuction { interval_ms: 0 }) {
+            return Err(OrderbookError::InvalidRequest("Batch auction interval must be greater than zero".to_string()));
+        }
+
+        // Obliviously matched orders only hold encrypted remainders, so they cannot
+        // be carried into or out of the other modes
+        let oblivious_change = (matching_mode == MatchingMode::Oblivious) != (self.matching_mode == MatchingMode::Oblivious);
+        if oblivious_change && !(self.buy_orders.is_empty() && self.sell_orders.is_empty()) {
+            return Err(OrderbookError::Conflict("Cannot switch to or from oblivious matching while orders are resting".to_string()));
+        }
+        if matching_mode == MatchingMode::Oblivious && !use_encryption {
+            return Err(OrderbookError::Conflict("Oblivious matching requires encryption to be enabled".to_string()));
+        }
+        if matching_mode != MatchingMode::Continuous && self.has_pegged_orders() {
+            return Err(OrderbookError::Conflict("Cannot leave continuous matching while pegged orders are resting".to_string()));
+        }
+        Ok(())
+    }
+
+    // Move every live order onto the new encryption setting, or none of them
+    fn migrate_encryption(&mut self, use_encryption: bool) -> Result<usize, OrderbookError> {
+        if use_encryption == self.use_encryption {
+            return Ok(0);
+        }
+
+        let (buy_orders, sell_orders, stop_orders, server_key) = if use_encryption {
+            let server_key = match &self.server_key {
+                Some(server_key) => server_key.clone(),
+                None => (*fhe_operations::get_server_key()?).clone(),
+            };
+            let client_key = fhe_operations::client_key()?;
+            let encrypt = |orders: &[Order]| {
+                orders
+                    .iter()
+                    .map(|order| {
+                        let mut encrypted = order.clone();
+                        fhe_operations::encrypt_order(&mut encrypted, &client_key)?;
+                        encrypted.is_encrypted = true;
+                        Ok(encrypted)
+                    })
+                    .collect::<Result<Vec<Order>, OrderbookError>>()
+            };
+            let stop_orders = self.trigger_book.orders
+                .iter()
+                .map(|order| {
+                    let mut encrypted = order.clone();
+                    fhe_operations::encrypt_trigger(&mut encrypted, &client_key)?;
+                    Ok(encrypted)
+                })
+                .collect::<Result<Vec<Order>, OrderbookError>>()?;
+            (encrypt(&self.buy_orders)?, encrypt(&self.sell_orders)?, stop_orders, Some(server_key))
+        } else {
+            let decrypt = |orders: &[Order]| {
+                orders
+                    .iter()
+                    .map(fhe_operations::decrypt_live_order)
+                    .collect::<Result<Vec<Order>, OrderbookError>>()
+            };
+            let (mut buy_orders, mut sell_orders) = (decrypt(&self.buy_orders)?, decrypt(&self.sell_orders)?);
+            // Encrypted books are kept in time order; plaintext books by price, best first
+            buy_orders.sort_by_key(|o| Reverse(o.price));
+            sell_orders.sort_by_key(|o| o.price);
+            (buy_orders, sell_orders, decrypt(&self.trigger_book.orders)?, self.server_key.clone())
+        };
+
+        let migrated = self.live_orders().count();
+        self.buy_orders = buy_orders;
+        self.sell_orders = sell_orders;
+        self.trigger_book.orders = stop_orders;
+        self.server_key = server_key;
         self.use_encryption = use_encryption;
-        true
+        // The top of book is only tracked for plaintext books
+        self.top_of_book = if use_encryption {
+            TopOfBook::default()
+        } else {
+            TopOfBook::from_book(&self.buy_orders, &self.sell_orders)
+        };
+        Ok(migrated)
     }
     
     pub fn new_encrypted() -> Self {
@@ -46,9 +243,9 @@ impl Orderbook {
             eprintln!("Failed to initialize FHE: {}", e);
         }
         
-        // Load server key
-        let server_key = match generate_key::load_server_key() {
-            Ok(key) => Some(key),
+        // Reuse the server key loaded by the FHE system
+        let server_key = match fhe_operations::get_server_key() {
+            Ok(key) => Some((*key).clone()),
             Err(e) => {
                 eprintln!("Failed to load server key: {}", e);
                 None
@@ -57,209 +254,773 @@ impl Orderbook {
         
         let has_encryption = server_key.is_some();
         Self {
+            market: DEFAULT_MARKET.to_string(),
             count: 0,
             buy_orders: Vec::new(),
             sell_orders: Vec::new(),
             fills: Vec::new(),
+            encrypted_fills: Vec::new(),
+            next_trade_id: 1,
+            closed_orders: HashMap::new(),
+            fees: FeeEngine::default(),
+            market_data: MarketData::default(),
+            matching_mode: MatchingMode::default(),
+            trigger_book: TriggerBook::default(),
+            last_trade_price: None,
+            traded_range: None,
+            top_of_book: TopOfBook::default(),
             server_key,
             use_encryption: has_encryption,
         }
     }
 
-    pub fn add_order(&mut self, mut order: Order) -> Order {
+    /// Add an order to the orderbook. Stop orders wait in the trigger book; any
+    /// other order is matched and rests as usual. The book is then settled:
+    /// triggered stop orders are activated and pegged orders are re-priced.
+    pub fn add_order(&mut self, order: Order) -> Order {
+        let id = order.id;
+        let result = if order.is_stop() {
+            self.add_stop_order(order)
+        } else {
+            self.enter_order(order)
+        };
+
+        self.settle_book();
+        self.get_order(id).unwrap_or(result)
+    }
+
+    // Activate triggered stop orders and re-price pegged orders until neither
+    // changes the book any further
+    fn settle_book(&mut self) {
+        loop {
+            self.process_triggers();
+            if !self.reprice_pegged_orders() {
+                break;
+            }
+        }
+    }
+
+    /// Re-price resting pegged orders whenever the top of book moves. Pegged
+    /// orders that cross the book at their new price trade as the aggressor,
+    /// which can move the top of book again. Returns whether any of them traded.
+    fn reprice_pegged_orders(&mut self) -> bool {
+        let mut traded = false;
+        loop {
+            // The top of book is only tracked for plaintext books, since it would reveal encrypted prices
+            let top = if self.use_encryption {
+                TopOfBook::default()
+            } else {
+                TopOfBook::from_book(&self.buy_orders, &self.sell_orders)
+            };
+            if top == self.top_of_book {
+                return traded;
+            }
+            self.top_of_book = top;
+
+            if !self.has_pegged_orders() {
+                return traded;
+            }
+            for order in self.buy_orders.iter_mut().chain(self.sell_orders.iter_mut()) {
+                pegged::reprice(order, &top);
+            }
+            self.buy_orders.sort_by_key(|o| Reverse(o.price));
+            self.sell_orders.sort_by_key(|o| o.price);
+
+            let crossing: Vec<u128> = self.buy_orders
+                .iter()
+                .filter(|buy| buy.is_pegged() && self.sell_orders.iter().any(|sell| self.crosses(buy, sell)))
+                .chain(self.sell_orders
+                    .iter()
+                    .filter(|sell| sell.is_pegged() && self.buy_orders.iter().any(|buy| self.crosses(buy, sell))))
+                .map(|order| order.id)
+                .collect();
+
+            for id in crossing {
+                // An earlier pegged order may already have traded this one away
+                let Some(mut order) = self.take_resting(id) else {
+                    continue;
+                };
+                let fill_count = self.fills.len();
+                match order.side {
+                    Side::Buy => self.try_match_buy_order(&mut order),
+                    Side::Sell => self.try_match_sell_order(&mut order),
+                }
+                traded |= self.fills.len() > fill_count;
+
+                if order.status == OrderStatus::Filled {
+                    self.archive_order(order);
+                } else {
+                    self.push_resting(order);
+                }
+            }
+        }
+    }
+
+    fn has_pegged_orders(&self) -> bool {
+        self.buy_orders.iter().chain(self.sell_orders.iter()).any(|order| order.is_pegged())
+    }
+
+    // Remove a resting order from the book
+    fn take_resting(&mut self, id: u128) -> Option<Order> {
+        let position = |orders: &Vec<Order>| orders.iter().position(|order| order.id == id);
+
+        if let Some(i) = position(&self.buy_orders) {
+            Some(self.buy_orders.remove(i))
+        } else {
+            position(&self.sell_orders).map(|i| self.sell_orders.remove(i))
+        }
+    }
+
+    fn add_stop_order(&mut self, mut order: Order) -> Order {
+        self.expire_orders(now_millis());
+
+        if order.quantity == 0 || order.trigger_price == Some(0) {
+            order.set_status(OrderStatus::Rejected);
+            self.archive_order(order.clone());
+            return order;
+        }
+
+        // Keep the trigger price encrypted so waiting stops do not reveal intent
+        if self.use_encryption && order.encrypted_trigger_price.is_none() {
+            let encrypted = fhe_operations::client_key()
+                .and_then(|client_key| fhe_operations::encrypt_trigger(&mut order, &client_key));
+            if let Err(e) = encrypted {
+                eprintln!("Failed to encrypt stop order {}: {}", order.id, e);
+                order.set_status(OrderStatus::Rejected);
+                self.archive_order(order.clone());
+                return order;
+            }
+        }
+
+        self.trigger_book.add(order.clone());
+        order
+    }
+
+    // Activate triggered stop orders until the last trade price stops triggering any
+    fn process_triggers(&mut self) {
+        let client_key = if self.trigger_book.has_encrypted_triggers() {
+            fhe_operations::client_key()
+                .map_err(|e| eprintln!("Failed to load client key for triggers: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        // Every price traded since the last check counts, not only the last one, so a
+        // sweep through several levels triggers stops at each of them. Without new
+        // trades, a stop already past the last trade price, e.g. one just placed, triggers.
+        while let Some(traded) = self.traded_range.take().or(self.last_trade_price.map(TradedRange::at)) {
+            let triggered = self.trigger_book.take_triggered(traded, client_key.as_deref());
+            if triggered.is_empty() {
+                break;
+            }
+            for order in triggered {
+                self.enter_order(triggers::activate(order));
+            }
+        }
+    }
+
+    // Match a non-stop order against the book and rest whatever remains
+    fn enter_order(&mut self, mut order: Order) -> Order {
+        let now = now_millis();
+        self.expire_orders(now);
+
+        // Reject empty orders and icebergs whose display size is zero or larger than the order
+        let invalid_display = order.display_quantity.is_some_and(|display| display == 0 || display > order.quantity);
+        // Pegged orders need the plaintext top of book and continuous matching
+        let invalid_peg = order.peg.is_some_and(|peg| {
+            peg.validate().is_err() || self.use_encryption || self.matching_mode != MatchingMode::Continuous
+        });
+        if order.quantity == 0 || invalid_display || invalid_peg {
+            order.set_status(OrderStatus::Rejected);
+            self.archive_order(order.clone());
+            return order;
+        }
+
+        if order.is_expired(now) {
+            order.set_status(OrderStatus::Expired);
+            self.archive_order(order.clone());
+            return order;
+        }
+
+        if order.is_pegged() {
+            let top = TopOfBook::from_book(&self.buy_orders, &self.sell_orders);
+            pegged::reprice(&mut order, &top);
+        }
+
         // If encryption is enabled and the order is not already encrypted, encrypt it
         if self.use_encryption && !order.is_encrypted {
-            match generate_key::load_client_key() {
-                Ok(client_key) => {
-                    fhe_operations::encrypt_order(&mut order, &client_key);
-                    order.is_encrypted = true;
-                }
+            let encrypted = fhe_operations::client_key()
+                .and_then(|client_key| fhe_operations::encrypt_order(&mut order, &client_key));
+            match encrypted {
+                Ok(()) => order.is_encrypted = true,
                 Err(e) => {
-                    eprintln!("Failed to load client key for encryption: {}", e);
+                    eprintln!("Failed to encrypt order {}: {}", order.id, e);
+                    order.set_status(OrderStatus::Rejected);
+                    self.archive_order(order.clone());
+                    return order;
                 }
             }
         }
         
-        // Try to match the order with existing orders
-        if order.side == Side::Buy {
-            self.try_match_buy_order(&order);
-        } else {
-            self.try_match_sell_order(&order);
+        // Try to match the order with existing orders; in batch auction mode
+        // orders rest until the next auction clears them
+        match self.matching_mode {
+            MatchingMode::Continuous => {
+                if order.side == Side::Buy {
+                    self.try_match_buy_order(&mut order);
+                } else {
+                    self.try_match_sell_order(&mut order);
+                }
+            }
+            MatchingMode::BatchAuction { .. } => {}
+            MatchingMode::Oblivious => {
+                if let Err(e) = self.match_obliviously(&mut order) {
+                    eprintln!("Oblivious matching failed: {}", e);
+                    order.set_status(OrderStatus::Rejected);
+                    self.archive_order(order.clone());
+                    return order;
+                }
+                // Whether the order traded is unknown, so it always rests
+                self.push_resting(order.clone());
+                return order;
+            }
+        }
+
+        // Fully filled orders never rest on the book
+        if order.status == OrderStatus::Filled {
+            self.archive_order(order.clone());
+            return order;
         }
         
-        // Add the order to the appropriate list
+        Self::reset_iceberg(&mut order);
+        self.push_resting(order.clone());
+        order
+    }
+
+    // Add the order to the appropriate list
+    fn push_resting(&mut self, order: Order) {
         match order.side {
             Side::Buy => {
-                self.buy_orders.push(order.clone());
+                self.buy_orders.push(order);
                 if !self.use_encryption {
                     // Sort by price (highest first) if not using encryption
-                    self.buy_orders.sort_by(|a, b| b.price.cmp(&a.price));
+                    self.buy_orders.sort_by_key(|o| Reverse(o.price));
                 }
             }
             Side::Sell => {
-                self.sell_orders.push(order.clone());
+                self.sell_orders.push(order);
                 if !self.use_encryption {
                     // Sort by price (lowest first) if not using encryption
-                    self.sell_orders.sort_by(|a, b| a.price.cmp(&b.price));
+                    self.sell_orders.sort_by_key(|o| o.price);
                 }
             }
         }
-        
-        order
     }
 
     pub fn get_orders(&self) -> (Vec<Order>, Vec<Order>) {
         (self.buy_orders.clone(), self.sell_orders.clone())
     }
+
+    // Look up an order by id, whether it is resting on the book or already closed
+    pub fn get_order(&self, id: u128) -> Option<Order> {
+        self.buy_orders
+            .iter()
+            .chain(self.sell_orders.iter())
+            .find(|order| order.id == id)
+            .or_else(|| self.trigger_book.get(id))
+            .or_else(|| self.closed_orders.get(&id))
+            .cloned()
+    }
     
     pub fn get_fills(&self) -> Vec<Fill> {
         self.fills.clone()
     }
 
-    // Try to match a buy order with existing sell orders
-    fn try_match_buy_order(&mut self, buy_order: &Order) {
-        if self.sell_orders.is_empty() {
-            return;
-        }
-        
-        // First collect all the matches without modifying self
-        let mut matches = Vec::new();
-        let mut matched_indices = Vec::new();
-        let mut remaining_quantity = buy_order.quantity;
-        
+    /// Fills after trade id `after`, for the public feed. An encrypted book only
+    /// publishes trades as its disclosure policy allows, aggregated into market
+    /// data, so its individual fills are not served.
+    pub fn public_fills(&self, after: u64) -> Result<Vec<Fill>, OrderbookError> {
         if self.use_encryption {
-            // Match using FHE operations
-            for (i, sell_order) in self.sell_orders.iter().enumerate() {
-                if remaining_quantity == 0 {
-                    break;
-                }
-                
-                // Check if the buy price is greater than or equal to the sell price
-                if fhe_operations::match_orders(buy_order, sell_order) {
-                    let match_quantity = remaining_quantity.min(sell_order.quantity);
-                    remaining_quantity -= match_quantity;
-                    
-                    // Store the match information
-                    matches.push((buy_order.clone(), sell_order.clone(), match_quantity));
-                    
-                    // If the sell order is fully matched, mark it for removal
-                    if match_quantity == sell_order.quantity {
-                        matched_indices.push(i);
-                    }
-                }
+            return Err(OrderbookError::Forbidden(
+                "Fills of an encrypted book are only published as market data, as its disclosure policy allows".to_string()
+            ));
+        }
+        Ok(self.fills.iter().filter(|fill| fill.trade_id > after).cloned().collect())
+    }
+
+    /// Encrypted fills swept after `after` (ms). Every fill of a sweep has the
+    /// same timestamp, so polling with the last timestamp seen misses none.
+    pub fn get_encrypted_fills(&self, after: u64) -> Vec<EncryptedFill> {
+        self.encrypted_fills.iter().filter(|fill| fill.timestamp > after).cloned().collect()
+    }
+
+    // Cancel a resting order and move it to the closed order history
+    pub fn cancel_order(&mut self, id: u128) -> Result<Order, OrderbookError> {
+        let mut order = if let Some(order) = self.take_resting(id) {
+            order
+        } else if let Some(order) = self.trigger_book.remove(id) {
+            order
+        } else if let Some(order) = self.closed_orders.get(&id) {
+            return Err(OrderbookError::OrderClosed { id, status: order.status });
+        } else {
+            return Err(OrderbookError::OrderNotFound(id));
+        };
+
+        order.set_status(OrderStatus::Cancelled);
+        self.archive_order(order.clone());
+        self.settle_book();
+        Ok(order)
+    }
+
+    // Move every resting order past its expiry time to the closed order history
+    pub fn expire_orders(&mut self, now: u64) {
+        for orders in [&mut self.buy_orders, &mut self.sell_orders] {
+            let (expired, live): (Vec<Order>, Vec<Order>) =
+                orders.drain(..).partition(|order| order.is_expired(now));
+            *orders = live;
+            for mut order in expired {
+                order.set_status(OrderStatus::Expired);
+                self.closed_orders.insert(order.id, order);
+            }
+        }
+
+        for mut order in self.trigger_book.take_expired(now) {
+            order.set_status(OrderStatus::Expired);
+            self.closed_orders.insert(order.id, order);
+        }
+    }
+
+    fn archive_order(&mut self, order: Order) {
+        self.closed_orders.insert(order.id, order);
+    }
+
+    // Move orders that can no longer trade from both sides of the book to the history
+    fn remove_closed_orders(&mut self) {
+        for orders in [&mut self.buy_orders, &mut self.sell_orders] {
+            let (closed, live): (Vec<Order>, Vec<Order>) =
+                orders.drain(..).partition(|order| order.status.is_terminal());
+            *orders = live;
+            for order in closed {
+                self.closed_orders.insert(order.id, order);
+            }
+        }
+    }
+
+    // Check whether one order's price is greater than or equal to another's
+    fn price_ge(&self, a: &Order, b: &Order) -> bool {
+        match (&a.encrypted_price, &b.encrypted_price) {
+            (Some(a_price), Some(b_price)) if self.use_encryption => {
+                fhe_operations::compare_prices(a_price, b_price).unwrap_or_else(|e| {
+                    eprintln!("Failed to compare orders {} and {}: {}", a.id, b.id, e);
+                    false
+                })
             }
+            _ => a.price >= b.price,
+        }
+    }
+
+    /// Clear every resting order at a single uniform price that maximizes matched
+    /// volume. Prices are only compared, so encrypted orders are cleared with
+    /// homomorphic comparisons and only the clearing price is decrypted.
+    pub fn run_auction(&mut self) -> Result<AuctionResult, OrderbookError> {
+        self.expire_orders(now_millis());
+
+        // Encrypted books are kept in time order, so capping them keeps the oldest orders
+        let (buy_orders, sell_orders) = if self.use_encryption {
+            let cap = |orders: &[Order]| orders.len().min(auction::MAX_ENCRYPTED_BATCH_ORDERS);
+            (&self.buy_orders[..cap(&self.buy_orders)], &self.sell_orders[..cap(&self.sell_orders)])
+        } else {
+            (&self.buy_orders[..], &self.sell_orders[..])
+        };
+
+        let ge = |a: &Order, b: &Order| self.price_ge(a, b);
+        let clearing = match auction::find_clearing(buy_orders, sell_orders, ge) {
+            Some(clearing) => clearing,
+            None => return Ok(AuctionResult::default()),
+        };
+
+        let clearing_price = if clearing.price_order.is_encrypted {
+            let (price, _) = fhe_operations::decrypt_order(clearing.price_order)?;
+            price
+        } else {
+            clearing.price_order.price
+        };
+
+        let buy_allocations = auction::allocate(buy_orders, Side::Buy, &clearing, ge);
+        let sell_allocations = auction::allocate(sell_orders, Side::Sell, &clearing, ge);
+        let trades = auction::pair_allocations(buy_orders, &buy_allocations, sell_orders, &sell_allocations);
+        let matched_volume = clearing.volume;
+
+        // No order is resting first in an auction, so the later order is treated as the taker
+        let mut trade_ids = Vec::new();
+        let mut buy_matches = Vec::new();
+        let mut sell_matches = Vec::new();
+        for (b, s, quantity) in trades {
+            let buy_order = self.buy_orders[b].clone();
+            let sell_order = self.sell_orders[s].clone();
+            let aggressor_side = if buy_order.id > sell_order.id { Side::Buy } else { Side::Sell };
+
+            let trade_id = self.record_fill(&buy_order, &sell_order, clearing_price, quantity, aggressor_side);
+            self.buy_orders[b].apply_fill(trade_id, quantity);
+            self.sell_orders[s].apply_fill(trade_id, quantity);
+            trade_ids.push(trade_id);
+            buy_matches.push((b, quantity));
+            sell_matches.push((s, quantity));
+        }
+
+        self.settle_auction_icebergs(Side::Buy, &buy_matches);
+        self.settle_auction_icebergs(Side::Sell, &sell_matches);
+        self.remove_closed_orders();
+
+        // Triggered stop orders join the next batch
+        self.settle_book();
+
+        Ok(AuctionResult {
+            clearing_price: Some(clearing_price),
+            matched_volume,
+            trade_ids,
+        })
+    }
+
+    /// Compute the uniform clearing price of the resting encrypted orders entirely
+    /// under encryption. Only the clearing price is revealed; each order's
+    /// allocation is returned encrypted for its owner to decrypt. The book itself
+    /// is left unchanged.
+    pub fn clear_encrypted_batch(&self, grid: &PriceGrid) -> Result<EncryptedClearing, OrderbookError> {
+        if !self.use_encryption {
+            return Err(OrderbookError::Conflict("Encrypted clearing requires encryption to be enabled".to_string()));
+        }
+
+        let client_key = fhe_operations::client_key()?;
+        encrypted_auction::clear_batch(&self.buy_orders, &self.sell_orders, grid, &client_key)
+    }
+
+    // Match an encrypted order against the whole opposite side without revealing
+    // which orders crossed. Plaintext copies of price and quantity are cleared,
+    // since the encrypted quantities become the only record of what remains.
+    fn match_obliviously(&mut self, order: &mut Order) -> Result<(), OrderbookError> {
+        if !order.is_encrypted {
+            return Err(OrderbookError::Conflict("Oblivious matching requires encrypted orders".to_string()));
+        }
+
+        let resting = match order.side {
+            Side::Buy => &mut self.sell_orders,
+            Side::Sell => &mut self.buy_orders,
+        };
+        let fills = oblivious::sweep(order, resting)?;
+        self.encrypted_fills.extend(fills);
+        let excess = self.encrypted_fills.len().saturating_sub(MAX_ENCRYPTED_FILLS);
+        self.encrypted_fills.drain(..excess);
+
+        order.price = 0;
+        order.quantity = 0;
+        order.visible_quantity = 0;
+        order.display_quantity = None;
+        Ok(())
+    }
+
+    /// Close the resting orders of oblivious matching that have nothing left to
+    /// trade. Each encrypted remainder is compared with zero homomorphically and
+    /// only that bit is decrypted, so compaction reveals which orders are used
+    /// up, but not when or against which orders they traded. Returns the number
+    /// of orders closed.
+    pub fn compact_oblivious_orders(&mut self) -> Result<usize, OrderbookError> {
+        if self.matching_mode != MatchingMode::Oblivious {
+            return Ok(0);
+        }
+
+        let client_key = fhe_operations::client_key()?;
+        let mut exhausted_orders = Vec::new();
+        for orders in [&mut self.buy_orders, &mut self.sell_orders] {
+            let exhausted = oblivious::exhausted(orders, &client_key)?;
+            let mut exhausted = exhausted.into_iter();
+            let (closed, live): (Vec<Order>, Vec<Order>) =
+                orders.drain(..).partition(|_| exhausted.next().unwrap_or(false));
+            *orders = live;
+            exhausted_orders.extend(closed);
+        }
+
+        let closed = exhausted_orders.len();
+        for mut order in exhausted_orders {
+            order.set_status(OrderStatus::Filled);
+            self.archive_order(order);
+        }
+        Ok(closed)
+    }
+
+    // Check whether a buy order crosses a sell order
+    fn crosses(&self, buy_order: &Order, sell_order: &Order) -> bool {
+        if !pegged::is_priced(buy_order) || !pegged::is_priced(sell_order) {
+            false
+        } else if buy_order.is_market() && sell_order.is_market() {
+            // Neither has a price to trade at
+            false
+        } else if self.use_encryption {
+            // Match using FHE operations
+            fhe_operations::match_orders(buy_order, sell_order).unwrap_or_else(|e| {
+                eprintln!("Failed to match orders {} and {}: {}", buy_order.id, sell_order.id, e);
+                false
+            })
         } else {
             // Match using plaintext comparison
+            buy_order.price >= sell_order.price
+        }
+    }
+
+    /// Ids of the resting orders on the opposite side that an incoming order
+    /// crosses. Encrypted prices are compared as one parallel batch up front, so
+    /// matching can then walk the book in priority order without further FHE work.
+    fn crossing_ids(&self, incoming: &Order) -> HashSet<u128> {
+        let resting = match incoming.side {
+            Side::Buy => &self.sell_orders,
+            Side::Sell => &self.buy_orders,
+        };
+        let crosses = |order: &Order| match incoming.side {
+            Side::Buy => self.crosses(incoming, order),
+            Side::Sell => self.crosses(order, incoming),
+        };
+
+        if !self.use_encryption || !incoming.is_encrypted {
+            return resting.iter().filter(|order| crosses(order)).map(|order| order.id).collect();
+        }
+
+        let mask = fhe_operations::client_key()
+            .and_then(|client_key| fhe_operations::crossing_batch(incoming, resting, &client_key));
+        match mask {
+            Ok(mask) => resting
+                .iter()
+                .zip(mask)
+                .filter(|(order, crosses)| *crosses && !(order.is_market() && incoming.is_market()))
+                .map(|(order, _)| order.id)
+                .collect(),
+            Err(e) => {
+                eprintln!("Failed to compare order {} with the book: {}", incoming.id, e);
+                HashSet::new()
+            }
+        }
+    }
+
+    // Try to match a buy order with existing sell orders
+    fn try_match_buy_order(&mut self, buy_order: &mut Order) {
+        let crossing = self.crossing_ids(buy_order);
+
+        // Each pass trades against the visible quantity of every crossing order;
+        // replenished iceberg slices can be reached by a later pass
+        while buy_order.remaining_quantity() > 0 && !self.sell_orders.is_empty() {
+            // First collect all the matches without modifying self
+            let mut matches = Vec::new();
+            let mut remaining_quantity = buy_order.remaining_quantity();
+            
             for (i, sell_order) in self.sell_orders.iter().enumerate() {
                 if remaining_quantity == 0 {
                     break;
                 }
                 
                 // Check if the buy price is greater than or equal to the sell price
-                if buy_order.price >= sell_order.price {
-                    let match_quantity = remaining_quantity.min(sell_order.quantity);
+                if crossing.contains(&sell_order.id) {
+                    let match_quantity = remaining_quantity.min(sell_order.available_quantity());
                     remaining_quantity -= match_quantity;
-                    
-                    // Store the match information
-                    matches.push((buy_order.clone(), sell_order.clone(), match_quantity));
-                    
-                    // If the sell order is fully matched, mark it for removal
-                    if match_quantity == sell_order.quantity {
-                        matched_indices.push(i);
-                    }
+                    matches.push((i, match_quantity));
                 }
             }
-        }
-        
-        // Now record all the fills
-        for (buy, sell, quantity) in matches {
-            self.record_fill(&buy, &sell, quantity);
-        }
-        
-        // Remove matched orders (in reverse order to maintain indices)
-        for i in matched_indices.iter().rev() {
-            self.sell_orders.remove(*i);
+
+            if matches.iter().all(|(_, quantity)| *quantity == 0) {
+                break;
+            }
+            
+            // Now record all the fills against both orders
+            for &(i, quantity) in &matches {
+                let sell_order = self.sell_orders[i].clone();
+                let price = Self::execution_price(&sell_order, buy_order);
+                let fill_id = self.record_fill(buy_order, &sell_order, price, quantity, Side::Buy);
+                buy_order.apply_fill(fill_id, quantity);
+                Self::apply_resting_fill(&mut self.sell_orders[i], fill_id, quantity);
+            }
+            
+            self.replenish_icebergs(Side::Sell, &matches);
+            self.remove_closed_orders();
         }
     }
     
     // Try to match a sell order with existing buy orders
-    fn try_match_sell_order(&mut self, sell_order: &Order) {
-        if self.buy_orders.is_empty() {
-            return;
-        }
-        
-        // First collect all the matches without modifying self
-        let mut matches = Vec::new();
-        let mut matched_indices = Vec::new();
-        let mut remaining_quantity = sell_order.quantity;
-        
-        if self.use_encryption {
-            // Match using FHE operations
+    fn try_match_sell_order(&mut self, sell_order: &mut Order) {
+        let crossing = self.crossing_ids(sell_order);
+
+        // Each pass trades against the visible quantity of every crossing order;
+        // replenished iceberg slices can be reached by a later pass
+        while sell_order.remaining_quantity() > 0 && !self.buy_orders.is_empty() {
+            // First collect all the matches without modifying self
+            let mut matches = Vec::new();
+            let mut remaining_quantity = sell_order.remaining_quantity();
+            
             for (i, buy_order) in self.buy_orders.iter().enumerate() {
                 if remaining_quantity == 0 {
                     break;
                 }
                 
                 // Check if the buy price is greater than or equal to the sell price
-                if fhe_operations::match_orders(buy_order, sell_order) {
-                    let match_quantity = remaining_quantity.min(buy_order.quantity);
+                if crossing.contains(&buy_order.id) {
+                    let match_quantity = remaining_quantity.min(buy_order.available_quantity());
                     remaining_quantity -= match_quantity;
-                    
-                    // Store the match information
-                    matches.push((buy_order.clone(), sell_order.clone(), match_quantity));
-                    
-                    // If the buy order is fully matched, mark it for removal
-                    if match_quantity == buy_order.quantity {
-                        matched_indices.push(i);
-                    }
+                    matches.push((i, match_quantity));
                 }
             }
+
+            if matches.iter().all(|(_, quantity)| *quantity == 0) {
+                break;
+            }
+            
+            // Now record all the fills against both orders
+            for &(i, quantity) in &matches {
+                let buy_order = self.buy_orders[i].clone();
+                let price = Self::execution_price(&buy_order, sell_order);
+                let fill_id = self.record_fill(&buy_order, sell_order, price, quantity, Side::Sell);
+                sell_order.apply_fill(fill_id, quantity);
+                Self::apply_resting_fill(&mut self.buy_orders[i], fill_id, quantity);
+            }
+            
+            self.replenish_icebergs(Side::Buy, &matches);
+            self.remove_closed_orders();
+        }
+    }
+
+    // Trade at the resting (maker) order's price, except that midpoint orders
+    // only ever trade at the mid they are pegged to. Market orders carry a
+    // sentinel rather than a price, so a resting market remainder trades at the
+    // incoming order's limit price.
+    fn execution_price(maker: &Order, taker: &Order) -> u32 {
+        if let Some(midpoint) = [maker, taker].into_iter().find(|order| order.is_midpoint()) {
+            midpoint.price
+        } else if maker.is_market() {
+            taker.price
         } else {
-            // Match using plaintext comparison
-            for (i, buy_order) in self.buy_orders.iter().enumerate() {
-                if remaining_quantity == 0 {
-                    break;
-                }
-                
-                // Check if the buy price is greater than or equal to the sell price
-                if buy_order.price >= sell_order.price {
-                    let match_quantity = remaining_quantity.min(buy_order.quantity);
-                    remaining_quantity -= match_quantity;
-                    
-                    // Store the match information
-                    matches.push((buy_order.clone(), sell_order.clone(), match_quantity));
-                    
-                    // If the buy order is fully matched, mark it for removal
-                    if match_quantity == buy_order.quantity {
-                        matched_indices.push(i);
-                    }
-                }
+            maker.price
+        }
+    }
+
+    // Apply a fill to a resting order, drawing encrypted icebergs down homomorphically
+    fn apply_resting_fill(order: &mut Order, fill_id: u64, quantity: u32) {
+        if quantity == 0 {
+            return;
+        }
+        order.apply_fill(fill_id, quantity);
+        if order.is_encrypted && order.is_iceberg()
+            && let Err(e) = iceberg::consume_clear(order, quantity)
+        {
+            eprintln!("Failed to update encrypted iceberg {}: {}", order.id, e);
+        }
+    }
+
+    // Show a new slice for every exhausted iceberg among the given orders. A
+    // replenished slice loses time priority and moves to the back of its price level.
+    fn replenish_icebergs(&mut self, side: Side, matches: &[(usize, u32)]) {
+        let orders = match side {
+            Side::Buy => &mut self.buy_orders,
+            Side::Sell => &mut self.sell_orders,
+        };
+
+        let replenished: Vec<u128> = matches
+            .iter()
+            .filter_map(|&(i, _)| orders[i].replenish().then_some(orders[i].id))
+            .collect();
+        self.requeue(side, &replenished);
+    }
+
+    // After an auction, which may trade into an iceberg's reserve, show a fresh
+    // slice for every iceberg that traded. Those whose slice ran out lose time priority.
+    fn settle_auction_icebergs(&mut self, side: Side, matches: &[(usize, u32)]) {
+        let orders = match side {
+            Side::Buy => &mut self.buy_orders,
+            Side::Sell => &mut self.sell_orders,
+        };
+
+        let mut exhausted = Vec::new();
+        for &(i, _) in matches {
+            let order = &mut orders[i];
+            if !order.is_iceberg() {
+                continue;
+            }
+            if order.visible_quantity == 0 && order.remaining_quantity() > 0 {
+                exhausted.push(order.id);
             }
+            Self::reset_iceberg(order);
         }
-        
-        // Now record all the fills
-        for (buy, sell, quantity) in matches {
-            self.record_fill(&buy, &sell, quantity);
+        self.requeue(side, &exhausted);
+    }
+
+    // Move the given orders to the back of their price level
+    fn requeue(&mut self, side: Side, ids: &[u128]) {
+        if ids.is_empty() {
+            return;
         }
-        
-        // Remove matched orders (in reverse order to maintain indices)
-        for i in matched_indices.iter().rev() {
-            self.buy_orders.remove(*i);
+
+        let orders = match side {
+            Side::Buy => &mut self.buy_orders,
+            Side::Sell => &mut self.sell_orders,
+        };
+        let (moved, kept): (Vec<Order>, Vec<Order>) =
+            orders.drain(..).partition(|order| ids.contains(&order.id));
+        *orders = kept;
+        for order in moved {
+            self.push_resting(order);
+        }
+    }
+
+    // Set up the visible slice of an incoming iceberg before it rests on the book
+    fn reset_iceberg(order: &mut Order) {
+        order.reset_visible();
+        if order.is_encrypted && order.is_iceberg()
+            && let Err(e) = iceberg::refresh_encrypted(order)
+        {
+            eprintln!("Failed to update encrypted iceberg {}: {}", order.id, e);
         }
     }
     
-    // Record a fill between a buy and sell order
-    fn record_fill(&mut self, buy_order: &Order, sell_order: &Order, quantity: u32) {
+    // Record a fill between a buy and sell order at the given price, returning its trade id.
+    // The aggressor is the incoming order; the resting order is the maker.
+    // Maker and taker fees are charged from the current fee schedule.
+    fn record_fill(&mut self, buy_order: &Order, sell_order: &Order, price: u32, quantity: u32, aggressor_side: Side) -> u64 {
+        let trade_id = self.next_trade_id;
+        self.next_trade_id += 1;
+
+        let (maker, taker) = match aggressor_side {
+            Side::Buy => (sell_order, buy_order),
+            Side::Sell => (buy_order, sell_order),
+        };
+
+        let fees = self.fees.apply(&self.market, &maker.user_pubkey, &taker.user_pubkey, price, quantity);
+
+        self.last_trade_price = Some(price);
+        match &mut self.traded_range {
+            Some(traded) => traded.include(price),
+            None => self.traded_range = Some(TradedRange::at(price)),
+        }
+
         let fill = Fill {
+            trade_id,
+            timestamp: now_millis(),
             buy_order_id: buy_order.id,
             sell_order_id: sell_order.id,
-            price: sell_order.price, // Use the sell price as the execution price
+            price,
             quantity,
             buyer_pubkey: buy_order.user_pubkey.clone(),
             seller_pubkey: sell_order.user_pubkey.clone(),
+            aggressor_side,
+            maker_order_id: maker.id,
+            taker_order_id: taker.id,
+            fees,
         };
         
+        // Encrypted orderbooks only publish trades allowed by the disclosure policy
+        if self.use_encryption {
+            self.market_data.record_private_fill(&fill);
+        } else {
+            self.market_data.record_public_fill(&fill);
+        }
+        
         self.fills.push(fill);
+        trade_id
     }
 
     pub fn market_buy(&mut self, quantity: u32, user_pubkey: String) -> Option<Order> {
@@ -269,13 +1030,7 @@ impl Orderbook {
         
         // Create a market buy order with a very high price to ensure it matches
         self.count += 1;
-        let market_order = Order::new(
-            self.count,
-            u32::MAX, // Maximum price to ensure it matches with any sell order
-            quantity,
-            Side::Buy,
-            user_pubkey,
-        );
+        let market_order = Order::new_market(self.count, quantity, Side::Buy, user_pubkey);
         
         // Add the order to the orderbook (which will trigger matching)
         let result = self.add_order(market_order);
@@ -289,13 +1044,7 @@ impl Orderbook {
         
         // Create a market sell order with a very low price to ensure it matches
         self.count += 1;
-        let market_order = Order::new(
-            self.count,
-            0, // Minimum price to ensure it matches with any buy order
-            quantity,
-            Side::Sell,
-            user_pubkey,
-        );
+        let market_order = Order::new_market(self.count, quantity, Side::Sell, user_pubkey);
         
         // Add the order to the orderbook (which will trigger matching)
         let result = self.add_order(market_order);
@@ -303,51 +1052,571 @@ impl Orderbook {
     }
         
     // Get decrypted orders (for display purposes)
-    pub fn get_decrypted_orders(&self) -> Result<(Vec<Order>, Vec<Order>), String> {
+    pub fn get_decrypted_orders(&self) -> Result<(Vec<Order>, Vec<Order>), OrderbookError> {
         if !self.use_encryption {
             return Ok(self.get_orders());
         }
         
-        let client_key = match generate_key::load_client_key() {
-            Ok(key) => key,
-            Err(e) => return Err(format!("Failed to load client key: {}", e)),
-        };
+        let mut decrypted_buy_orders = self.buy_orders
+            .iter()
+            .map(|order| self.decrypt_for_display(order))
+            .collect::<Result<Vec<Order>, OrderbookError>>()?;
+        let mut decrypted_sell_orders = self.sell_orders
+            .iter()
+            .map(|order| self.decrypt_for_display(order))
+            .collect::<Result<Vec<Order>, OrderbookError>>()?;
         
-        let mut decrypted_buy_orders = Vec::new();
-        let mut decrypted_sell_orders = Vec::new();
+        // Sort the decrypted orders
+        decrypted_buy_orders.sort_by_key(|o| Reverse(o.price)); // Highest first
+        decrypted_sell_orders.sort_by_key(|o| o.price); // Lowest first
         
-        // Decrypt buy orders
-        for order in &self.buy_orders {
-            if order.is_encrypted {
-                let (price, quantity) = fhe_operations::decrypt_order(order, &client_key);
-                let mut decrypted = order.clone();
-                decrypted.price = price;
-                decrypted.quantity = quantity;
-                decrypted.is_encrypted = false;
-                decrypted_buy_orders.push(decrypted);
-            } else {
-                decrypted_buy_orders.push(order.clone());
+        Ok((decrypted_buy_orders, decrypted_sell_orders))
+    }
+
+    // Stop orders waiting in the trigger book. Encrypted trigger prices are never
+    // decrypted for display, since only the activation bit may be revealed.
+    pub fn get_stop_orders(&self) -> Vec<Order> {
+        self.trigger_book.orders.clone()
+    }
+
+    // Get a single decrypted order by id (for display purposes)
+    pub fn get_decrypted_order(&self, id: u128) -> Result<Order, OrderbookError> {
+        let order = self.get_order(id).ok_or(OrderbookError::OrderNotFound(id))?;
+        self.decrypt_for_display(&order)
+    }
+
+    // Replace an order's plaintext price and quantity with their decrypted values.
+    // Each value is decrypted with the keys it was encrypted under, so orders that
+    // left the book before a key rotation can still be read. Trigger prices stay
+    // encrypted, and so do quantities under oblivious matching, where comparing
+    // two listings would otherwise reveal which orders traded and how much.
+    fn decrypt_for_display(&self, order: &Order) -> Result<Order, OrderbookError> {
+        let mut decrypted = order.clone();
+        if !order.is_encrypted {
+            return Ok(decrypted);
+        }
+        if self.matching_mode == MatchingMode::Oblivious {
+            if let Some(encrypted_price) = &order.encrypted_price {
+                decrypted.price = fhe_operations::decrypt_u32(encrypted_price)?;
             }
+            return Ok(decrypted);
         }
-        
-        // Decrypt sell orders
-        for order in &self.sell_orders {
-            if order.is_encrypted {
-                let (price, quantity) = fhe_operations::decrypt_order(order, &client_key);
-                let mut decrypted = order.clone();
-                decrypted.price = price;
-                decrypted.quantity = quantity;
-                decrypted.is_encrypted = false;
-                decrypted_sell_orders.push(decrypted);
-            } else {
-                decrypted_sell_orders.push(order.clone());
+        let (price, quantity) = fhe_operations::decrypt_order(order)?;
+        decrypted.price = price;
+        decrypted.quantity = quantity;
+        decrypted.is_encrypted = false;
+        Ok(decrypted)
+    }
+
+    // Resting orders and waiting stop orders, whose ciphertexts must follow the active key
+    fn live_orders(&self) -> impl Iterator<Item = &Order> {
+        self.buy_orders.iter().chain(&self.sell_orders).chain(&self.trigger_book.orders)
+    }
+
+    /// Check that the book can be moved onto new keys: every live ciphertext must
+    /// be decryptable and match the parameters of its keys, or re-encrypting it
+    /// would strand the order.
+    pub fn check_key_rotation(&self) -> Result<(), OrderbookError> {
+        let key_ids: BTreeSet<KeyId> = self.live_orders()
+            .flat_map(|order| order.ciphertexts().map(|ciphertext| ciphertext.key_id()))
+            .collect();
+        for key_id in key_ids {
+            fhe_operations::client_key_for(key_id).map_err(|e| {
+                OrderbookError::Conflict(format!("Cannot rotate keys while orders are encrypted under unusable key {}: {}", key_id, e))
+            })?;
+        }
+        for ciphertext in self.live_orders().flat_map(|order| order.ciphertexts()) {
+            fhe_operations::verify_ciphertext(ciphertext)?;
+        }
+        Ok(())
+    }
+
+    /// Re-encrypt every resting and waiting stop order under key version `key_id`,
+    /// then make it the active key. The book is left untouched unless every order
+    /// re-encrypts. Past fills and closed orders keep their original keys, which
+    /// stay available for decryption. Returns the number of orders re-encrypted.
+    pub fn rotate_keys(&mut self, key_id: KeyId) -> Result<usize, OrderbookError> {
+        let client_key = fhe_operations::client_key_for(key_id)?;
+        let server_key = fhe_operations::server_key_for(key_id)?;
+        let reencrypt = |orders: &[Order]| {
+            orders
+                .iter()
+                .map(|order| fhe_operations::reencrypt_order(order, &client_key))
+                .collect::<Result<Vec<Order>, OrderbookError>>()
+        };
+        let buy_orders = reencrypt(&self.buy_orders)?;
+        let sell_orders = reencrypt(&self.sell_orders)?;
+        let stop_orders = reencrypt(&self.trigger_book.orders)?;
+
+        let reencrypted = self.live_orders()
+            .filter(|order| order.ciphertexts().any(|ciphertext| ciphertext.key_id() != key_id))
+            .count();
+
+        // Activating the key is the last step that can fail, so the book and the
+        // active key always change together
+        fhe_operations::activate_key(key_id)?;
+        self.server_key = Some((*server_key).clone());
+        self.buy_orders = buy_orders;
+        self.sell_orders = sell_orders;
+        self.trigger_book.orders = stop_orders;
+        Ok(reencrypted)
+    }
+
+    /// Capture the book so it can be saved and restored later
+    pub fn snapshot(&self) -> BookSnapshot {
+        let mut closed_orders: Vec<Order> = self.closed_orders.values().cloned().collect();
+        closed_orders.sort_by_key(|order| order.id);
+
+        BookSnapshot {
+            version: SNAPSHOT_VERSION,
+            taken_at: now_millis(),
+            market: self.market.clone(),
+            count: self.count,
+            next_trade_id: self.next_trade_id,
+            use_encryption: self.use_encryption,
+            matching_mode: self.matching_mode,
+            disclosure_policy: self.market_data.disclosure_policy,
+            fee_schedule: self.fees.schedule.clone(),
+            buy_orders: self.buy_orders.clone(),
+            sell_orders: self.sell_orders.clone(),
+            stop_orders: self.trigger_book.orders.clone(),
+            closed_orders,
+            fills: self.fills.clone(),
+            encrypted_fills: self.encrypted_fills.clone(),
+            last_trade_price: self.last_trade_price,
+            user_fees: self.fees.users.clone(),
+            market_fees: self.fees.markets.clone(),
+        }
+    }
+
+    /// Replace the book with a snapshot. Every ciphertext must match the parameters
+    /// of the key version it names, and live orders encrypted under an older key
+    /// are re-encrypted under the active one. The book is left untouched unless
+    /// the whole snapshot can be restored. Returns the number of orders re-encrypted.
+    pub fn restore(&mut self, snapshot: BookSnapshot) -> Result<usize, OrderbookError> {
+        if snapshot.use_encryption && self.server_key.is_none() {
+            return Err(OrderbookError::KeyUnavailable("Restoring an encrypted snapshot needs the FHE keys".to_string()));
+        }
+        let fill_ciphertexts = snapshot.encrypted_fills
+            .iter()
+            .flat_map(|fill| [&fill.encrypted_price, &fill.encrypted_quantity]);
+        for ciphertext in snapshot.closed_orders.iter().flat_map(|order| order.ciphertexts()).chain(fill_ciphertexts) {
+            fhe_operations::verify_ciphertext(ciphertext)?;
+        }
+
+        let mut book = Orderbook::new(self.server_key.clone());
+        book.use_encryption = snapshot.use_encryption;
+        book.market = snapshot.market;
+        book.count = snapshot.count;
+        book.next_trade_id = snapshot.next_trade_id;
+        book.matching_mode = snapshot.matching_mode;
+        book.market_data = MarketData::new(snapshot.disclosure_policy);
+        book.fees = FeeEngine::new(snapshot.fee_schedule);
+        book.fees.users = snapshot.user_fees;
+        book.fees.markets = snapshot.market_fees;
+        book.buy_orders = snapshot.buy_orders;
+        book.sell_orders = snapshot.sell_orders;
+        book.trigger_book.orders = snapshot.stop_orders;
+        book.closed_orders = snapshot.closed_orders.into_iter().map(|order| (order.id, order)).collect();
+        book.fills = snapshot.fills;
+        book.encrypted_fills = snapshot.encrypted_fills;
+        book.last_trade_price = snapshot.last_trade_price;
+        if !book.use_encryption {
+            book.top_of_book = TopOfBook::from_book(&book.buy_orders, &book.sell_orders);
+        }
+
+        // Live ciphertexts are verified here, and moved onto the active key if needed
+        book.check_key_rotation()?;
+        let mut reencrypted = 0;
+        if book.live_orders().any(|order| order.ciphertexts().next().is_some()) {
+            let active = fhe_operations::active_key_id()?;
+            if book.live_orders().flat_map(|order| order.ciphertexts()).any(|ciphertext| ciphertext.key_id() != active) {
+                reencrypted = book.rotate_keys(active)?;
             }
         }
-        
-        // Sort the decrypted orders
-        decrypted_buy_orders.sort_by(|a, b| b.price.cmp(&a.price)); // Highest first
-        decrypted_sell_orders.sort_by(|a, b| a.price.cmp(&b.price)); // Lowest first
-        
-        Ok((decrypted_buy_orders, decrypted_sell_orders))
+
+        *self = book;
+        Ok(reencrypted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fhe_operations::test_keys;
+    use crate::utils::market_data::DisclosurePolicy;
+    use crate::utils::pegged::{Peg, PegReference};
+
+    fn encrypted_book() -> Orderbook {
+        Orderbook::new(Some((*fhe_operations::get_server_key().unwrap()).clone()))
+    }
+
+    // Place a limit order the way the API does, numbering it from the book's count
+    fn limit(book: &mut Orderbook, price: u32, quantity: u32, side: Side, user_pubkey: &str) -> Order {
+        book.count += 1;
+        book.add_order(Order::new(book.count, price, quantity, side, user_pubkey.to_string()))
+    }
+
+    fn stop(book: &mut Orderbook, trigger_price: u32, limit_price: Option<u32>, quantity: u32, side: Side) -> Order {
+        book.count += 1;
+        book.add_order(Order::new_stop(book.count, trigger_price, limit_price, quantity, side, "stopper".to_string()))
+    }
+
+    fn traded(book: &Orderbook) -> Vec<(u32, u32)> {
+        book.fills.iter().map(|fill| (fill.price, fill.quantity)).collect()
+    }
+
+    #[test]
+    fn market_sell_trades_at_the_resting_bids() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 99, 5, Side::Buy, "bob");
+
+        let order = book.market_sell(7, "carol".to_string()).unwrap();
+
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(traded(&book), vec![(100, 5), (99, 2)]);
+        assert_eq!(book.last_trade_price, Some(99));
+    }
+
+    #[test]
+    fn incoming_limit_order_trades_at_the_maker_price() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 95, 5, Side::Sell, "bob");
+
+        assert_eq!(traded(&book), vec![(100, 5)]);
+    }
+
+    #[test]
+    fn resting_market_remainder_trades_at_the_incoming_price() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 101, 5, Side::Sell, "alice");
+        book.market_buy(10, "bob".to_string()).unwrap();
+        limit(&mut book, 103, 5, Side::Sell, "carol");
+
+        assert_eq!(traded(&book), vec![(101, 5), (103, 5)]);
+    }
+
+    #[test]
+    fn market_orders_do_not_trade_with_each_other() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 101, 5, Side::Sell, "alice");
+        book.market_buy(10, "bob".to_string()).unwrap();
+        let order = book.market_sell(5, "carol".to_string()).unwrap();
+
+        assert_eq!(traded(&book), vec![(101, 5)]);
+        assert_eq!(order.filled_quantity, 0);
+    }
+
+    #[test]
+    fn sweep_fires_only_stops_within_the_traded_prices() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 99, 5, Side::Buy, "bob");
+        limit(&mut book, 90, 5, Side::Buy, "bob");
+        limit(&mut book, 110, 5, Side::Sell, "alice");
+        let inside = stop(&mut book, 99, Some(99), 2, Side::Sell);
+        let below = stop(&mut book, 95, None, 2, Side::Sell);
+        let above = stop(&mut book, 101, None, 2, Side::Buy);
+
+        book.market_sell(7, "carol".to_string()).unwrap();
+
+        assert_eq!(traded(&book), vec![(100, 5), (99, 2), (99, 2)]);
+        assert_eq!(book.get_order(inside.id).unwrap().status, OrderStatus::Filled);
+        let waiting: Vec<u128> = book.trigger_book.orders.iter().map(|order| order.id).collect();
+        assert_eq!(waiting, vec![below.id, above.id]);
+    }
+
+    #[test]
+    fn triggered_stops_cascade_through_the_book() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 95, 5, Side::Buy, "bob");
+        limit(&mut book, 90, 5, Side::Buy, "bob");
+        let first = stop(&mut book, 100, None, 5, Side::Sell);
+        let second = stop(&mut book, 95, None, 5, Side::Sell);
+
+        book.market_sell(5, "carol".to_string()).unwrap();
+
+        assert_eq!(traded(&book), vec![(100, 5), (95, 5), (90, 5)]);
+        assert!(book.trigger_book.orders.is_empty());
+        assert_eq!(book.get_order(first.id).unwrap().status, OrderStatus::Filled);
+        assert_eq!(book.get_order(second.id).unwrap().status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn oblivious_listings_hide_quantities() {
+        let _keys = test_keys();
+        let mut book = encrypted_book();
+        book.set_matching_mode(MatchingMode::Oblivious).unwrap();
+        let buy = limit(&mut book, 100, 10, Side::Buy, "alice");
+        let sell = limit(&mut book, 99, 4, Side::Sell, "bob");
+
+        let (buys, sells) = book.get_decrypted_orders().unwrap();
+        for order in buys.iter().chain(&sells) {
+            assert!(order.is_encrypted);
+            assert_eq!((order.quantity, order.visible_quantity, order.filled_quantity), (0, 0, 0));
+        }
+        assert_eq!((buys[0].id, buys[0].price), (buy.id, 100));
+        assert_eq!((sells[0].id, sells[0].price), (sell.id, 99));
+        assert_eq!(book.get_decrypted_order(buy.id).unwrap().quantity, 0);
+
+        // The sweep still traded, which only the client key can tell
+        let remaining = |order: &Order| fhe_operations::decrypt_u32(order.encrypted_quantity.as_ref().unwrap()).unwrap();
+        assert_eq!((remaining(&book.buy_orders[0]), remaining(&book.sell_orders[0])), (6, 0));
+        assert!(book.fills.is_empty());
+    }
+
+    #[test]
+    fn encrypted_books_do_not_serve_individual_fills() {
+        let _keys = test_keys();
+        let mut book = encrypted_book();
+        book.market_data = MarketData::new(DisclosurePolicy::Hidden);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 100, 5, Side::Sell, "bob");
+
+        assert_eq!(book.fills.len(), 1);
+        assert!(matches!(book.public_fills(0), Err(OrderbookError::Forbidden(_))));
+        assert_eq!(book.market_data.stats(now_millis()).trade_count_24h, 0);
+    }
+
+    #[test]
+    fn market_sells_publish_the_traded_prices() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 99, 5, Side::Buy, "bob");
+        book.market_sell(7, "carol".to_string()).unwrap();
+
+        let stats = book.market_data.stats(now_millis());
+        assert_eq!((stats.low_24h, stats.high_24h, stats.last_price), (Some(99), Some(100), Some(99)));
+        assert_eq!(stats.quote_volume_24h, 100 * 5 + 99 * 2);
+        assert_eq!(book.public_fills(1).unwrap().len(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn market_order_takers_pay_taker_fees() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 99, 5, Side::Buy, "bob");
+        book.market_sell(7, "carol".to_string()).unwrap();
+
+        let fees: Vec<(i32, i64, i64)> = book.fills
+            .iter()
+            .map(|fill| (fill.fees.taker_fee_bps, fill.fees.maker_fee, fill.fees.taker_fee))
+            .collect();
+        // 500 and 198 notional at 10 bps maker and 20 bps taker, rounded up
+        assert_eq!(fees, vec![(20, 1, 1), (20, 1, 1)]);
+        assert_eq!(book.fees.users["carol"].volume, 698);
+        assert_eq!(book.fees.users["carol"].taker_fees, 2);
+        assert_eq!(book.fees.markets[DEFAULT_MARKET].volume, 698);
+    }
+
+    #[test]
+    fn refused_configurations_leave_the_book_unchanged() {
+        let _keys = test_keys();
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+
+        // Encryption alone could be enabled, but not oblivious matching over a resting order
+        let refused = book.configure(true, Some(MatchingMode::Oblivious));
+
+        assert!(matches!(refused, Err(OrderbookError::Conflict(_))));
+        assert!(!book.use_encryption);
+        assert_eq!(book.matching_mode, MatchingMode::Continuous);
+        assert!(!book.buy_orders[0].is_encrypted);
+        assert!(book.buy_orders[0].encrypted_price.is_none());
+    }
+
+    #[test]
+    fn enabling_encryption_clears_the_batch_before_encrypting() {
+        let _keys = test_keys();
+        let mut book = Orderbook::new(None);
+        book.set_matching_mode(MatchingMode::BatchAuction { interval_ms: 1_000 }).unwrap();
+        limit(&mut book, 101, 5, Side::Buy, "alice");
+        limit(&mut book, 99, 3, Side::Sell, "bob");
+        assert!(book.fills.is_empty());
+
+        let migrated = book.configure(true, Some(MatchingMode::Continuous)).unwrap();
+
+        assert_eq!(migrated, 1);
+        assert_eq!(traded(&book), vec![(99, 3)]);
+        assert!(book.use_encryption && book.buy_orders[0].is_encrypted);
+        assert_eq!(book.matching_mode, MatchingMode::Continuous);
+    }
+
+    #[test]
+    fn rotated_orders_still_match() {
+        let _keys = test_keys();
+        let mut book = encrypted_book();
+        let parameters = fhe_operations::key_parameters(fhe_operations::active_key_id().unwrap()).unwrap();
+        let key_id = fhe_operations::generate_key_version(parameters).unwrap();
+        let buy = limit(&mut book, 100, 5, Side::Buy, "alice");
+        stop(&mut book, 90, None, 2, Side::Sell);
+
+        assert_eq!(book.rotate_keys(key_id).unwrap(), 2);
+
+        assert_eq!(fhe_operations::active_key_id().unwrap(), key_id);
+        assert!(book.live_orders().flat_map(|order| order.ciphertexts()).all(|ciphertext| ciphertext.key_id() == key_id));
+        limit(&mut book, 100, 3, Side::Sell, "bob");
+        assert_eq!(traded(&book), vec![(100, 3)]);
+        let buy = book.get_decrypted_order(buy.id).unwrap();
+        assert_eq!((buy.price, buy.remaining_quantity()), (100, 2));
+    }
+
+    #[test]
+    fn orders_can_be_looked_up_after_leaving_the_book() {
+        let mut book = Orderbook::new(None);
+        let filled = limit(&mut book, 100, 5, Side::Buy, "alice");
+        let cancelled = limit(&mut book, 90, 5, Side::Buy, "alice");
+        let partial = limit(&mut book, 110, 8, Side::Sell, "bob");
+        limit(&mut book, 100, 5, Side::Sell, "bob");
+        let rejected = limit(&mut book, 100, 0, Side::Sell, "bob");
+
+        book.cancel_order(cancelled.id).unwrap();
+        limit(&mut book, 110, 3, Side::Buy, "carol");
+
+        let status = |book: &Orderbook, id| book.get_order(id).unwrap().status;
+        assert_eq!(status(&book, filled.id), OrderStatus::Filled);
+        assert_eq!(status(&book, cancelled.id), OrderStatus::Cancelled);
+        assert_eq!(status(&book, partial.id), OrderStatus::PartiallyFilled);
+        assert_eq!(status(&book, rejected.id), OrderStatus::Rejected);
+        assert_eq!(book.get_order(filled.id).unwrap().fill_ids, vec![book.fills[0].trade_id]);
+        assert!(matches!(
+            book.cancel_order(filled.id),
+            Err(OrderbookError::OrderClosed { status: OrderStatus::Filled, .. })
+        ));
+        assert!(matches!(book.cancel_order(99), Err(OrderbookError::OrderNotFound(99))));
+    }
+
+    #[test]
+    fn expired_orders_leave_the_book() {
+        let mut book = Orderbook::new(None);
+        book.count += 1;
+        let mut order = Order::new(book.count, 100, 5, Side::Buy, "alice".to_string());
+        order.expires_at = Some(now_millis() + 60_000);
+        let order = book.add_order(order);
+
+        book.expire_orders(now_millis() + 60_000);
+
+        assert!(book.buy_orders.is_empty());
+        assert_eq!(book.get_order(order.id).unwrap().status, OrderStatus::Expired);
+        limit(&mut book, 100, 5, Side::Sell, "bob");
+        assert!(book.fills.is_empty());
+    }
+
+    #[test]
+    fn fills_record_the_maker_and_the_aggressor() {
+        let mut book = Orderbook::new(None);
+        let before = now_millis();
+        let maker = limit(&mut book, 100, 5, Side::Sell, "alice");
+        limit(&mut book, 101, 5, Side::Sell, "bob");
+        let taker = limit(&mut book, 101, 12, Side::Buy, "carol");
+
+        let fills = &book.fills;
+        assert_eq!(fills.iter().map(|fill| fill.trade_id).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(fills.iter().all(|fill| fill.timestamp >= before && fill.aggressor_side == Side::Buy));
+        assert_eq!((fills[0].maker_order_id, fills[0].taker_order_id), (maker.id, taker.id));
+        assert_eq!((fills[0].buy_order_id, fills[0].sell_order_id), (taker.id, maker.id));
+        assert_eq!((fills[0].buyer_pubkey.as_str(), fills[0].seller_pubkey.as_str()), ("carol", "alice"));
+        assert_eq!(book.get_order(taker.id).unwrap().fill_ids, vec![1, 2]);
+
+        limit(&mut book, 101, 2, Side::Sell, "alice");
+        assert_eq!(book.fills[2].aggressor_side, Side::Sell);
+        assert_eq!(book.fills[2].maker_order_id, taker.id);
+    }
+
+    #[test]
+    fn oblivious_sweeps_trade_only_crossing_orders() {
+        let _keys = test_keys();
+        let mut book = encrypted_book();
+        book.set_matching_mode(MatchingMode::Oblivious).unwrap();
+        let buy = limit(&mut book, 100, 10, Side::Buy, "alice");
+        let above = limit(&mut book, 101, 3, Side::Sell, "bob");
+        let crossing = limit(&mut book, 99, 4, Side::Sell, "carol");
+
+        let remaining = |book: &Orderbook, id| {
+            fhe_operations::decrypt_u32(book.get_order(id).unwrap().encrypted_quantity.as_ref().unwrap()).unwrap()
+        };
+        assert_eq!(remaining(&book, buy.id), 6);
+        assert_eq!(remaining(&book, above.id), 3);
+        assert_eq!(remaining(&book, crossing.id), 0);
+
+        // Every sweep reports a fill against each resting order, traded or not
+        let fills: Vec<(u128, u32, u32)> = book.get_encrypted_fills(0)
+            .iter()
+            .map(|fill| (
+                fill.sell_order_id,
+                fhe_operations::decrypt_u32(&fill.encrypted_price).unwrap(),
+                fhe_operations::decrypt_u32(&fill.encrypted_quantity).unwrap(),
+            ))
+            .collect();
+        assert_eq!(fills, vec![(above.id, 101, 0), (crossing.id, 99, 4)]);
+        assert!(book.fills.is_empty());
+
+        assert_eq!(book.compact_oblivious_orders().unwrap(), 1);
+        assert_eq!(book.get_order(crossing.id).unwrap().status, OrderStatus::Filled);
+        assert_eq!(book.sell_orders.len(), 1);
+    }
+
+    #[test]
+    fn replenished_iceberg_slices_lose_time_priority() {
+        let mut book = Orderbook::new(None);
+        book.count += 1;
+        let iceberg = book.add_order(Order::new_iceberg(book.count, 100, 10, 3, Side::Buy, "alice".to_string()));
+        let plain = limit(&mut book, 100, 5, Side::Buy, "bob");
+
+        limit(&mut book, 100, 4, Side::Sell, "carol");
+        limit(&mut book, 100, 6, Side::Sell, "carol");
+
+        let buyers: Vec<(u128, u32)> = book.fills.iter().map(|fill| (fill.buy_order_id, fill.quantity)).collect();
+        assert_eq!(buyers, vec![(iceberg.id, 3), (plain.id, 1), (plain.id, 4), (iceberg.id, 2)]);
+        let iceberg = book.get_order(iceberg.id).unwrap();
+        assert_eq!((iceberg.remaining_quantity(), iceberg.visible_quantity), (5, 1));
+    }
+
+    #[test]
+    fn pegged_orders_follow_the_top_of_book() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 105, 5, Side::Sell, "bob");
+        book.count += 1;
+        let peg = Peg { reference: PegReference::BestBid, offset: 1 };
+        let pegged = book.add_order(Order::new_pegged(book.count, peg, 5, Side::Buy, "carol".to_string()));
+        assert_eq!(pegged.price, 101);
+
+        let better = limit(&mut book, 103, 5, Side::Buy, "alice");
+        assert_eq!(book.get_order(pegged.id).unwrap().price, 104);
+
+        book.cancel_order(better.id).unwrap();
+        assert_eq!(book.get_order(pegged.id).unwrap().price, 101);
+
+        // A best bid through the ask pushes the peg across the book, where it trades
+        limit(&mut book, 104, 1, Side::Buy, "alice");
+        assert_eq!(traded(&book), vec![(105, 5)]);
+        assert_eq!(book.get_order(pegged.id).unwrap().status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn restored_books_carry_on_where_the_snapshot_left_off() {
+        let mut book = Orderbook::new(None);
+        limit(&mut book, 100, 5, Side::Buy, "alice");
+        limit(&mut book, 100, 2, Side::Sell, "bob");
+        let waiting = stop(&mut book, 90, None, 1, Side::Sell);
+        book.set_matching_mode(MatchingMode::BatchAuction { interval_ms: 500 }).unwrap();
+
+        let mut restored = Orderbook::new(None);
+        assert_eq!(restored.restore(book.snapshot()).unwrap(), 0);
+
+        assert_eq!(restored.matching_mode, MatchingMode::BatchAuction { interval_ms: 500 });
+        assert_eq!((restored.count, restored.next_trade_id), (book.count, book.next_trade_id));
+        assert_eq!(traded(&restored), vec![(100, 2)]);
+        assert_eq!(restored.get_order(waiting.id).unwrap().trigger_price, Some(90));
+        assert_eq!(restored.fees.users["bob"].volume, book.fees.users["bob"].volume);
+
+        limit(&mut restored, 99, 3, Side::Sell, "carol");
+        restored.run_auction().unwrap();
+        assert_eq!(restored.fills[1].trade_id, book.next_trade_id);
+        assert_eq!(restored.get_order(1).unwrap().status, OrderStatus::Filled);
+    }
+}
@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use super::ciphertext::Ciphertext;
+use super::fees::FillFees;
+use super::pegged::{Peg, PegReference};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Side {
@@ -6,6 +10,51 @@ pub enum Side {
     Sell,
 }
 
+/// How an order is priced and when it enters the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    #[default]
+    Limit,
+    Market,
+    // Held in the trigger book until the trigger price trades, then entered as a market order
+    StopMarket,
+    // Held in the trigger book until the trigger price trades, then entered as a limit order
+    StopLimit,
+    // Re-priced from the best bid, best ask or mid whenever the top of book changes
+    Pegged,
+}
+
+/// Lifecycle state of an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+impl OrderStatus {
+    /// Whether the order can no longer trade
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Filled | OrderStatus::Cancelled | OrderStatus::Expired | OrderStatus::Rejected
+        )
+    }
+}
+
+// Current time as milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     pub id: u128,
@@ -13,51 +62,273 @@ pub struct Order {
     pub quantity: u32,
     pub side: Side,
     pub user_pubkey: String,
+    #[serde(default)]
+    pub order_type: OrderType,
+    // Last trade price at which a stop order activates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger_price: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_trigger_price: Option<Ciphertext>,
+    // Reference price a pegged order tracks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peg: Option<Peg>,
     // Encrypted values using FHE
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub encrypted_price: Option<Vec<u8>>,
+    pub encrypted_price: Option<Ciphertext>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub encrypted_quantity: Option<Vec<u8>>,
+    pub encrypted_quantity: Option<Ciphertext>,
     // Flag to indicate if this order is using encryption
     #[serde(default)]
     pub is_encrypted: bool,
+    // Lifecycle tracking
+    pub status: OrderStatus,
+    #[serde(default)]
+    pub filled_quantity: u32,
+    pub created_at: u64,
+    pub updated_at: u64,
+    // Optional expiry time (milliseconds since the Unix epoch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    // Trade ids of the fills this order took part in
+    #[serde(default)]
+    pub fill_ids: Vec<u64>,
+    // Iceberg orders only show `display_quantity` at a time; the rest is held in reserve
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_quantity: Option<u32>,
+    #[serde(default)]
+    pub visible_quantity: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_display_quantity: Option<Ciphertext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_visible_quantity: Option<Ciphertext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_reserve_quantity: Option<Ciphertext>,
 }
 
 impl Order {
     pub fn new(id: u128, price: u32, quantity: u32, side: Side, user_pubkey: String) -> Self {
+        let now = now_millis();
         Self {
             id,
             price,
             quantity,
             side,
             user_pubkey,
+            order_type: OrderType::Limit,
+            trigger_price: None,
+            encrypted_trigger_price: None,
+            peg: None,
             encrypted_price: None,
             encrypted_quantity: None,
             is_encrypted: false,
+            status: OrderStatus::New,
+            filled_quantity: 0,
+            created_at: now,
+            updated_at: now,
+            expires_at: None,
+            fill_ids: Vec::new(),
+            display_quantity: None,
+            visible_quantity: quantity,
+            encrypted_display_quantity: None,
+            encrypted_visible_quantity: None,
+            encrypted_reserve_quantity: None,
         }
     }
-    
-    pub fn new_encrypted(id: u128, price: u32, quantity: u32, side: Side, user_pubkey: String, 
-                        encrypted_price: Vec<u8>, encrypted_quantity: Vec<u8>) -> Self {
-        Self {
-            id,
-            price,
-            quantity,
-            side,
-            user_pubkey,
-            encrypted_price: Some(encrypted_price),
-            encrypted_quantity: Some(encrypted_quantity),
-            is_encrypted: true,
+
+    // Turn this order into an iceberg that shows `display_quantity` at a time
+    pub fn new_iceberg(id: u128, price: u32, quantity: u32, display_quantity: u32, side: Side, user_pubkey: String) -> Self {
+        let mut order = Self::new(id, price, quantity, side, user_pubkey);
+        order.display_quantity = Some(display_quantity);
+        order.visible_quantity = display_quantity.min(quantity);
+        order
+    }
+
+    // Create a market order, priced so that it crosses every resting order
+    pub fn new_market(id: u128, quantity: u32, side: Side, user_pubkey: String) -> Self {
+        let price = match side {
+            Side::Buy => u32::MAX,
+            Side::Sell => 0,
+        };
+        let mut order = Self::new(id, price, quantity, side, user_pubkey);
+        order.order_type = OrderType::Market;
+        order
+    }
+
+    // Create a stop order; without a limit price it becomes a market order when triggered
+    pub fn new_stop(id: u128, trigger_price: u32, limit_price: Option<u32>, quantity: u32, side: Side, user_pubkey: String) -> Self {
+        let mut order = Self::new(id, limit_price.unwrap_or(0), quantity, side, user_pubkey);
+        order.order_type = match limit_price {
+            Some(_) => OrderType::StopLimit,
+            None => OrderType::StopMarket,
+        };
+        order.trigger_price = Some(trigger_price);
+        order
+    }
+
+    // Create a pegged order; it is priced from the top of book when it enters
+    pub fn new_pegged(id: u128, peg: Peg, quantity: u32, side: Side, user_pubkey: String) -> Self {
+        let mut order = Self::new(id, 0, quantity, side, user_pubkey);
+        order.order_type = OrderType::Pegged;
+        order.peg = Some(peg);
+        order
+    }
+
+    pub fn is_pegged(&self) -> bool {
+        self.peg.is_some()
+    }
+
+    // Midpoint orders only ever execute at the mid
+    pub fn is_midpoint(&self) -> bool {
+        self.peg.is_some_and(|peg| peg.reference == PegReference::Mid)
+    }
+
+    // Market orders, including triggered stop-market orders, carry a sentinel
+    // price that crosses everything rather than a limit price
+    pub fn is_market(&self) -> bool {
+        matches!(self.order_type, OrderType::Market | OrderType::StopMarket)
+    }
+
+    pub fn is_stop(&self) -> bool {
+        matches!(self.order_type, OrderType::StopMarket | OrderType::StopLimit)
+    }
+
+    pub fn is_iceberg(&self) -> bool {
+        self.display_quantity.is_some() || self.encrypted_display_quantity.is_some()
+    }
+
+    // Every encrypted field the order holds
+    pub fn ciphertexts(&self) -> impl Iterator<Item = &Ciphertext> {
+        [
+            &self.encrypted_trigger_price,
+            &self.encrypted_price,
+            &self.encrypted_quantity,
+            &self.encrypted_display_quantity,
+            &self.encrypted_visible_quantity,
+            &self.encrypted_reserve_quantity,
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    pub fn ciphertexts_mut(&mut self) -> impl Iterator<Item = &mut Ciphertext> {
+        [
+            &mut self.encrypted_trigger_price,
+            &mut self.encrypted_price,
+            &mut self.encrypted_quantity,
+            &mut self.encrypted_display_quantity,
+            &mut self.encrypted_visible_quantity,
+            &mut self.encrypted_reserve_quantity,
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    // Quantity that can trade against incoming orders right now
+    pub fn available_quantity(&self) -> u32 {
+        if self.is_iceberg() {
+            self.visible_quantity
+        } else {
+            self.remaining_quantity()
         }
     }
+
+    // Show a new slice once the visible one is used up. Returns whether the
+    // order was replenished, in which case it must lose time priority.
+    pub fn replenish(&mut self) -> bool {
+        match self.display_quantity {
+            Some(display_quantity) if self.visible_quantity == 0 && self.remaining_quantity() > 0 => {
+                self.visible_quantity = display_quantity.min(self.remaining_quantity());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Reset the visible slice from the remaining quantity, e.g. after the order
+    // traded as the aggressor and is about to rest
+    pub fn reset_visible(&mut self) {
+        self.visible_quantity = match self.display_quantity {
+            Some(display_quantity) => display_quantity.min(self.remaining_quantity()),
+            None => self.remaining_quantity(),
+        };
+    }
+
+    // Quantity still open on the book
+    pub fn remaining_quantity(&self) -> u32 {
+        self.quantity.saturating_sub(self.filled_quantity)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    pub fn set_status(&mut self, status: OrderStatus) {
+        self.status = status;
+        self.updated_at = now_millis();
+    }
+
+    // Apply an execution against this order and update its status
+    pub fn apply_fill(&mut self, fill_id: u64, quantity: u32) {
+        self.filled_quantity += quantity;
+        self.visible_quantity = self.visible_quantity.saturating_sub(quantity);
+        self.fill_ids.push(fill_id);
+        let status = if self.remaining_quantity() == 0 {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        self.set_status(status);
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fill {
+    pub trade_id: u64,
+    pub timestamp: u64,
     pub buy_order_id: u128,
     pub sell_order_id: u128,
     pub price: u32,
     pub quantity: u32,
     pub buyer_pubkey: String,
     pub seller_pubkey: String,
+    // Side of the incoming order that triggered the trade
+    pub aggressor_side: Side,
+    // Resting (maker) and incoming (taker) order ids
+    pub maker_order_id: u128,
+    pub taker_order_id: u128,
+    // Fees charged to the maker and taker for this trade
+    #[serde(flatten)]
+    pub fees: FillFees,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_move_an_order_through_its_lifecycle() {
+        let mut order = Order::new(1, 100, 10, Side::Buy, "alice".to_string());
+        assert_eq!(order.status, OrderStatus::New);
+
+        order.apply_fill(7, 4);
+        assert_eq!(order.status, OrderStatus::PartiallyFilled);
+        assert_eq!((order.filled_quantity, order.remaining_quantity()), (4, 6));
+        assert!(!order.status.is_terminal());
+
+        order.apply_fill(9, 6);
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.fill_ids, vec![7, 9]);
+        assert!(order.status.is_terminal());
+        assert!(order.updated_at >= order.created_at);
+    }
+
+    #[test]
+    fn orders_expire_at_their_expiry_time() {
+        let mut order = Order::new(1, 100, 10, Side::Buy, "alice".to_string());
+        assert!(!order.is_expired(u64::MAX));
+
+        order.expires_at = Some(1_000);
+        assert!(!order.is_expired(999));
+        assert!(order.is_expired(1_000));
+    }
 }
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use crate::error::OrderbookError;
+use super::orders::{Order, Side};
+
+/// Reference price a pegged order tracks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PegReference {
+    BestBid,
+    BestAsk,
+    // Midpoint of the best bid and ask, rounded down; midpoint orders only trade at the mid
+    Mid,
+}
+
+/// How a pegged order is priced: its reference price plus a signed offset in ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peg {
+    pub reference: PegReference,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+impl Peg {
+    pub fn validate(&self) -> Result<(), OrderbookError> {
+        if self.reference == PegReference::Mid && self.offset != 0 {
+            return Err(OrderbookError::InvalidRequest("Midpoint orders cannot have an offset".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Best bid and ask of the limit orders resting on the book. Pegged orders are
+/// left out so they never track each other, and market orders waiting for an
+/// auction because their sentinel price is not a price anyone quoted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopOfBook {
+    pub best_bid: Option<u32>,
+    pub best_ask: Option<u32>,
+}
+
+impl TopOfBook {
+    /// Top of a plaintext book, whose sides are kept sorted best price first, so
+    /// only the orders ahead of the best limit order are looked at
+    pub fn from_book(buy_orders: &[Order], sell_orders: &[Order]) -> Self {
+        let best_limit_price = |orders: &[Order]| {
+            orders
+                .iter()
+                .find(|order| !order.is_pegged() && !order.is_market())
+                .map(|order| order.price)
+        };
+        Self {
+            best_bid: best_limit_price(buy_orders),
+            best_ask: best_limit_price(sell_orders),
+        }
+    }
+
+    pub fn mid(&self) -> Option<u32> {
+        match (self.best_bid, self.best_ask) {
+            (Some(bid), Some(ask)) => Some(((bid as u64 + ask as u64) / 2) as u32),
+            _ => None,
+        }
+    }
+}
+
+// Price a pegged order would have at the given top of book, or `None` while its
+// reference is missing or the offset takes it out of the valid price range
+pub fn peg_price(peg: &Peg, top: &TopOfBook) -> Option<u32> {
+    let reference = match peg.reference {
+        PegReference::BestBid => top.best_bid,
+        PegReference::BestAsk => top.best_ask,
+        PegReference::Mid => top.mid(),
+    }?;
+    reference
+        .checked_add_signed(peg.offset)
+        .filter(|price| *price > 0 && *price < u32::MAX)
+}
+
+/// Re-price a pegged order from the top of book. An order without a price is
+/// parked at the far end of its side, where it cannot trade.
+pub fn reprice(order: &mut Order, top: &TopOfBook) {
+    let Some(peg) = &order.peg else {
+        return;
+    };
+    order.price = peg_price(peg, top).unwrap_or(match order.side {
+        Side::Buy => 0,
+        Side::Sell => u32::MAX,
+    });
+}
+
+// Whether an order has a price it can trade at; only parked pegged orders do not
+pub fn is_priced(order: &Order) -> bool {
+    !order.is_pegged() || (order.price > 0 && order.price < u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peg(reference: PegReference, offset: i32) -> Peg {
+        Peg { reference, offset }
+    }
+
+    #[test]
+    fn pegs_track_their_reference_plus_the_offset() {
+        let top = TopOfBook { best_bid: Some(100), best_ask: Some(105) };
+
+        assert_eq!(peg_price(&peg(PegReference::BestBid, 1), &top), Some(101));
+        assert_eq!(peg_price(&peg(PegReference::BestAsk, -2), &top), Some(103));
+        assert_eq!(peg_price(&peg(PegReference::Mid, 0), &top), Some(102));
+        assert_eq!(peg_price(&peg(PegReference::BestBid, -100), &top), None);
+        assert_eq!(peg_price(&peg(PegReference::BestAsk, 0), &TopOfBook { best_bid: Some(100), best_ask: None }), None);
+    }
+
+    #[test]
+    fn unpriced_pegs_are_parked_where_they_cannot_trade() {
+        let mut buy = Order::new_pegged(1, peg(PegReference::BestBid, 0), 5, Side::Buy, "alice".to_string());
+        let mut sell = Order::new_pegged(2, peg(PegReference::BestAsk, 0), 5, Side::Sell, "bob".to_string());
+
+        reprice(&mut buy, &TopOfBook::default());
+        reprice(&mut sell, &TopOfBook::default());
+
+        assert_eq!((buy.price, sell.price), (0, u32::MAX));
+        assert!(!is_priced(&buy) && !is_priced(&sell));
+    }
+
+    #[test]
+    fn top_of_book_skips_pegged_and_market_orders() {
+        let buys = [
+            Order::new_market(1, 5, Side::Buy, "alice".to_string()),
+            Order::new_pegged(2, peg(PegReference::BestBid, 0), 5, Side::Buy, "alice".to_string()),
+            Order::new(3, 99, 5, Side::Buy, "alice".to_string()),
+        ];
+
+        assert_eq!(TopOfBook::from_book(&buys, &[]), TopOfBook { best_bid: Some(99), best_ask: None });
+    }
+
+    #[test]
+    fn midpoint_pegs_take_no_offset() {
+        assert!(peg(PegReference::Mid, 1).validate().is_err());
+        assert!(peg(PegReference::BestBid, -1).validate().is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use super::auction::MatchingMode;
+use super::fees::{FeeSchedule, MarketFees, UserFees};
+use super::market_data::DisclosurePolicy;
+use super::oblivious::EncryptedFill;
+use super::orders::{Fill, Order};
+
+// Format version written into every snapshot
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Everything needed to bring an orderbook back: resting and stop orders with
+/// their ciphertexts, closed orders, fills, accrued fees and settings. Candles
+/// and trade statistics are not included and start empty after a restore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub version: u32,
+    // When the snapshot was taken, in milliseconds since the Unix epoch
+    pub taken_at: u64,
+    pub market: String,
+    pub count: u128,
+    pub next_trade_id: u64,
+    pub use_encryption: bool,
+    pub matching_mode: MatchingMode,
+    pub disclosure_policy: DisclosurePolicy,
+    pub fee_schedule: FeeSchedule,
+    pub buy_orders: Vec<Order>,
+    pub sell_orders: Vec<Order>,
+    pub stop_orders: Vec<Order>,
+    pub closed_orders: Vec<Order>,
+    pub fills: Vec<Fill>,
+    pub encrypted_fills: Vec<EncryptedFill>,
+    pub last_trade_price: Option<u32>,
+    pub user_fees: HashMap<String, UserFees>,
+    pub market_fees: HashMap<String, MarketFees>,
+}
+
+impl BookSnapshot {
+    /// Resting and waiting stop orders
+    pub fn live_orders(&self) -> impl Iterator<Item = &Order> {
+        self.buy_orders.iter().chain(&self.sell_orders).chain(&self.stop_orders)
+    }
+}
+
+// Just the format version, read first so other versions fail with a clear error
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+/// Read a snapshot written by `save`
+pub fn load(path: impl AsRef<Path>) -> io::Result<BookSnapshot> {
+    let bytes = fs::read(path)?;
+    let header: SnapshotHeader = serde_json::from_slice(&bytes).map_err(io::Error::other)?;
+    if header.version != SNAPSHOT_VERSION {
+        return Err(io::Error::other(format!(
+            "Unsupported snapshot version {}: expected {}",
+            header.version, SNAPSHOT_VERSION
+        )));
+    }
+    serde_json::from_slice(&bytes).map_err(io::Error::other)
+}
+
+/// Write a snapshot as JSON. The file is replaced atomically, so a reader never
+/// sees a partly written snapshot.
+pub fn save(snapshot: &BookSnapshot, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let bytes = serde_json::to_vec(snapshot).map_err(io::Error::other)?;
+    let partial = path.with_extension("partial");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::orderbook::Orderbook;
+    use crate::utils::orders::Side;
+
+    // A file under the system temp directory, unique to this test process
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("fhe-orderbook-{}-{}.json", std::process::id(), name))
+    }
+
+    #[test]
+    fn snapshots_survive_a_save_and_load() {
+        let mut book = Orderbook::new(None);
+        book.add_order(Order::new(1, 100, 5, Side::Buy, "alice".to_string()));
+        book.add_order(Order::new(2, 100, 2, Side::Sell, "bob".to_string()));
+        book.count = 2;
+        let path = temp_path("round-trip");
+
+        save(&book.snapshot(), &path).unwrap();
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.count, 2);
+        assert_eq!(loaded.buy_orders.len(), 1);
+        assert_eq!(loaded.buy_orders[0].filled_quantity, 2);
+        assert_eq!(loaded.closed_orders.iter().map(|order| order.id).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(loaded.fills.len(), 1);
+    }
+
+    #[test]
+    fn other_snapshot_versions_are_refused() {
+        let mut snapshot = Orderbook::new(None).snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        let path = temp_path("version");
+
+        save(&snapshot, &path).unwrap();
+        let error = load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert!(error.to_string().contains("Unsupported snapshot version"));
+    }
+}
@@ -0,0 +1,131 @@
+use crate::error::OrderbookError;
+use super::fhe_operations::{self, VersionedClientKey};
+use super::orders::{Order, OrderType, Side, now_millis};
+
+/// Stop orders waiting for their trigger price to trade
+#[derive(Debug, Clone, Default)]
+pub struct TriggerBook {
+    pub orders: Vec<Order>,
+}
+
+/// Lowest and highest price traded since stop orders were last checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TradedRange {
+    pub low: u32,
+    pub high: u32,
+}
+
+impl TradedRange {
+    pub fn at(price: u32) -> Self {
+        Self { low: price, high: price }
+    }
+
+    pub fn include(&mut self, price: u32) {
+        self.low = self.low.min(price);
+        self.high = self.high.max(price);
+    }
+}
+
+// A buy stop triggers once the market trades at or above its trigger price,
+// a sell stop once it trades at or below, at any price within the range.
+// Encrypted trigger prices are compared homomorphically and need the client
+// key to read the activation bit.
+pub fn is_triggered(order: &Order, traded: TradedRange, client_key: Option<&VersionedClientKey>) -> Result<bool, OrderbookError> {
+    // The price most likely to trigger the order
+    let price = match order.side {
+        Side::Buy => traded.high,
+        Side::Sell => traded.low,
+    };
+    if let Some(encrypted_trigger) = &order.encrypted_trigger_price {
+        let client_key = client_key.ok_or_else(|| {
+            OrderbookError::KeyUnavailable("Client key required to evaluate encrypted triggers".to_string())
+        })?;
+        return fhe_operations::trigger_activated(encrypted_trigger, &order.side, price, client_key);
+    }
+
+    Ok(match (order.trigger_price, &order.side) {
+        (Some(trigger_price), Side::Buy) => price >= trigger_price,
+        (Some(trigger_price), Side::Sell) => price <= trigger_price,
+        (None, _) => false,
+    })
+}
+
+// Turn a triggered stop order into the order that enters the book
+pub fn activate(mut order: Order) -> Order {
+    if order.order_type == OrderType::StopMarket {
+        order.price = match order.side {
+            Side::Buy => u32::MAX,
+            Side::Sell => 0,
+        };
+    }
+    order.updated_at = now_millis();
+    order
+}
+
+impl TriggerBook {
+    pub fn add(&mut self, order: Order) {
+        self.orders.push(order);
+    }
+
+    pub fn get(&self, id: u128) -> Option<&Order> {
+        self.orders.iter().find(|order| order.id == id)
+    }
+
+    pub fn remove(&mut self, id: u128) -> Option<Order> {
+        let i = self.orders.iter().position(|order| order.id == id)?;
+        Some(self.orders.remove(i))
+    }
+
+    pub fn has_encrypted_triggers(&self) -> bool {
+        self.orders.iter().any(|order| order.encrypted_trigger_price.is_some())
+    }
+
+    // Remove every order a price in the traded range triggers, oldest first.
+    // Orders whose trigger cannot be evaluated stay in the book.
+    pub fn take_triggered(&mut self, traded: TradedRange, client_key: Option<&VersionedClientKey>) -> Vec<Order> {
+        let (triggered, waiting): (Vec<Order>, Vec<Order>) =
+            self.orders.drain(..).partition(|order| {
+                is_triggered(order, traded, client_key).unwrap_or_else(|e| {
+                    eprintln!("Failed to evaluate trigger for order {}: {}", order.id, e);
+                    false
+                })
+            });
+        self.orders = waiting;
+        triggered
+    }
+
+    // Remove every order past its expiry time
+    pub fn take_expired(&mut self, now: u64) -> Vec<Order> {
+        let (expired, live): (Vec<Order>, Vec<Order>) =
+            self.orders.drain(..).partition(|order| order.is_expired(now));
+        self.orders = live;
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(trigger_price: u32, side: Side) -> Order {
+        Order::new_stop(1, trigger_price, None, 5, side, "stopper".to_string())
+    }
+
+    #[test]
+    fn stops_trigger_only_within_the_traded_range() {
+        let traded = TradedRange { low: 95, high: 100 };
+
+        assert!(is_triggered(&stop(97, Side::Sell), traded, None).unwrap());
+        assert!(is_triggered(&stop(95, Side::Sell), traded, None).unwrap());
+        assert!(!is_triggered(&stop(94, Side::Sell), traded, None).unwrap());
+        assert!(is_triggered(&stop(100, Side::Buy), traded, None).unwrap());
+        assert!(!is_triggered(&stop(101, Side::Buy), traded, None).unwrap());
+    }
+
+    #[test]
+    fn triggered_stop_market_orders_cross_everything() {
+        assert_eq!(activate(stop(97, Side::Sell)).price, 0);
+        assert_eq!(activate(stop(97, Side::Buy)).price, u32::MAX);
+        assert!(activate(stop(97, Side::Buy)).is_market());
+    }
+}
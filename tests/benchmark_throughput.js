@@ -0,0 +1,86 @@
+/**
+ * Throughput benchmark for the Encrypted Orderbook API
+ *
+ * Places a batch of resting orders, then a batch of orders that cross them,
+ * and reports how many orders per second the server handled in each phase.
+ * Run it against a server started with FHE keys to measure encrypted matching.
+ *
+ * Usage: node tests/benchmark_throughput.js [orders]
+ */
+
+const fetch = globalThis.fetch || require('node-fetch');
+const API_URL = 'http://localhost:8080';
+// Token for the admin endpoints (reset, config, key generation), as given to the server
+const ADMIN_TOKEN = process.env.ORDERBOOK_ADMIN_TOKEN;
+const ORDERS = parseInt(process.argv[2] || '50', 10);
+
+// Helper function to make API requests
+async function apiRequest(endpoint, method = 'GET', data = null) {
+    const options = {
+        method,
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    };
+
+    if (ADMIN_TOKEN) {
+        options.headers['Authorization'] = `Bearer ${ADMIN_TOKEN}`;
+    }
+
+    if (data) {
+        options.body = JSON.stringify(data);
+    }
+
+    const response = await fetch(`${API_URL}${endpoint}`, options);
+    return await response.json();
+}
+
+// Place each order in turn and report the rate at which they were accepted
+async function timePhase(name, orders) {
+    const start = process.hrtime.bigint();
+    for (const order of orders) {
+        const result = await apiRequest('/orders', 'POST', order);
+        if (!result.success) {
+            throw new Error(`${name}: order rejected: ${JSON.stringify(result)}`);
+        }
+    }
+    const seconds = Number(process.hrtime.bigint() - start) / 1e9;
+    const rate = orders.length / seconds;
+    console.log(`${name}: ${orders.length} orders in ${seconds.toFixed(2)}s (${rate.toFixed(1)} orders/s)`);
+    return rate;
+}
+
+async function runBenchmark() {
+    console.log('⏱️  ENCRYPTED ORDERBOOK THROUGHPUT BENCHMARK ⏱️');
+    console.log('=============================================');
+
+    await apiRequest('/reset', 'POST');
+    const config = await apiRequest('/config');
+    console.log(`Encryption: ${config.use_encryption ? 'enabled' : 'disabled'}`);
+
+    // Sells spread over a range of prices, so each buy is compared with a full book
+    const resting = Array.from({ length: ORDERS }, (_, i) => ({
+        price: 100 + (i % 10),
+        quantity: 10,
+        side: 'sell',
+        user_pubkey: 'bench_seller'
+    }));
+    // Each buy fills part of the best sell, leaving the book the same size
+    const crossing = Array.from({ length: ORDERS }, () => ({
+        price: 105,
+        quantity: 1,
+        side: 'buy',
+        user_pubkey: 'bench_buyer'
+    }));
+
+    await timePhase('Resting orders', resting);
+    await timePhase('Crossing orders', crossing);
+
+    const fills = await apiRequest('/fills');
+    console.log(`Fills: ${fills.length}`);
+}
+
+runBenchmark().catch((error) => {
+    console.error('❌ BENCHMARK FAILED:', error);
+    process.exit(1);
+});
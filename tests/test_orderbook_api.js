@@ -12,6 +12,8 @@
 
 const fetch = require('node-fetch');
 const API_URL = 'http://localhost:8080';
+// Token for the admin endpoints (reset, config, key generation), as given to the server
+const ADMIN_TOKEN = process.env.ORDERBOOK_ADMIN_TOKEN;
 
 // Helper function to make API requests
 async function apiRequest(endpoint, method = 'GET', data = null) {
@@ -22,6 +24,10 @@ async function apiRequest(endpoint, method = 'GET', data = null) {
         }
     };
 
+    if (ADMIN_TOKEN) {
+        options.headers['Authorization'] = `Bearer ${ADMIN_TOKEN}`;
+    }
+
     if (data) {
         options.body = JSON.stringify(data);
     }